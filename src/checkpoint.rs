@@ -0,0 +1,269 @@
+//! This module implements the export of the complete state of a
+//! [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) at a version into a single stream, and its
+//! import into another storage.
+//!
+//! A checkpoint starts with a header made of:
+//! - the magic bytes `JMTCKPT` followed by the format version (`3`),
+//! - the version of the exported tree as a little-endian `u64`,
+//! - the [`NAME`](NamedHasher::NAME) of the hasher, prefixed by its length as a byte,
+//! - an identifier of the hasher, which is the hash of `JMT::Checkpoint`,
+//! - the root hash of the exported tree.
+//!
+//! Checkpoints of format version `1`, whose header lacks the name of the hasher, and of format
+//! version `2`, whose header also holds the length of the digests of the hasher (always 32) as a
+//! byte after its name, can still be imported.
+//!
+//! It is followed by frames, each made of a tag byte, the length of its payload as a
+//! little-endian `u32` and the payload, which is the borsh encoding of a `(NodeKey, Node)` pair
+//! for node frames and of a `(KeyHash, OwnedValue)` pair for value frames. An end frame, with no
+//! payload, closes the stream.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::convert::TryInto;
+use std::io::{Read, Write};
+
+use anyhow::{bail, ensure, format_err, Result};
+
+use crate::{
+    node_type::{Child, Children, Node, NodeKey},
+    storage::{NodeBatch, TreeReader, TreeWriter},
+    types::Version,
+    JellyfishMerkleTree, KeyHash, MissingRootError, NamedHasher, OwnedValue, RootHash,
+    SimpleHasher, ValueHash,
+};
+
+const MAGIC: &[u8; 7] = b"JMTCKPT";
+const FORMAT_VERSION: u8 = 3;
+const END_FRAME_TAG: u8 = 0x00;
+const NODE_FRAME_TAG: u8 = 0x01;
+const VALUE_FRAME_TAG: u8 = 0x02;
+
+/// Identifies the hasher a checkpoint was created with.
+fn hasher_id<H: SimpleHasher>() -> [u8; 32] {
+    H::hash(b"JMT::Checkpoint")
+}
+
+fn write_frame(writer: &mut impl Write, tag: u8, payload: &[u8]) -> Result<()> {
+    let len: u32 = payload
+        .len()
+        .try_into()
+        .map_err(|_| format_err!("Checkpoint frame of {} bytes is too large.", payload.len()))?;
+    writer.write_all(&[tag])?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: NamedHasher,
+{
+    /// Writes all the nodes and values reachable from the root at `version` to `writer`, in the
+    /// format described in the [`checkpoint`](crate::checkpoint) module. The checkpoint can be
+    /// imported into another storage with [`import_checkpoint`].
+    pub fn export_checkpoint(&self, version: Version, mut writer: impl Write) -> Result<()> {
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = self
+            .reader
+            .get_node_option(&root_node_key)?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+
+        let name_len: u8 = H::NAME
+            .len()
+            .try_into()
+            .map_err(|_| format_err!("Hasher name {} is too long.", H::NAME))?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        writer.write_all(&version.to_le_bytes())?;
+        writer.write_all(&[name_len])?;
+        writer.write_all(H::NAME.as_bytes())?;
+        writer.write_all(&hasher_id::<H>())?;
+        writer.write_all(&root_node.hash::<H>())?;
+
+        let mut stack = vec![(root_node_key, root_node)];
+        while let Some((node_key, node)) = stack.pop() {
+            match &node {
+                Node::Internal(internal_node) => {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                        let child_node = self.reader.get_node(&child_node_key)?;
+                        stack.push((child_node_key, child_node));
+                    }
+                }
+                Node::Leaf(leaf_node) => {
+                    let value = self.reader.get_value(version, leaf_node.key_hash())?;
+                    write_frame(
+                        &mut writer,
+                        VALUE_FRAME_TAG,
+                        &borsh::to_vec(&(leaf_node.key_hash(), value))?,
+                    )?;
+                }
+                Node::Null => {}
+            }
+            write_frame(
+                &mut writer,
+                NODE_FRAME_TAG,
+                &borsh::to_vec(&(node_key, node))?,
+            )?;
+        }
+        write_frame(&mut writer, END_FRAME_TAG, &[])?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads a checkpoint written by
+/// [`JellyfishMerkleTree::export_checkpoint`](crate::JellyfishMerkleTree::export_checkpoint) and
+/// writes its nodes and values to `store`, returning the version and the root hash of the
+/// imported tree. Values are recorded at the version of the checkpoint.
+///
+/// The checkpoint must have been created with the hasher `H`. The whole tree is checked against
+/// the root hash recorded in the checkpoint before anything is written to `store`.
+pub fn import_checkpoint<H: NamedHasher, W: TreeWriter>(
+    mut reader: impl Read,
+    store: &W,
+) -> Result<(Version, RootHash)> {
+    let magic = read_array::<7>(&mut reader)?;
+    let [format_version] = read_array(&mut reader)?;
+    ensure!(
+        &magic == MAGIC && (1..=FORMAT_VERSION).contains(&format_version),
+        "Not a checkpoint, or unsupported checkpoint format."
+    );
+    let version = Version::from_le_bytes(read_array(&mut reader)?);
+    if format_version >= 2 {
+        let [name_len] = read_array(&mut reader)?;
+        let mut name = vec![0; name_len.into()];
+        reader.read_exact(&mut name)?;
+        ensure!(
+            name == H::NAME.as_bytes(),
+            "Checkpoint was created with the hasher {}, not {}.",
+            String::from_utf8_lossy(&name),
+            H::NAME
+        );
+    }
+    if format_version == 2 {
+        let [output_len] = read_array(&mut reader)?;
+        ensure!(
+            output_len == 32,
+            "Checkpoint was created with a hasher of {} bytes output, not 32.",
+            output_len
+        );
+    }
+    ensure!(
+        read_array::<32>(&mut reader)? == hasher_id::<H>(),
+        "Checkpoint was created with another hasher."
+    );
+    let root_hash = RootHash(read_array(&mut reader)?);
+
+    let mut nodes = BTreeMap::new();
+    let mut values = BTreeMap::new();
+    loop {
+        let [tag] = read_array(&mut reader)?;
+        let len = u32::from_le_bytes(read_array(&mut reader)?);
+        // Reading through `take` avoids allocating a corrupted length upfront.
+        let mut payload = Vec::new();
+        (&mut reader).take(len.into()).read_to_end(&mut payload)?;
+        ensure!(payload.len() == len as usize, "Checkpoint is truncated.");
+        match tag {
+            END_FRAME_TAG => {
+                ensure!(payload.is_empty(), "End frame has a payload.");
+                break;
+            }
+            NODE_FRAME_TAG => {
+                let (node_key, node): (NodeKey, Node) = borsh::from_slice(&payload)?;
+                ensure!(
+                    nodes.insert(node_key.clone(), node).is_none(),
+                    "Duplicated node {:?} in checkpoint.",
+                    node_key
+                );
+            }
+            VALUE_FRAME_TAG => {
+                let (key_hash, value): (KeyHash, OwnedValue) = borsh::from_slice(&payload)?;
+                ensure!(
+                    values.insert(key_hash, value).is_none(),
+                    "Duplicated value of {:?} in checkpoint.",
+                    key_hash
+                );
+            }
+            _ => bail!("Unknown checkpoint frame tag {}.", tag),
+        }
+    }
+
+    // Check that the nodes and values form exactly the tree the checkpoint claims to hold.
+    let root_node_key = NodeKey::new_empty_path(version);
+    let root_node = nodes
+        .get(&root_node_key)
+        .ok_or_else(|| format_err!("Checkpoint has no root node."))?;
+    ensure!(
+        root_node.hash::<H>() == root_hash.0,
+        "Root hash of the checkpoint does not match its root node."
+    );
+    let mut num_reachable_nodes = 0;
+    let mut num_leaves = 0;
+    let mut stack = vec![(root_node_key, root_node)];
+    while let Some((node_key, node)) = stack.pop() {
+        num_reachable_nodes += 1;
+        match node {
+            Node::Internal(internal_node) => {
+                let children = Children::from(internal_node.clone());
+                ensure!(
+                    children.num_children() == children.values().count()
+                        && (children.num_children() > 1
+                            || children.values().any(|child| !child.is_leaf()))
+                        && internal_node.leaf_count()
+                            == children.values().map(Child::leaf_count).sum::<usize>(),
+                    "Internal node {:?} is malformed.",
+                    node_key
+                );
+                for (nibble, child) in internal_node.children_unsorted() {
+                    let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                    let child_node = nodes
+                        .get(&child_node_key)
+                        .ok_or_else(|| format_err!("Missing node at {:?}.", child_node_key))?;
+                    ensure!(
+                        !matches!(child_node, Node::Null)
+                            && child_node.hash::<H>() == child.hash
+                            && child_node.node_type() == child.node_type,
+                        "Node {:?} does not match its parent.",
+                        child_node_key
+                    );
+                    stack.push((child_node_key, child_node));
+                }
+            }
+            Node::Leaf(leaf_node) => {
+                num_leaves += 1;
+                let value = values
+                    .get(&leaf_node.key_hash())
+                    .ok_or_else(|| format_err!("Missing value of {:?}.", leaf_node.key_hash()))?;
+                ensure!(
+                    ValueHash::with::<H>(value) == leaf_node.value_hash(),
+                    "Value of {:?} does not match its leaf.",
+                    leaf_node.key_hash()
+                );
+            }
+            Node::Null => ensure!(
+                node_key.nibble_path().is_empty(),
+                "Non-root null node exists with node key {:?}",
+                node_key
+            ),
+        }
+    }
+    ensure!(
+        num_reachable_nodes == nodes.len() && num_leaves == values.len(),
+        "Checkpoint holds nodes or values unreachable from its root."
+    );
+
+    let values = values
+        .into_iter()
+        .map(|(key_hash, value)| ((version, key_hash), Some(value)))
+        .collect();
+    store.write_node_batch(&NodeBatch::new(nodes, values))?;
+    Ok((version, root_hash))
+}
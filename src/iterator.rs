@@ -11,13 +11,13 @@ use alloc::{sync::Arc, vec::Vec};
 use anyhow::{bail, ensure, format_err, Result};
 
 use crate::{
-    node_type::{Child, InternalNode, Node, NodeKey},
+    node_type::{Child, InternalNode, LeafNode, Node, NodeKey},
     storage::TreeReader,
     types::{
         nibble::{nibble_path::NibblePath, Nibble, ROOT_NIBBLE_HEIGHT},
         Version,
     },
-    KeyHash, OwnedValue,
+    KeyHash, OwnedValue, ValueHash,
 };
 
 /// `NodeVisitInfo` keeps track of the status of an internal node during the iteration process. It
@@ -121,6 +121,11 @@ where
     /// Constructs a new iterator. This puts the internal state in the correct position, so the
     /// following `next` call will yield the smallest key that is greater or equal to
     /// `starting_key`.
+    ///
+    /// `starting_key` does not need to be in the tree: if it is absent, e.g. because it is the
+    /// cursor of a paginated scan and was deleted between two pages, the iteration starts at the
+    /// next greater key, and yields nothing if there is none. This only depends on the keys of the
+    /// tree at `version`, so the same arguments always yield the same leaves.
     pub fn new(reader: Arc<R>, version: Version, starting_key: KeyHash) -> Result<Self> {
         let mut parent_stack = Vec::new();
         let mut done = false;
@@ -271,13 +276,37 @@ where
     }
 }
 
-impl<R> Iterator for JellyfishMerkleIterator<R>
+impl<R> JellyfishMerkleIterator<R>
 where
     R: TreeReader,
 {
-    type Item = Result<(KeyHash, OwnedValue)>;
+    /// Constructs an iterator over the key hashes and value hashes of all the leaves of the tree
+    /// at `version`, in key order. The hashes are read from the leaves, so no value is fetched.
+    pub fn hashes_only(reader: Arc<R>, version: Version) -> Result<JellyfishMerkleHashIterator<R>> {
+        Ok(JellyfishMerkleHashIterator {
+            inner: Self::new(reader, version, KeyHash([0; 32]))?,
+        })
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Groups the key-value pairs yielded by this iterator into chunks of `chunk_size`, in key
+    /// order, e.g. to process them in parallel. The leaves are still read one at a time, so at most
+    /// one chunk is held in memory. The last chunk may be smaller, and an error is yielded in place
+    /// of the chunk it occurred in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn chunks(self, chunk_size: usize) -> JellyfishMerkleChunkIterator<R> {
+        assert!(chunk_size > 0, "Chunk size must be positive.");
+        JellyfishMerkleChunkIterator {
+            inner: self,
+            chunk_size,
+        }
+    }
+
+    /// Returns the next leaf, without moving past it: the same leaf is returned again unless
+    /// [`skip_leaf`](Self::skip_leaf) is called.
+    fn next_leaf(&mut self) -> Option<Result<(NodeKey, LeafNode)>> {
         if self.done {
             return None;
         }
@@ -291,13 +320,7 @@ where
                     // true in `new`). Return the node and mark `self.done` so next time we return
                     // None.
                     self.done = true;
-                    return match self
-                        .reader
-                        .get_value(root_node_key.version(), leaf_node.key_hash())
-                    {
-                        Ok(value) => Some(Ok((leaf_node.key_hash(), value))),
-                        Err(e) => Some(Err(e)),
-                    };
+                    return Some(Ok((root_node_key, leaf_node)));
                 }
                 Ok(Node::Internal(_)) => {
                     // This means `starting_key` is bigger than every key in this tree, or we have
@@ -329,22 +352,98 @@ where
                     let visit_info = NodeVisitInfo::new(node_key, internal_node);
                     self.parent_stack.push(visit_info);
                 }
-                Ok(Node::Leaf(leaf_node)) => {
-                    return match self
-                        .reader
-                        .get_value(node_key.version(), leaf_node.key_hash())
-                    {
-                        Ok(value) => {
-                            let ret = (leaf_node.key_hash(), value);
-                            Self::cleanup_stack(&mut self.parent_stack);
-                            Some(Ok(ret))
-                        }
-                        Err(e) => Some(Err(e)),
-                    }
-                }
+                Ok(Node::Leaf(leaf_node)) => return Some(Ok((node_key, leaf_node))),
                 Ok(Node::Null) => return Some(Err(format_err!("Should not reach a null node."))),
                 Err(err) => return Some(Err(err)),
             }
         }
     }
+
+    /// Moves past the leaf returned by [`next_leaf`](Self::next_leaf).
+    fn skip_leaf(&mut self) {
+        // A tree with a single leaf has no stack to clean up, and is already done.
+        if !self.parent_stack.is_empty() {
+            Self::cleanup_stack(&mut self.parent_stack);
+        }
+    }
+}
+
+impl<R> Iterator for JellyfishMerkleIterator<R>
+where
+    R: TreeReader,
+{
+    type Item = Result<(KeyHash, OwnedValue)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node_key, leaf_node) = match self.next_leaf()? {
+            Ok(leaf) => leaf,
+            Err(err) => return Some(Err(err)),
+        };
+        match self
+            .reader
+            .get_value(node_key.version(), leaf_node.key_hash())
+        {
+            Ok(value) => {
+                self.skip_leaf();
+                Some(Ok((leaf_node.key_hash(), value)))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// An iterator over the key hashes and value hashes of all the leaves of a
+/// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree), created by
+/// [`JellyfishMerkleIterator::hashes_only`].
+///
+/// Unlike [`JellyfishMerkleIterator`], it never reads values, which makes it much cheaper when
+/// values are large and only a digest of the tree is needed.
+pub struct JellyfishMerkleHashIterator<R> {
+    inner: JellyfishMerkleIterator<R>,
+}
+
+impl<R> Iterator for JellyfishMerkleHashIterator<R>
+where
+    R: TreeReader,
+{
+    type Item = Result<(KeyHash, ValueHash)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, leaf_node) = match self.inner.next_leaf()? {
+            Ok(leaf) => leaf,
+            Err(err) => return Some(Err(err)),
+        };
+        self.inner.skip_leaf();
+        Some(Ok((leaf_node.key_hash(), leaf_node.value_hash())))
+    }
+}
+
+/// An iterator over the key-value pairs of a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
+/// grouped into chunks, created by [`JellyfishMerkleIterator::chunks`].
+pub struct JellyfishMerkleChunkIterator<R> {
+    inner: JellyfishMerkleIterator<R>,
+    chunk_size: usize,
+}
+
+impl<R> Iterator for JellyfishMerkleChunkIterator<R>
+where
+    R: TreeReader,
+{
+    type Item = Result<Vec<(KeyHash, OwnedValue)>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        while chunk.len() < self.chunk_size {
+            match self.inner.next() {
+                Some(Ok(leaf)) => chunk.push(leaf),
+                Some(Err(err)) => return Some(Err(err)),
+                None => break,
+            }
+        }
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(Ok(chunk))
+        }
+    }
 }
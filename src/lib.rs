@@ -81,7 +81,10 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 mod bytes32ext;
+#[cfg(feature = "std")]
+pub mod checkpoint;
 mod iterator;
+mod map;
 mod node_type;
 mod reader;
 mod tree;
@@ -94,13 +97,22 @@ pub mod mock;
 pub mod restore;
 
 use bytes32ext::Bytes32Ext;
-pub use iterator::JellyfishMerkleIterator;
+pub use iterator::{
+    JellyfishMerkleChunkIterator, JellyfishMerkleHashIterator, JellyfishMerkleIterator,
+};
+pub use map::JmtMap;
 #[cfg(feature = "ics23")]
 pub use tree::ics23_impl::ics23_spec;
-pub use tree::JellyfishMerkleTree;
+pub use tree::{
+    Direction, GetProofOutcome, JellyfishMerkleTree, MembershipFilter, ProofBuffer, RootRepair,
+    StorageSize,
+};
+#[cfg(feature = "std")]
+pub use tree::{ProofCache, RootHashCache};
 #[cfg(any(test, feature = "sha2"))]
 pub use tree::Sha256Jmt;
 
+pub use types::nibble::common_prefix_nibbles;
 use types::nibble::ROOT_NIBBLE_HEIGHT;
 pub use types::proof;
 pub use types::Version;
@@ -108,12 +120,19 @@ pub use types::Version;
 /// Contains types used to bridge a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
 /// to the backing storage recording the tree's internal data.
 pub mod storage {
-    pub use node_type::{LeafNode, Node, NodeKey};
+    pub use node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey, NodeType};
+    #[cfg(feature = "access_histogram")]
+    pub use reader::AccessCountingReader;
     pub use reader::HasPreimage;
-    pub use reader::TreeReader;
+    #[cfg(feature = "std")]
+    pub use reader::RecordingReader;
+    pub use reader::{OverlayReader, ShardedTreeReader, TreeReader};
+    pub use tree_cache::{CacheLayer, ReadOnlySnapshot, TreeCache, TreeCacheCheckpoint};
     pub use types::nibble::nibble_path::NibblePath;
+    pub use types::nibble::Nibble;
     pub use writer::{
-        NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch, TreeUpdateBatch, TreeWriter,
+        BatchError, CommittedRoot, NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch,
+        TreeTruncator, TreeUpdateBatch, TreeWriter, TruncationBatch,
     };
 
     use super::*;
@@ -144,11 +163,154 @@ impl core::fmt::Display for MissingRootError {
     }
 }
 
+/// An error that occurs when the tree fails to read a node or a value from its
+/// [`TreeReader`](storage::TreeReader), telling backend failures, which may be worth retrying,
+/// from data missing from storage, which means it is corrupted (or pruned).
+///
+/// It is attached to the [`anyhow::Error`]s returned by the tree and can be retrieved with
+/// [`anyhow::Error::downcast_ref`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Error))]
+pub enum ReadError {
+    /// The storage backend failed to read the node at this key. The error of the backend is the
+    /// cause of the returned error, and can still be downcast to its own type.
+    #[cfg_attr(feature = "std", error("Storage backend failed to read node at {0:?}."))]
+    Backend(storage::NodeKey),
+    /// The node at this key was expected to exist, but is missing from storage.
+    #[cfg_attr(feature = "std", error("Missing node at {0:?}."))]
+    UnexpectedMissing(storage::NodeKey),
+    /// The storage backend failed to read the value of `key_hash` at `max_version`. The error of
+    /// the backend is the cause of the returned error, and can still be downcast to its own type.
+    #[cfg_attr(
+        feature = "std",
+        error("Storage backend failed to read value with max_version {max_version} and key hash {key_hash:?}.")
+    )]
+    BackendValue {
+        max_version: Version,
+        key_hash: KeyHash,
+    },
+    /// The value of `key_hash` at `max_version` was expected to exist, but is missing from
+    /// storage.
+    #[cfg_attr(
+        feature = "std",
+        error("Missing value with max_version {max_version} and key hash {key_hash:?}.")
+    )]
+    UnexpectedMissingValue {
+        max_version: Version,
+        key_hash: KeyHash,
+    },
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ReadError::Backend(node_key) => {
+                write!(f, "Storage backend failed to read node at {:?}.", node_key)
+            }
+            ReadError::UnexpectedMissing(node_key) => write!(f, "Missing node at {:?}.", node_key),
+            ReadError::BackendValue {
+                max_version,
+                key_hash,
+            } => write!(
+                f,
+                "Storage backend failed to read value with max_version {} and key hash {:?}.",
+                max_version, key_hash
+            ),
+            ReadError::UnexpectedMissingValue {
+                max_version,
+                key_hash,
+            } => write!(
+                f,
+                "Missing value with max_version {} and key hash {:?}.",
+                max_version, key_hash
+            ),
+        }
+    }
+}
+
+/// An error that occurs when the merkle proof of a key would have more siblings than the
+/// maximum depth asked for, e.g. by
+/// [`JellyfishMerkleTree::get_with_proof_max_depth`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[cfg_attr(
+    feature = "std",
+    error("Merkle proof of key hash {key_hash:?} is deeper than {max_depth} siblings.")
+)]
+pub struct ProofTooDeep {
+    pub key_hash: KeyHash,
+    pub max_depth: usize,
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for ProofTooDeep {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Merkle proof of key hash {:?} is deeper than {} siblings.",
+            self.key_hash, self.max_depth
+        )
+    }
+}
+
+/// An error that occurs when a node is put into a [`TreeCache`](storage::TreeCache) at a key that
+/// already holds a different node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[cfg_attr(
+    feature = "std",
+    error("Node with key {node_key:?} already exists in NodeBatch")
+)]
+pub struct NodeAlreadyExists {
+    pub node_key: storage::NodeKey,
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for NodeAlreadyExists {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Node with key {:?} already exists in NodeBatch",
+            self.node_key
+        )
+    }
+}
+
+/// An error that occurs when a value is larger than the limit it is read with, see
+/// [`TreeReader::get_value_option_limited`](storage::TreeReader::get_value_option_limited).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[cfg_attr(
+    feature = "std",
+    error("Value of key hash {key_hash:?} at version {max_version} is {size} bytes, more than the limit of {max_bytes} bytes.")
+)]
+pub struct ValueTooLarge {
+    pub key_hash: KeyHash,
+    pub max_version: Version,
+    pub size: usize,
+    pub max_bytes: usize,
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for ValueTooLarge {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Value of key hash {:?} at version {} is {} bytes, more than the limit of {} bytes.",
+            self.key_hash, self.max_version, self.size, self.max_bytes
+        )
+    }
+}
+
 // TODO: reorg
 
 const SPARSE_MERKLE_PLACEHOLDER_HASH: [u8; 32] = *b"SPARSE_MERKLE_PLACEHOLDER_HASH__";
 
 /// An owned value stored in the [`JellyfishMerkleTree`].
+///
+/// An empty value is a value like any other: it is stored in a leaf and proven by an inclusion
+/// proof, whereas a deletion (`None`) removes the leaf and is proven by a non-inclusion proof.
 pub type OwnedValue = alloc::vec::Vec<u8>;
 
 #[cfg(any(test))]
@@ -171,6 +333,14 @@ use proptest_derive::Arbitrary;
 #[cfg_attr(any(test), derive(Arbitrary))]
 pub struct RootHash(pub [u8; 32]);
 
+impl RootHash {
+    /// The root hash of a tree with zero leaves, i.e. the hash of the null node.
+    ///
+    /// The null node hashes to a fixed placeholder rather than to the output of the tree's
+    /// [`SimpleHasher`], so this is the same for every hasher.
+    pub const EMPTY: RootHash = RootHash(SPARSE_MERKLE_PLACEHOLDER_HASH);
+}
+
 impl From<RootHash> for [u8; 32] {
     fn from(value: RootHash) -> Self {
         value.0
@@ -309,6 +479,11 @@ impl<'a> core::fmt::Debug for EscapedByteSlice<'a> {
 
 /// A minimal trait representing a hash function. We implement our own
 /// rather than relying on `Digest` for broader compatibility.
+///
+/// Digests are 32 bytes long: node hashes, [`RootHash`]es, proofs and their encodings all store
+/// 32-byte hashes. A hash function with a longer output, e.g. BLAKE2b-512, can be used by
+/// truncating its digests to their first 32 bytes in [`finalize`](SimpleHasher::finalize), as
+/// SHA-512/256 does with SHA-512.
 pub trait SimpleHasher: Sized {
     /// Creates a new hasher with default state.
     fn new() -> Self;
@@ -341,6 +516,37 @@ where
     }
 }
 
+/// A [`SimpleHasher`] with a name identifying it, e.g. to record in a header which hasher some
+/// serialized data was produced with, and reject data produced with another one.
+///
+/// The name is not part of [`SimpleHasher`] itself, since every [`Digest`] gets its
+/// implementation from the same blanket impl, which cannot tell them apart. There is no
+/// counterpart for the length of the digests, which is 32 bytes for every [`SimpleHasher`].
+///
+/// Only [checkpoints](crate::checkpoint) record the name of their hasher and check it on import.
+/// Proofs and their encodings do not record which hasher produced them, and verifying a proof does
+/// not check it: a proof verified with another hasher than the one it was produced with is only
+/// rejected because the root hash it leads to does not match.
+pub trait NamedHasher: SimpleHasher {
+    /// The name of this hasher.
+    const NAME: &'static str;
+}
+
+#[cfg(any(test, feature = "sha2"))]
+impl NamedHasher for sha2::Sha256 {
+    const NAME: &'static str = "sha256";
+}
+
+#[cfg(any(test, feature = "sha2"))]
+impl NamedHasher for sha2::Sha512_256 {
+    const NAME: &'static str = "sha512_256";
+}
+
+#[cfg(feature = "blake3_tests")]
+impl NamedHasher for blake3::Hasher {
+    const NAME: &'static str = "blake3";
+}
+
 /// A trivial implementation of [`SimpleHasher`] that simply returns the first 32 bytes of the
 /// provided data. This is useful to avoid hashing data when testing, and facilitate debugging
 /// specific tree configurations.
@@ -362,3 +568,7 @@ impl SimpleHasher for TransparentHasher {
         self.key
     }
 }
+
+impl NamedHasher for TransparentHasher {
+    const NAME: &'static str = "transparent";
+}
@@ -0,0 +1,116 @@
+//! This module implements [`JmtMap`], a map-like facade over a [`JellyfishMerkleTree`] that hides
+//! its versions.
+
+use alloc::{collections::BTreeMap, vec};
+use core::marker::PhantomData;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    storage::{TreeReader, TreeWriter},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+/// A map from keys to values stored in a [`JellyfishMerkleTree`] in `store`, for applications that
+/// do not need to manage versions themselves.
+///
+/// [`insert`](JmtMap::insert) and [`remove`](JmtMap::remove) are buffered in memory until
+/// [`commit`](JmtMap::commit), which applies them to the tree as the next version and persists
+/// it with [`TreeWriter::commit`].
+///
+/// The map finds the latest committed version with [`TreeReader::get_latest_version`], which
+/// `store` must implement.
+pub struct JmtMap<'a, S, H: SimpleHasher> {
+    store: &'a S,
+    next_version: Version,
+    pending: BTreeMap<KeyHash, Option<OwnedValue>>,
+    _phantom_hasher: PhantomData<H>,
+}
+
+impl<'a, S, H> JmtMap<'a, S, H>
+where
+    S: TreeReader + TreeWriter,
+    H: SimpleHasher,
+{
+    /// Creates a map over `store`, to which no version of the tree has been committed yet.
+    ///
+    /// Fails if `store` already holds a version, as told by
+    /// [`TreeReader::get_latest_version`], since the map would overwrite it. Use
+    /// [`open`](JmtMap::open) to pick up from it instead.
+    pub fn new(store: &'a S) -> Result<Self> {
+        if let Some(version) = store.get_latest_version()? {
+            bail!(
+                "Store already holds version {}, open the map instead.",
+                version
+            );
+        }
+        Ok(Self::with_next_version(store, 0))
+    }
+
+    /// Creates a map over `store`, reading the tree at the latest version committed to it, as told
+    /// by [`TreeReader::get_latest_version`], and committing the next version on top of it. If no
+    /// version was committed yet, this is the same as [`new`](JmtMap::new).
+    pub fn open(store: &'a S) -> Result<Self> {
+        let next_version = store.get_latest_version()?.map_or(0, |version| version + 1);
+        Ok(Self::with_next_version(store, next_version))
+    }
+
+    fn with_next_version(store: &'a S, next_version: Version) -> Self {
+        Self {
+            store,
+            next_version,
+            pending: BTreeMap::new(),
+            _phantom_hasher: Default::default(),
+        }
+    }
+
+    /// Returns the value of `key`: the pending one if `key` was inserted or removed since the
+    /// last commit, and the one in the latest committed version otherwise.
+    pub fn get(&self, key: KeyHash) -> Result<Option<OwnedValue>> {
+        if let Some(value) = self.pending.get(&key) {
+            return Ok(value.clone());
+        }
+        match self.version() {
+            Some(version) => self.tree().get(key, version),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets the value of `key` to `value` on the next commit.
+    pub fn insert(&mut self, key: KeyHash, value: OwnedValue) {
+        self.pending.insert(key, Some(value));
+    }
+
+    /// Removes `key` on the next commit.
+    pub fn remove(&mut self, key: KeyHash) {
+        self.pending.insert(key, None);
+    }
+
+    /// Applies the pending insertions and removals to the tree as a new version, persists it, and
+    /// returns its root hash. If this fails, nothing is committed and the changes stay pending.
+    pub fn commit(&mut self) -> Result<RootHash> {
+        let value_set = self
+            .pending
+            .iter()
+            .map(|(key, value)| (*key, value.clone()));
+        let (root_hash, batch) = self.tree().put_value_set(value_set, self.next_version)?;
+        let committed_roots = self.store.commit(vec![root_hash], batch)?;
+        self.pending.clear();
+        self.next_version += 1;
+        Ok(committed_roots[0].root_hash())
+    }
+
+    /// Returns the latest committed version, or `None` if none has been committed yet.
+    pub fn version(&self) -> Option<Version> {
+        self.next_version.checked_sub(1)
+    }
+
+    /// Returns `true` if there are insertions or removals that have not been committed.
+    pub fn has_pending_changes(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn tree(&self) -> JellyfishMerkleTree<'a, S, H> {
+        JellyfishMerkleTree::new(self.store)
+    }
+}
@@ -4,6 +4,7 @@
 //! A mock, in-memory tree store useful for testing.
 
 use alloc::{collections::BTreeSet, vec};
+use core::marker::PhantomData;
 use parking_lot::RwLock;
 
 use alloc::vec::Vec;
@@ -16,9 +17,13 @@ use std::collections::{hash_map::Entry, HashMap};
 
 use crate::{
     node_type::{LeafNode, Node, NodeKey},
-    storage::{HasPreimage, NodeBatch, StaleNodeIndex, TreeReader, TreeUpdateBatch, TreeWriter},
-    types::Version,
-    KeyHash, OwnedValue,
+    reader::check_value_size,
+    storage::{
+        HasPreimage, NodeBatch, StaleNodeIndex, TreeReader, TreeTruncator, TreeUpdateBatch,
+        TreeWriter, TruncationBatch,
+    },
+    types::{Version, PRE_GENESIS_VERSION},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher,
 };
 
 #[derive(Default, Debug)]
@@ -87,6 +92,77 @@ impl TreeReader for MockTreeStore {
             None => Ok(None),
         }
     }
+
+    fn get_value_option_limited(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+        max_bytes: usize,
+    ) -> Result<Option<OwnedValue>> {
+        let locked = self.data.read();
+        let value = locked
+            .value_history
+            .get(&key_hash)
+            .and_then(|version_history| {
+                version_history
+                    .iter()
+                    .rev()
+                    .find(|(version, _)| *version <= max_version)
+            });
+        match value {
+            Some((_, Some(value))) => {
+                // Checked before cloning the value out of the store.
+                check_value_size(max_version, key_hash, value.len(), max_bytes)?;
+                Ok(Some(value.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn stale_versions(&self) -> Result<Vec<Version>> {
+        let mut stale_versions: Vec<Version> = self
+            .data
+            .read()
+            .stale_nodes
+            .iter()
+            .map(|index| index.stale_since_version)
+            .collect();
+        stale_versions.dedup();
+        Ok(stale_versions)
+    }
+
+    fn get_stale_nodes_since(&self, stale_since_version: Version) -> Result<Vec<StaleNodeIndex>> {
+        Ok(self
+            .data
+            .read()
+            .stale_nodes
+            .iter()
+            .skip_while(|index| index.stale_since_version < stale_since_version)
+            .take_while(|index| index.stale_since_version == stale_since_version)
+            .cloned()
+            .collect())
+    }
+
+    fn get_node_keys(&self) -> Result<Vec<NodeKey>> {
+        Ok(self.data.read().nodes.keys().cloned().collect())
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        Ok(self
+            .data
+            .read()
+            .nodes
+            .keys()
+            .filter(|node_key| {
+                node_key.nibble_path().is_empty() && node_key.version() != PRE_GENESIS_VERSION
+            })
+            .map(NodeKey::version)
+            .max())
+    }
+
+    fn num_versions(&self) -> Result<u64> {
+        Ok(self.get_latest_version()?.map_or(0, |version| version + 1))
+    }
 }
 
 impl HasPreimage for MockTreeStore {
@@ -98,20 +174,59 @@ impl HasPreimage for MockTreeStore {
 impl TreeWriter for MockTreeStore {
     fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
         let mut locked = self.data.write();
-        for (node_key, node) in node_batch.nodes() {
-            let replaced = locked.nodes.insert(node_key.clone(), node.clone());
-            if !self.allow_overwrite {
-                assert_eq!(replaced, None);
+        self.write_node_batch_locked(&mut locked, node_batch)
+    }
+
+    fn write_update_batch_atomic(&self, batch: TreeUpdateBatch) -> Result<()> {
+        let mut locked = self.data.write();
+        // Check what could fail before writing anything, so that a failed write leaves the store
+        // unchanged.
+        if !self.allow_overwrite {
+            for (node_key, node) in batch.node_batch.nodes() {
+                // Writing the same node again, e.g. when a batch is redelivered, is a no-op.
+                ensure!(
+                    !matches!(locked.nodes.get(node_key), Some(existing) if existing != node),
+                    "Key {:?} exists.",
+                    node_key
+                );
             }
         }
-        for ((version, key_hash), value) in node_batch.values() {
-            put_value(
-                &mut locked.value_history,
-                *version,
-                *key_hash,
-                value.clone(),
-            )?
+        for ((version, key_hash), value) in batch.node_batch.values() {
+            if let Some(version_history) = locked.value_history.get(key_hash) {
+                if let Some((last_version, _)) = version_history.last() {
+                    ensure!(
+                        last_version <= version || is_stored(version_history, *version, value),
+                        "values must be pushed in order"
+                    );
+                }
+            }
         }
+        self.write_node_batch_locked(&mut locked, &batch.node_batch)?;
+        // Marking a node stale again at the same version, e.g. when a batch is redelivered, is a
+        // no-op.
+        locked.stale_nodes.extend(batch.stale_node_index_batch);
+        Ok(())
+    }
+}
+
+impl TreeTruncator for MockTreeStore {
+    fn truncate(&self, truncation_batch: &TruncationBatch) -> Result<()> {
+        let mut locked = self.data.write();
+        for node_key in truncation_batch.node_keys.iter() {
+            let removed = locked.nodes.remove(node_key).is_some();
+            ensure!(removed, "Truncation batch refers to non-existent node.");
+        }
+        let version = truncation_batch.version;
+        locked
+            .value_history
+            .values_mut()
+            .for_each(|history| history.retain(|(v, _)| *v <= version));
+        locked
+            .value_history
+            .retain(|_, history| !history.is_empty());
+        locked
+            .stale_nodes
+            .retain(|index| index.stale_since_version <= version);
         Ok(())
     }
 }
@@ -125,6 +240,10 @@ pub fn put_value(
 ) -> Result<()> {
     match value_history.entry(key) {
         Entry::Occupied(mut occupied) => {
+            // Writing the same value again, e.g. when a batch is redelivered, is a no-op.
+            if is_stored(occupied.get(), version, &value) {
+                return Ok(());
+            }
             if let Some((last_version, last_value)) = occupied.get_mut().last_mut() {
                 match version.cmp(last_version) {
                     core::cmp::Ordering::Less => bail!("values must be pushed in order"),
@@ -145,6 +264,18 @@ pub fn put_value(
     Ok(())
 }
 
+/// Whether `value` is already the value at `version` in `version_history`, which is sorted by
+/// version.
+fn is_stored(
+    version_history: &[(Version, Option<OwnedValue>)],
+    version: Version,
+    value: &Option<OwnedValue>,
+) -> bool {
+    version_history
+        .binary_search_by_key(&version, |(v, _)| *v)
+        .is_ok_and(|i| version_history[i].1 == *value)
+}
+
 impl MockTreeStore {
     pub fn new(allow_overwrite: bool) -> Self {
         Self {
@@ -173,19 +304,30 @@ impl MockTreeStore {
             .insert(key_hash, preimage.clone());
     }
 
-    fn put_stale_node_index(&self, index: StaleNodeIndex) -> Result<()> {
-        let is_new_entry = self.data.write().stale_nodes.insert(index);
-        ensure!(is_new_entry, "Duplicated retire log.");
-        Ok(())
+    pub fn write_tree_update_batch(&self, batch: TreeUpdateBatch) -> Result<()> {
+        self.write_update_batch_atomic(batch)
     }
 
-    pub fn write_tree_update_batch(&self, batch: TreeUpdateBatch) -> Result<()> {
-        self.write_node_batch(&batch.node_batch)?;
-        batch
-            .stale_node_index_batch
-            .into_iter()
-            .map(|i| self.put_stale_node_index(i))
-            .collect::<Result<Vec<_>>>()?;
+    fn write_node_batch_locked(
+        &self,
+        locked: &mut MockTreeStoreInner,
+        node_batch: &NodeBatch,
+    ) -> Result<()> {
+        for (node_key, node) in node_batch.nodes() {
+            let replaced = locked.nodes.insert(node_key.clone(), node.clone());
+            if !self.allow_overwrite {
+                // Writing the same node again, e.g. when a batch is redelivered, is a no-op.
+                assert!(replaced.is_none() || replaced.as_ref() == Some(node));
+            }
+        }
+        for ((version, key_hash), value) in node_batch.values() {
+            put_value(
+                &mut locked.value_history,
+                *version,
+                *key_hash,
+                value.clone(),
+            )?
+        }
         Ok(())
     }
 
@@ -213,4 +355,91 @@ impl MockTreeStore {
     pub fn num_nodes(&self) -> usize {
         self.data.read().nodes.len()
     }
+
+    /// Returns the number of versions at which a value of `key_hash` was written.
+    pub fn num_values(&self, key_hash: KeyHash) -> usize {
+        self.data
+            .read()
+            .value_history
+            .get(&key_hash)
+            .map_or(0, |version_history| version_history.len())
+    }
+}
+
+/// A single operation applied by a [`TreeDriver`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Stages an insertion or update of `key` in the next version.
+    Put(KeyHash, OwnedValue),
+    /// Stages a deletion of `key` in the next version.
+    Delete(KeyHash),
+    /// Commits all staged operations as a new version.
+    Commit,
+}
+
+/// Drives a [`JellyfishMerkleTree`] backed by a [`MockTreeStore`] with a flat sequence of
+/// [`Op`]s, which makes it a convenient target for property tests and fuzzers.
+pub struct TreeDriver<H: SimpleHasher> {
+    store: MockTreeStore,
+    pending: Vec<(KeyHash, Option<OwnedValue>)>,
+    next_version: Version,
+    _phantom_hasher: PhantomData<H>,
+}
+
+impl<H: SimpleHasher> Default for TreeDriver<H> {
+    fn default() -> Self {
+        Self {
+            store: Default::default(),
+            pending: Vec::new(),
+            next_version: 0,
+            _phantom_hasher: Default::default(),
+        }
+    }
+}
+
+impl<H: SimpleHasher> TreeDriver<H> {
+    /// Creates a driver over an empty [`MockTreeStore`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Applies `ops` in order and returns the root hash of every version committed along the way.
+    /// Operations staged after the last [`Op::Commit`] are kept for the next call.
+    ///
+    /// # Panics
+    /// Panics if the tree fails to apply or persist a version.
+    pub fn apply(&mut self, ops: Vec<Op>) -> Vec<RootHash> {
+        let mut root_hashes = Vec::new();
+        for op in ops {
+            match op {
+                Op::Put(key, value) => self.pending.push((key, Some(value))),
+                Op::Delete(key) => self.pending.push((key, None)),
+                Op::Commit => root_hashes.push(self.commit()),
+            }
+        }
+        root_hashes
+    }
+
+    fn commit(&mut self) -> RootHash {
+        let value_set = core::mem::take(&mut self.pending);
+        let (root_hash, batch) = JellyfishMerkleTree::<_, H>::new(&self.store)
+            .put_value_set(value_set, self.next_version)
+            .expect("applying a version to the tree should succeed");
+        let committed_roots = self
+            .store
+            .commit(vec![root_hash], batch)
+            .expect("writing a version to the store should succeed");
+        self.next_version += 1;
+        committed_roots[0].root_hash()
+    }
+
+    /// Returns the version that the next [`Op::Commit`] will create.
+    pub fn next_version(&self) -> Version {
+        self.next_version
+    }
+
+    /// Returns the underlying store, e.g. to read back values and proofs.
+    pub fn store(&self) -> &MockTreeStore {
+        &self.store
+    }
 }
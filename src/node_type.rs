@@ -26,7 +26,7 @@ use serde::{Deserialize, Serialize};
 use crate::proof::SparseMerkleNode;
 use crate::{
     types::{
-        nibble::{nibble_path::NibblePath, Nibble},
+        nibble::{nibble_path::NibblePath, Nibble, ROOT_NIBBLE_HEIGHT},
         proof::{SparseMerkleInternalNode, SparseMerkleLeafNode},
         Version,
     },
@@ -34,6 +34,12 @@ use crate::{
 };
 
 /// The unique key of each node.
+///
+/// `NodeKey` implements [`Hash`](core::hash::Hash) consistently with [`Eq`]: both are derived
+/// from the version and the nibble path only, so it can key external maps such as caches of
+/// prefetched nodes. This is part of the public contract and will not change within
+/// semver-compatible releases. The hash values themselves depend on the
+/// [`Hasher`](core::hash::Hasher) in use and should not be persisted.
 #[derive(
     Clone,
     Debug,
@@ -69,12 +75,29 @@ impl NodeKey {
         Self::new(version, NibblePath::new(vec![]))
     }
 
-    /// Gets the version.
+    /// Gets the version the node was created at, i.e. the version of the tree whose update
+    /// wrote it. The node stays part of the trees of the later versions until it is replaced.
+    ///
+    /// ```
+    /// use jmt::storage::{Nibble, NodeKey};
+    ///
+    /// let root_key = NodeKey::new(7, std::iter::empty::<Nibble>().collect());
+    /// assert_eq!(root_key.version(), 7);
+    /// ```
     pub fn version(&self) -> Version {
         self.version
     }
 
-    /// Gets the nibble path.
+    /// Gets the nibble path leading from the root of the tree to the node, empty for the root.
+    ///
+    /// ```
+    /// use jmt::storage::{Nibble, NibblePath, NodeKey};
+    ///
+    /// let nibble_path: NibblePath = [Nibble::from(1), Nibble::from(0xa)].into_iter().collect();
+    /// let node_key = NodeKey::new(7, nibble_path.clone());
+    /// assert_eq!(node_key.nibble_path(), &nibble_path);
+    /// assert_eq!(node_key.nibble_path().num_nibbles(), 2);
+    /// ```
     pub fn nibble_path(&self) -> &NibblePath {
         &self.nibble_path
     }
@@ -100,6 +123,50 @@ impl NodeKey {
     pub(crate) fn set_version(&mut self, version: Version) {
         self.version = version;
     }
+
+    /// Encodes this key into bytes, suitable as a key in storage backends.
+    ///
+    /// The layout is the Borsh encoding of the key, and will not change within semver-compatible
+    /// releases:
+    ///
+    /// ```text
+    /// node_key    := version nibble_path
+    /// version     := u64, little endian
+    /// nibble_path := num_nibbles len path[len]
+    /// num_nibbles := u64, little endian
+    /// len         := u32, little endian, (num_nibbles + 1) / 2
+    /// path        := the nibbles, 2 per byte from the high half; an odd path is padded with 0
+    /// ```
+    ///
+    /// Note that the bytes of two keys do not sort like the keys themselves.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        borsh::to_vec(self).expect("serialization is infallible")
+    }
+
+    /// Decodes a key encoded with [`to_bytes`](NodeKey::to_bytes). The whole of `bytes` must be
+    /// a single, canonically encoded key.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let node_key: Self = borsh::from_slice(bytes)?;
+        let nibble_path = node_key.nibble_path();
+        anyhow::ensure!(
+            nibble_path.num_nibbles() <= ROOT_NIBBLE_HEIGHT,
+            "Nibble path has more than {} ({}) nibbles.",
+            ROOT_NIBBLE_HEIGHT,
+            nibble_path.num_nibbles()
+        );
+        anyhow::ensure!(
+            nibble_path.bytes().len() == nibble_path.num_nibbles().div_ceil(2),
+            "Nibble path of {} nibbles has {} bytes.",
+            nibble_path.num_nibbles(),
+            nibble_path.bytes().len()
+        );
+        anyhow::ensure!(
+            nibble_path.num_nibbles() == nibble_path.bytes().len() * 2
+                || nibble_path.bytes().last().is_some_and(|byte| byte & 0x0f == 0),
+            "Odd nibble path is not padded with 0."
+        );
+        Ok(node_key)
+    }
 }
 
 #[derive(
@@ -317,8 +384,8 @@ impl SparseMerkleInternalNode {
     fn from<H: SimpleHasher>(internal_node: InternalNode) -> Self {
         let bitmaps = internal_node.generate_bitmaps();
         SparseMerkleInternalNode::new(
-            internal_node.merkle_hash::<H>(0, 8, bitmaps),
-            internal_node.merkle_hash::<H>(8, 8, bitmaps),
+            InternalNode::merkle_hash::<H>(&internal_node.children, 0, 8, bitmaps),
+            InternalNode::merkle_hash::<H>(&internal_node.children, 8, 8, bitmaps),
         )
     }
 }
@@ -406,6 +473,15 @@ fn has_child(
 impl InternalNode {
     /// Creates a new Internal node.
     pub fn new(children: Children) -> Self {
+        Self::assert_valid_children(&children);
+        let leaf_count = Self::sum_leaf_count(&children);
+        Self {
+            children,
+            leaf_count,
+        }
+    }
+
+    fn assert_valid_children(children: &Children) {
         // Assert the internal node must have >= 1 children. If it only has one child, it cannot be
         // a leaf node. Otherwise, the leaf node should be a child of this internal node's parent.
         assert!(!children.is_empty(), "Children must not be empty");
@@ -419,12 +495,6 @@ impl InternalNode {
                 "If there's only one child, it must not be a leaf."
             );
         }
-
-        let leaf_count = Self::sum_leaf_count(&children);
-        Self {
-            children,
-            leaf_count,
-        }
     }
 
     fn sum_leaf_count(children: &Children) -> usize {
@@ -446,14 +516,33 @@ impl InternalNode {
         }
     }
 
+    /// Computes the hash of this internal node, see [`hash_from_children`](Self::hash_from_children).
     pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
-        self.merkle_hash::<H>(
+        Self::merkle_hash::<H>(
+            &self.children,
             0,  /* start index */
             16, /* the number of leaves in the subtree of which we want the hash of root */
             self.generate_bitmaps(),
         )
     }
 
+    /// Computes the hash of the internal node made of `children`, without building it.
+    ///
+    /// The 16 children are the bottom level of a 4-level binary Merkle tree, whose root hash is
+    /// the hash of the internal node. The hash of a binary node is `H(b"JMT::IntrnalNode" || left
+    /// || right)`, except that a subtree without any child is the placeholder hash
+    /// `b"SPARSE_MERKLE_PLACEHOLDER_HASH__"`, and a subtree whose only child is a leaf is the hash
+    /// of that leaf. A child's hash is the hash of the node it points to, and its node type tells
+    /// whether it is a leaf.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `children` is empty or only holds a leaf, which is not a valid internal node.
+    pub fn hash_from_children<H: SimpleHasher>(children: &Children) -> [u8; 32] {
+        Self::assert_valid_children(children);
+        Self::merkle_hash::<H>(children, 0, 16, Self::children_bitmaps(children))
+    }
+
     pub fn children_sorted(&self) -> impl Iterator<Item = (Nibble, &Child)> {
         // Previously this used `.sorted_by_key()` directly on the iterator but this does not appear
         // to be available in itertools (it does not seem to ever have existed???) for unknown
@@ -474,9 +563,13 @@ impl InternalNode {
     /// exists if `existence_bitmap[i]` is set; child at index `i` is leaf node if
     /// `leaf_bitmap[i]` is set.
     pub fn generate_bitmaps(&self) -> (u16, u16) {
+        Self::children_bitmaps(&self.children)
+    }
+
+    fn children_bitmaps(children: &Children) -> (u16, u16) {
         let mut existence_bitmap = 0;
         let mut leaf_bitmap = 0;
-        for (nibble, child) in self.children.iter() {
+        for (nibble, child) in children.iter() {
             let i = u8::from(nibble);
             existence_bitmap |= 1u16 << i;
             if child.is_leaf() {
@@ -488,6 +581,20 @@ impl InternalNode {
         (existence_bitmap, leaf_bitmap)
     }
 
+    /// Returns the bitmap of the populated child slots: the child at index `i` exists if bit `i`
+    /// is set. This is the `existence_bitmap` of [`generate_bitmaps`](Self::generate_bitmaps).
+    pub fn children_bitmap(&self) -> u16 {
+        self.generate_bitmaps().0
+    }
+
+    /// Returns an iterator over the nibble, the hash and whether it is a leaf of every child, in
+    /// nibble order.
+    pub fn child_hashes(&self) -> impl Iterator<Item = (Nibble, [u8; 32], bool)> + '_ {
+        self.children
+            .iter_sorted()
+            .map(|(nibble, child)| (nibble, child.hash, child.is_leaf()))
+    }
+
     /// Given a range [start, start + width), returns the sub-bitmap of that range.
     fn range_bitmaps(start: u8, width: u8, bitmaps: (u16, u16)) -> (u16, u16) {
         assert!(start < 16 && width.count_ones() == 1 && start % width == 0);
@@ -508,13 +615,13 @@ impl InternalNode {
         start: u8,
         width: u8,
         (existence_bitmap, leaf_bitmap): (u16, u16),
-    ) -> SparseMerkleNode {
+    ) -> anyhow::Result<SparseMerkleNode> {
         // Given a bit [start, 1 << nibble_height], return the value of that range.
         let (range_existence_bitmap, range_leaf_bitmap) =
             Self::range_bitmaps(start, width, (existence_bitmap, leaf_bitmap));
         if range_existence_bitmap == 0 {
             // No child under this subtree
-            SparseMerkleNode::Null
+            Ok(SparseMerkleNode::Null)
         } else if has_only_child(width, range_existence_bitmap, range_leaf_bitmap) {
             // Only 1 leaf child under this subtree or reach the lowest level
             let only_child_index = Nibble::from(range_existence_bitmap.trailing_zeros() as u8);
@@ -538,33 +645,37 @@ impl InternalNode {
                          the child of version {:?} at index {:x}.",
                         child.version, only_child_index
                     )
-                })
-                .unwrap();
+                })?;
 
-            match child_node {
+            Ok(match child_node {
                 Node::Internal(node) => {
                     SparseMerkleNode::Internal(SparseMerkleInternalNode::from::<H>(node))
                 }
                 Node::Leaf(node) => SparseMerkleNode::Leaf(SparseMerkleLeafNode::from(node)),
                 Node::Null => unreachable!("Impossible to get a null node at this location"),
-            }
+            })
         } else {
-            let left_child = self.merkle_hash::<H>(
+            let left_child = Self::merkle_hash::<H>(
+                &self.children,
                 start,
                 width / 2,
                 (range_existence_bitmap, range_leaf_bitmap),
             );
-            let right_child = self.merkle_hash::<H>(
+            let right_child = Self::merkle_hash::<H>(
+                &self.children,
                 start + width / 2,
                 width / 2,
                 (range_existence_bitmap, range_leaf_bitmap),
             );
-            SparseMerkleNode::Internal(SparseMerkleInternalNode::new(left_child, right_child))
+            Ok(SparseMerkleNode::Internal(SparseMerkleInternalNode::new(
+                left_child,
+                right_child,
+            )))
         }
     }
 
     fn merkle_hash<H: SimpleHasher>(
-        &self,
+        children: &Children,
         start: u8,
         width: u8,
         (existence_bitmap, leaf_bitmap): (u16, u16),
@@ -578,7 +689,9 @@ impl InternalNode {
         } else if has_only_child(width, range_existence_bitmap, range_leaf_bitmap) {
             // Only 1 leaf child under this subtree or reach the lowest level
             let only_child_index = Nibble::from(range_existence_bitmap.trailing_zeros() as u8);
-            self.child(only_child_index)
+            children
+                .get(only_child_index)
+                .as_ref()
                 .with_context(|| {
                     format!(
                         "Corrupted internal node: existence_bitmap indicates \
@@ -589,12 +702,14 @@ impl InternalNode {
                 .unwrap()
                 .hash
         } else {
-            let left_child = self.merkle_hash::<H>(
+            let left_child = Self::merkle_hash::<H>(
+                children,
                 start,
                 width / 2,
                 (range_existence_bitmap, range_leaf_bitmap),
             );
-            let right_child = self.merkle_hash::<H>(
+            let right_child = Self::merkle_hash::<H>(
+                children,
                 start + width / 2,
                 width / 2,
                 (range_existence_bitmap, range_leaf_bitmap),
@@ -679,8 +794,8 @@ impl InternalNode {
         node_key: &NodeKey,
         n: Nibble,
         get_only_child: bool,
-    ) -> (Option<NodeKey>, Vec<SparseMerkleNode>) {
-        let mut siblings: Vec<SparseMerkleNode> = vec![];
+        siblings: &mut Vec<SparseMerkleNode>,
+    ) -> anyhow::Result<Option<NodeKey>> {
         let (existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
 
         let n_bitmap = 1 << n.as_usize();
@@ -698,14 +813,14 @@ impl InternalNode {
                 sibling_half_start,
                 width,
                 (existence_bitmap, leaf_bitmap),
-            ));
+            )?);
 
             let (range_existence_bitmap, range_leaf_bitmap) =
                 Self::range_bitmaps(child_half_start, width, (existence_bitmap, leaf_bitmap));
 
             if range_existence_bitmap == 0 {
                 // No child in this range.
-                return (None, siblings);
+                return Ok(None);
             } else if get_only_child
                 && (has_only_child(width, range_existence_bitmap, range_leaf_bitmap))
             {
@@ -714,47 +829,39 @@ impl InternalNode {
                 // `None` because it's existence indirectly proves the n-th child doesn't exist.
                 // Please read proof format for details.
                 let only_child_index = Nibble::from(range_existence_bitmap.trailing_zeros() as u8);
-                return (
-                    {
-                        let only_child_version = self
-                            .child(only_child_index)
-                            // Should be guaranteed by the self invariants, but these are not easy to express at the moment
-                            .with_context(|| {
-                                format!(
-                                    "Corrupted internal node: child_bitmap indicates \
-                                         the existence of a non-exist child at index {:x}",
-                                    only_child_index
-                                )
-                            })
-                            .unwrap()
-                            .version;
-                        Some(node_key.gen_child_node_key(only_child_version, only_child_index))
-                    },
-                    siblings,
-                );
+                let only_child_version = self
+                    .child(only_child_index)
+                    // Should be guaranteed by the self invariants, but these are not easy to express at the moment
+                    .with_context(|| {
+                        format!(
+                            "Corrupted internal node: child_bitmap indicates \
+                                 the existence of a non-exist child at index {:x}",
+                            only_child_index
+                        )
+                    })
+                    .unwrap()
+                    .version;
+                return Ok(Some(
+                    node_key.gen_child_node_key(only_child_version, only_child_index),
+                ));
             } else if !get_only_child
                 && (has_child(width, range_existence_bitmap, n_bitmap, range_leaf_bitmap))
             {
                 // Early return the child in that subtree iff it is the only child and the nibble points
                 // to it
-                return (
-                    {
-                        let only_child_version = self
-                            .child(n)
-                            // Should be guaranteed by the self invariants, but these are not easy to express at the moment
-                            .with_context(|| {
-                                format!(
-                                    "Corrupted internal node: child_bitmap indicates \
-                                         the existence of a non-exist child at index {:x}",
-                                    n
-                                )
-                            })
-                            .unwrap()
-                            .version;
-                        Some(node_key.gen_child_node_key(only_child_version, n))
-                    },
-                    siblings,
-                );
+                let only_child_version = self
+                    .child(n)
+                    // Should be guaranteed by the self invariants, but these are not easy to express at the moment
+                    .with_context(|| {
+                        format!(
+                            "Corrupted internal node: child_bitmap indicates \
+                                 the existence of a non-exist child at index {:x}",
+                            n
+                        )
+                    })
+                    .unwrap()
+                    .version;
+                return Ok(Some(node_key.gen_child_node_key(only_child_version, n)));
             }
         }
         unreachable!("Impossible to get here without returning even at the lowest level.")
@@ -767,8 +874,16 @@ impl InternalNode {
         tree_cache: &impl TreeReader,
         node_key: &NodeKey,
         n: Nibble,
-    ) -> (Option<NodeKey>, Vec<SparseMerkleNode>) {
-        self.get_child_with_siblings_helper::<H>(tree_cache, node_key, n, false)
+    ) -> anyhow::Result<(Option<NodeKey>, Vec<SparseMerkleNode>)> {
+        let mut siblings = vec![];
+        let child = self.get_child_with_siblings_helper::<H>(
+            tree_cache,
+            node_key,
+            n,
+            false,
+            &mut siblings,
+        )?;
+        Ok((child, siblings))
     }
 
     /// [`get_only_child_with_siblings`] will **either** return the child that matches the nibble n or the only
@@ -782,8 +897,64 @@ impl InternalNode {
         tree_reader: &impl TreeReader,
         node_key: &NodeKey,
         n: Nibble,
-    ) -> (Option<NodeKey>, Vec<SparseMerkleNode>) {
-        self.get_child_with_siblings_helper::<H>(tree_reader, node_key, n, true)
+    ) -> anyhow::Result<(Option<NodeKey>, Vec<SparseMerkleNode>)> {
+        let mut siblings = vec![];
+        let child =
+            self.append_only_child_siblings::<H>(tree_reader, node_key, n, &mut siblings)?;
+        Ok((child, siblings))
+    }
+
+    /// Same as [`get_only_child_with_siblings`], but appends the siblings to `siblings` instead
+    /// of allocating a new list.
+    pub(crate) fn append_only_child_siblings<H: SimpleHasher>(
+        &self,
+        tree_reader: &impl TreeReader,
+        node_key: &NodeKey,
+        n: Nibble,
+        siblings: &mut Vec<SparseMerkleNode>,
+    ) -> anyhow::Result<Option<NodeKey>> {
+        self.get_child_with_siblings_helper::<H>(tree_reader, node_key, n, true, siblings)
+    }
+
+    /// Same as [`get_only_child_with_siblings`], but only counts the siblings that are not
+    /// placeholders instead of building them, so no node is read and no hash is computed.
+    pub(crate) fn get_only_child_with_sibling_count(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+    ) -> (Option<NodeKey>, usize) {
+        let mut sibling_count = 0;
+        let (existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
+
+        // Nibble height from 3 to 0.
+        for h in (0..4).rev() {
+            let width = 1 << h;
+            let (child_half_start, sibling_half_start) = get_child_and_sibling_half_start(n, h);
+            let (sibling_existence_bitmap, _) =
+                Self::range_bitmaps(sibling_half_start, width, (existence_bitmap, leaf_bitmap));
+            if sibling_existence_bitmap != 0 {
+                sibling_count += 1;
+            }
+
+            let (range_existence_bitmap, range_leaf_bitmap) =
+                Self::range_bitmaps(child_half_start, width, (existence_bitmap, leaf_bitmap));
+
+            if range_existence_bitmap == 0 {
+                // No child in this range.
+                return (None, sibling_count);
+            } else if has_only_child(width, range_existence_bitmap, range_leaf_bitmap) {
+                let only_child_index = Nibble::from(range_existence_bitmap.trailing_zeros() as u8);
+                let only_child_version = self
+                    .child(only_child_index)
+                    .expect("Corrupted internal node: child_bitmap indicates a non-exist child.")
+                    .version;
+                return (
+                    Some(node_key.gen_child_node_key(only_child_version, only_child_index)),
+                    sibling_count,
+                );
+            }
+        }
+        unreachable!("Impossible to get here without returning even at the lowest level.")
     }
 
     #[cfg(test)]
@@ -818,6 +989,10 @@ pub(crate) fn get_child_half_start(n: Nibble, height: u8) -> u8 {
 /// Represents a key-value pair in the map.
 ///
 /// Note: this does not store the key itself.
+///
+/// A leaf only commits to the hash of its value, and its encoding is stable, see
+/// [`Node::to_bytes`]. Metadata that must be covered by the leaf hash, e.g. a creation timestamp,
+/// is to be encoded into the value itself.
 #[derive(
     Clone,
     Debug,
@@ -850,12 +1025,19 @@ impl LeafNode {
     }
 
     /// Gets the associated value hash.
-    pub(crate) fn value_hash(&self) -> ValueHash {
+    pub fn value_hash(&self) -> ValueHash {
         self.value_hash
     }
 
+    /// Computes the hash of this leaf node, see [`hash_from_parts`](Self::hash_from_parts).
     pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
-        SparseMerkleLeafNode::new(self.key_hash, self.value_hash).hash::<H>()
+        Self::hash_from_parts::<H>(self.key_hash, self.value_hash)
+    }
+
+    /// Computes the hash of the leaf node of `key_hash` and `value_hash`, without building it,
+    /// which is `H(b"JMT::LeafNode" || key_hash || value_hash)`.
+    pub fn hash_from_parts<H: SimpleHasher>(key_hash: KeyHash, value_hash: ValueHash) -> [u8; 32] {
+        SparseMerkleLeafNode::new(key_hash, value_hash).hash::<H>()
     }
 }
 
@@ -904,6 +1086,60 @@ impl From<LeafNode> for Node {
 }
 
 impl Node {
+    /// Encodes this node into bytes, suitable as a value in storage backends.
+    ///
+    /// The layout is the Borsh encoding of the node, and will not change within semver-compatible
+    /// releases:
+    ///
+    /// ```text
+    /// node        := 0x00                                     a null node
+    ///              | 0x01 child{16} num_children leaf_count   an internal node
+    ///              | 0x02 key_hash[32] value_hash[32]         a leaf node
+    /// child       := 0x00                                     no child at this nibble
+    ///              | 0x01 hash[32] version node_type
+    /// node_type   := 0x00                                     a leaf child
+    ///              | 0x01 leaf_count                          an internal child
+    /// version, num_children, leaf_count := u64, little endian
+    /// ```
+    ///
+    /// Children are in nibble order, and all hashes are written as their raw 32 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        borsh::to_vec(self).expect("serialization is infallible")
+    }
+
+    /// Decodes a node encoded with [`to_bytes`](Node::to_bytes). The whole of `bytes` must be a
+    /// single encoded node, and an internal node must be one that [`InternalNode::new`] would
+    /// build from its children.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let node: Self = borsh::from_slice(bytes)?;
+        if let Node::Internal(internal_node) = &node {
+            let children = &internal_node.children;
+            let num_children = children.children.iter().flatten().count();
+            anyhow::ensure!(
+                children.num_children == num_children,
+                "Internal node has {} children but claims {}.",
+                num_children,
+                children.num_children
+            );
+            anyhow::ensure!(num_children > 0, "Internal node has no children.");
+            anyhow::ensure!(
+                num_children > 1 || !children.values().any(Child::is_leaf),
+                "Internal node has a single child, which is a leaf."
+            );
+            let leaf_count = children
+                .values()
+                .try_fold(0usize, |sum, child| sum.checked_add(child.leaf_count()))
+                .ok_or_else(|| anyhow::format_err!("Internal node leaf count overflows."))?;
+            anyhow::ensure!(
+                internal_node.leaf_count == leaf_count,
+                "Internal node has {} leaves but claims {}.",
+                leaf_count,
+                internal_node.leaf_count
+            );
+        }
+        Ok(node)
+    }
+
     /// Creates the [`Null`](Node::Null) variant.
     pub(crate) fn new_null() -> Self {
         Node::Null
@@ -954,8 +1190,10 @@ impl Node {
         }
     }
 
-    /// Computes the hash of nodes.
-    pub(crate) fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+    /// Computes the hash of nodes: the placeholder hash `b"SPARSE_MERKLE_PLACEHOLDER_HASH__"`
+    /// for [`Null`](Node::Null), and the hash of the wrapped node otherwise, see
+    /// [`InternalNode::hash_from_children`] and [`LeafNode::hash_from_parts`].
+    pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
         match self {
             Node::Null => SPARSE_MERKLE_PLACEHOLDER_HASH,
             Node::Internal(internal_node) => internal_node.hash::<H>(),
@@ -1,17 +1,33 @@
 use alloc::vec::Vec;
-use anyhow::{format_err, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use crate::node_type::{LeafNode, Node, NodeKey};
-use crate::{KeyHash, OwnedValue, Version};
+use crate::storage::{NodeBatch, StaleNodeIndex};
+use crate::types::nibble::Nibble;
+use crate::{KeyHash, OwnedValue, ReadError, ValueTooLarge, Version};
+
+#[cfg(feature = "access_histogram")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::{
+    collections::HashSet,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
 
 /// Defines the interface between a
 /// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
 /// and underlying storage holding nodes.
 pub trait TreeReader {
     /// Gets node given a node key. Returns error if the node does not exist.
+    ///
+    /// The error carries a [`ReadError`] telling a failure of [`get_node_option`]
+    /// (`ReadError::Backend`) from a missing node (`ReadError::UnexpectedMissing`).
+    ///
+    /// [`get_node_option`]: TreeReader::get_node_option
     fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
-        self.get_node_option(node_key)?
-            .ok_or_else(|| format_err!("Missing node at {:?}.", node_key))
+        self.get_node_option(node_key)
+            .context(ReadError::Backend(node_key.clone()))?
+            .ok_or_else(|| anyhow!(ReadError::UnexpectedMissing(node_key.clone())))
     }
 
     /// Gets node given a node key. Returns `None` if the node does not exist.
@@ -19,12 +35,21 @@ pub trait TreeReader {
 
     /// Gets a value by identifier, returning the newest value whose version is *less than or
     /// equal to* the specified version. Returns an error if the value does not exist.
+    ///
+    /// The error carries a [`ReadError`] telling a failure of
+    /// [`get_value_option`](TreeReader::get_value_option) (`ReadError::BackendValue`) from a
+    /// missing value (`ReadError::UnexpectedMissingValue`).
     fn get_value(&self, max_version: Version, key_hash: KeyHash) -> Result<OwnedValue> {
-        self.get_value_option(max_version, key_hash)?
+        self.get_value_option(max_version, key_hash)
+            .context(ReadError::BackendValue {
+                max_version,
+                key_hash,
+            })?
             .ok_or_else(|| {
-                format_err!(
-                    "Missing value with max_version {max_version:} and key hash {key_hash:?}."
-                )
+                anyhow!(ReadError::UnexpectedMissingValue {
+                    max_version,
+                    key_hash
+                })
             })
     }
 
@@ -36,9 +61,152 @@ pub trait TreeReader {
         key_hash: KeyHash,
     ) -> Result<Option<OwnedValue>>;
 
+    /// Gets a value like [`get_value_option`](TreeReader::get_value_option), but fails with a
+    /// [`ValueTooLarge`] error if the value is larger than `max_bytes`, e.g. to serve untrusted
+    /// read requests without being made to load arbitrarily large values.
+    ///
+    /// The default implementation checks the size after reading the value. Storage that can tell
+    /// the size of a value without materializing it should override this to check it before.
+    fn get_value_option_limited(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+        max_bytes: usize,
+    ) -> Result<Option<OwnedValue>> {
+        let value = self.get_value_option(max_version, key_hash)?;
+        if let Some(value) = &value {
+            check_value_size(max_version, key_hash, value.len(), max_bytes)?;
+        }
+        Ok(value)
+    }
+
     /// Gets the rightmost leaf. Note that this assumes we are in the process of restoring the tree
     /// and all nodes are at the same version.
     fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>>;
+
+    /// Gets the distinct versions since which some node is stale, in ascending order, e.g. to
+    /// prune stale nodes one version at a time with
+    /// [`get_stale_nodes_since`](TreeReader::get_stale_nodes_since).
+    ///
+    /// Storage that does not index the [`StaleNodeIndex`]es it is written does not need to
+    /// implement this, and fails by default.
+    fn stale_versions(&self) -> Result<Vec<Version>> {
+        bail!("This tree reader does not index stale nodes.")
+    }
+
+    /// Gets the [`StaleNodeIndex`]es of the nodes stale since exactly `stale_since_version`.
+    ///
+    /// Storage that does not index the [`StaleNodeIndex`]es it is written does not need to
+    /// implement this, and fails by default.
+    fn get_stale_nodes_since(&self, stale_since_version: Version) -> Result<Vec<StaleNodeIndex>> {
+        let _ = stale_since_version;
+        bail!("This tree reader does not index stale nodes.")
+    }
+
+    /// Gets the keys of all the nodes in storage, in any order. This is a full scan, only meant
+    /// for offline audits such as
+    /// [`JellyfishMerkleTree::find_orphans`](crate::JellyfishMerkleTree::find_orphans).
+    ///
+    /// Storage that cannot enumerate its nodes does not need to implement this, and fails by
+    /// default.
+    fn get_node_keys(&self) -> Result<Vec<NodeKey>> {
+        bail!("This tree reader cannot enumerate its nodes.")
+    }
+
+    /// Gets the latest version whose root is in storage, or `None` if no version was committed.
+    /// The root put at the pre-genesis version by a restoration is not a committed version, and
+    /// must be ignored.
+    ///
+    /// Storage that does not keep track of its latest version does not need to implement this,
+    /// and fails by default.
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        bail!("This tree reader does not keep track of its latest version.")
+    }
+
+    /// Gets the number of versions committed so far, i.e. one more than the
+    /// [latest version](TreeReader::get_latest_version), whether or not earlier versions were
+    /// pruned.
+    ///
+    /// The default implementation derives it from
+    /// [`get_latest_version`](TreeReader::get_latest_version), and fails if that does.
+    fn num_versions(&self) -> Result<u64> {
+        Ok(self.get_latest_version()?.map_or(0, |version| version + 1))
+    }
+}
+
+/// Reads through a shared reference, forwarding every method so that the ones overridden by `R`
+/// are used.
+impl<R: TreeReader + ?Sized> TreeReader for &R {
+    fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
+        (**self).get_node(node_key)
+    }
+
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        (**self).get_node_option(node_key)
+    }
+
+    fn get_value(&self, max_version: Version, key_hash: KeyHash) -> Result<OwnedValue> {
+        (**self).get_value(max_version, key_hash)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        (**self).get_value_option(max_version, key_hash)
+    }
+
+    fn get_value_option_limited(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+        max_bytes: usize,
+    ) -> Result<Option<OwnedValue>> {
+        (**self).get_value_option_limited(max_version, key_hash, max_bytes)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        (**self).get_rightmost_leaf()
+    }
+
+    fn stale_versions(&self) -> Result<Vec<Version>> {
+        (**self).stale_versions()
+    }
+
+    fn get_stale_nodes_since(&self, stale_since_version: Version) -> Result<Vec<StaleNodeIndex>> {
+        (**self).get_stale_nodes_since(stale_since_version)
+    }
+
+    fn get_node_keys(&self) -> Result<Vec<NodeKey>> {
+        (**self).get_node_keys()
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        (**self).get_latest_version()
+    }
+
+    fn num_versions(&self) -> Result<u64> {
+        (**self).num_versions()
+    }
+}
+
+/// Fails with a [`ValueTooLarge`] error if a value of `size` bytes is larger than `max_bytes`.
+pub(crate) fn check_value_size(
+    max_version: Version,
+    key_hash: KeyHash,
+    size: usize,
+    max_bytes: usize,
+) -> Result<()> {
+    if size > max_bytes {
+        return Err(anyhow!(ValueTooLarge {
+            key_hash,
+            max_version,
+            size,
+            max_bytes,
+        }));
+    }
+    Ok(())
 }
 
 /// Defines the ability for a tree to look up the preimage of its key hashes.
@@ -46,3 +214,393 @@ pub trait HasPreimage {
     /// Gets the preimage of a key hash, if it is present in the tree.
     fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>>;
 }
+
+/// A [`TreeReader`] reading from `base` as if the not yet committed `overlay` were applied to it,
+/// e.g. to generate proofs against a proposed state without writing it to storage.
+///
+/// The overlay is expected to hold the newest data: a value found in the overlay shadows any
+/// value of the same key in `base`.
+pub struct OverlayReader<'a, R> {
+    /// The underlying storage.
+    pub base: &'a R,
+    /// The nodes and values looked up before `base`.
+    pub overlay: &'a NodeBatch,
+}
+
+impl<'a, R> OverlayReader<'a, R> {
+    /// Creates an `OverlayReader` applying `overlay` over `base`.
+    pub fn new(base: &'a R, overlay: &'a NodeBatch) -> Self {
+        Self { base, overlay }
+    }
+}
+
+impl<'a, R: TreeReader> TreeReader for OverlayReader<'a, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.overlay.get_node(node_key) {
+            Some(node) => Ok(Some(node.clone())),
+            None => self.base.get_node_option(node_key),
+        }
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        match self.overlay.get_value(max_version, key_hash) {
+            Some(value) => Ok(value.clone()),
+            None => self.base.get_value_option(max_version, key_hash),
+        }
+    }
+
+    fn get_value_option_limited(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+        max_bytes: usize,
+    ) -> Result<Option<OwnedValue>> {
+        match self.overlay.get_value(max_version, key_hash) {
+            Some(value) => {
+                if let Some(value) = value {
+                    check_value_size(max_version, key_hash, value.len(), max_bytes)?;
+                }
+                Ok(value.clone())
+            }
+            None => self
+                .base
+                .get_value_option_limited(max_version, key_hash, max_bytes),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        Ok(self
+            .overlay
+            .rightmost_leaf()
+            .into_iter()
+            .chain(self.base.get_rightmost_leaf()?)
+            .max_by_key(|(_, leaf_node)| leaf_node.key_hash()))
+    }
+
+    fn stale_versions(&self) -> Result<Vec<Version>> {
+        self.base.stale_versions()
+    }
+
+    fn get_stale_nodes_since(&self, stale_since_version: Version) -> Result<Vec<StaleNodeIndex>> {
+        self.base.get_stale_nodes_since(stale_since_version)
+    }
+
+    fn get_node_keys(&self) -> Result<Vec<NodeKey>> {
+        let mut node_keys = self.base.get_node_keys()?;
+        node_keys.extend(self.overlay.nodes().keys().cloned());
+        node_keys.sort_unstable();
+        node_keys.dedup();
+        Ok(node_keys)
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        Ok(self
+            .base
+            .get_latest_version()?
+            .max(self.overlay.latest_version()))
+    }
+}
+
+/// A [`TreeReader`] over storage partitioned into `N` shards by the first nibble of the node keys,
+/// each shard being a [`TreeReader`] of its own.
+///
+/// The 16 possible first nibbles are split into `N` contiguous ranges, the `i`-th shard holding the
+/// `i`-th range, so that shards are ordered by key. Nodes with an empty nibble path, i.e. roots, are
+/// held by the designated `root_shard`. Values are held by the shard of the first nibble of their
+/// key hash. Writes must be routed the same way, see [`node_shard`](ShardedTreeReader::node_shard)
+/// and [`value_shard`](ShardedTreeReader::value_shard).
+pub struct ShardedTreeReader<const N: usize, R> {
+    /// The shards, in key order.
+    pub shards: [R; N],
+    /// The index of the shard holding the roots.
+    pub root_shard: usize,
+}
+
+impl<const N: usize, R> ShardedTreeReader<N, R> {
+    /// Creates a `ShardedTreeReader` over `shards`, the roots being held by the shard at
+    /// `root_shard`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is 0 or greater than 16, or if `root_shard` is not less than `N`.
+    pub fn new(shards: [R; N], root_shard: usize) -> Self {
+        assert!(
+            0 < N && N <= 16,
+            "Number of shards must be between 1 and 16, got {}.",
+            N
+        );
+        assert!(
+            root_shard < N,
+            "Root shard {} is out of {} shards.",
+            root_shard,
+            N
+        );
+        Self { shards, root_shard }
+    }
+
+    /// Returns the index of the shard holding the node at `node_key`.
+    pub fn node_shard(&self, node_key: &NodeKey) -> usize {
+        if node_key.nibble_path().is_empty() {
+            self.root_shard
+        } else {
+            Self::nibble_shard(node_key.nibble_path().get_nibble(0))
+        }
+    }
+
+    /// Returns the index of the shard holding the values of `key_hash`.
+    pub fn value_shard(&self, key_hash: KeyHash) -> usize {
+        Self::nibble_shard(Nibble::from(key_hash.0[0] >> 4))
+    }
+
+    fn nibble_shard(nibble: Nibble) -> usize {
+        usize::from(u8::from(nibble)) * N / 16
+    }
+}
+
+impl<const N: usize, R: TreeReader> TreeReader for ShardedTreeReader<N, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.shards[self.node_shard(node_key)].get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.shards[self.value_shard(key_hash)].get_value_option(max_version, key_hash)
+    }
+
+    fn get_value_option_limited(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+        max_bytes: usize,
+    ) -> Result<Option<OwnedValue>> {
+        self.shards[self.value_shard(key_hash)].get_value_option_limited(
+            max_version,
+            key_hash,
+            max_bytes,
+        )
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        // The root shard may hold a leaf at the root, so every shard is asked.
+        let mut rightmost_leaves = Vec::new();
+        for shard in &self.shards {
+            rightmost_leaves.extend(shard.get_rightmost_leaf()?);
+        }
+        Ok(rightmost_leaves
+            .into_iter()
+            .max_by_key(|(_, leaf_node)| leaf_node.key_hash()))
+    }
+
+    fn stale_versions(&self) -> Result<Vec<Version>> {
+        let mut stale_versions = Vec::new();
+        for shard in &self.shards {
+            stale_versions.extend(shard.stale_versions()?);
+        }
+        stale_versions.sort_unstable();
+        stale_versions.dedup();
+        Ok(stale_versions)
+    }
+
+    fn get_stale_nodes_since(&self, stale_since_version: Version) -> Result<Vec<StaleNodeIndex>> {
+        let mut stale_nodes = Vec::new();
+        for shard in &self.shards {
+            stale_nodes.extend(shard.get_stale_nodes_since(stale_since_version)?);
+        }
+        stale_nodes.sort_unstable();
+        Ok(stale_nodes)
+    }
+
+    fn get_node_keys(&self) -> Result<Vec<NodeKey>> {
+        let mut node_keys = Vec::new();
+        for shard in &self.shards {
+            node_keys.extend(shard.get_node_keys()?);
+        }
+        Ok(node_keys)
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        self.shards[self.root_shard].get_latest_version()
+    }
+
+    fn num_versions(&self) -> Result<u64> {
+        self.shards[self.root_shard].num_versions()
+    }
+}
+
+/// A [`TreeReader`] counting how many times each node is read from `base`, e.g. to find the nodes
+/// worth keeping in a cache. Only available with the `access_histogram` feature, so that other
+/// builds do not pay for the bookkeeping.
+#[cfg(feature = "access_histogram")]
+pub struct AccessCountingReader<'a, R> {
+    /// The underlying storage.
+    pub base: &'a R,
+    accesses: Mutex<HashMap<NodeKey, u64>>,
+}
+
+#[cfg(feature = "access_histogram")]
+impl<'a, R> AccessCountingReader<'a, R> {
+    /// Creates an `AccessCountingReader` over `base`, with no access recorded yet.
+    pub fn new(base: &'a R) -> Self {
+        Self {
+            base,
+            accesses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the number of reads of each node key since the reader was created or last reset,
+    /// including reads of nodes missing from `base`.
+    pub fn access_histogram(&self) -> HashMap<NodeKey, u64> {
+        self.lock().clone()
+    }
+
+    /// Forgets every recorded access.
+    pub fn reset_access_histogram(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<NodeKey, u64>> {
+        // Counts are updated atomically, so they are still usable after a panic.
+        self.accesses.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "access_histogram")]
+impl<'a, R: TreeReader> TreeReader for AccessCountingReader<'a, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        *self.lock().entry(node_key.clone()).or_insert(0) += 1;
+        self.base.get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.base.get_value_option(max_version, key_hash)
+    }
+
+    fn get_value_option_limited(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+        max_bytes: usize,
+    ) -> Result<Option<OwnedValue>> {
+        self.base
+            .get_value_option_limited(max_version, key_hash, max_bytes)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.base.get_rightmost_leaf()
+    }
+
+    fn stale_versions(&self) -> Result<Vec<Version>> {
+        self.base.stale_versions()
+    }
+
+    fn get_stale_nodes_since(&self, stale_since_version: Version) -> Result<Vec<StaleNodeIndex>> {
+        self.base.get_stale_nodes_since(stale_since_version)
+    }
+
+    fn get_node_keys(&self) -> Result<Vec<NodeKey>> {
+        self.base.get_node_keys()
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        self.base.get_latest_version()
+    }
+
+    fn num_versions(&self) -> Result<u64> {
+        self.base.num_versions()
+    }
+}
+
+/// A [`TreeReader`] recording the key of every node read from `base`, e.g. to build a proof
+/// covering exactly the nodes a transaction read, with
+/// [`JellyfishMerkleTree::get_multiproof`](crate::JellyfishMerkleTree::get_multiproof).
+#[cfg(feature = "std")]
+pub struct RecordingReader<'a, R> {
+    /// The underlying storage.
+    pub base: &'a R,
+    read_set: Mutex<HashSet<NodeKey>>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, R> RecordingReader<'a, R> {
+    /// Creates a `RecordingReader` over `base`, with no read recorded yet.
+    pub fn new(base: &'a R) -> Self {
+        Self {
+            base,
+            read_set: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Returns the keys of the nodes read through this reader, including the nodes missing from
+    /// `base`.
+    pub fn into_read_set(self) -> HashSet<NodeKey> {
+        self.read_set
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashSet<NodeKey>> {
+        // Keys are inserted atomically, so the set is still usable after a panic.
+        self.read_set.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, R: TreeReader> TreeReader for RecordingReader<'a, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.lock().insert(node_key.clone());
+        self.base.get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.base.get_value_option(max_version, key_hash)
+    }
+
+    fn get_value_option_limited(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+        max_bytes: usize,
+    ) -> Result<Option<OwnedValue>> {
+        self.base
+            .get_value_option_limited(max_version, key_hash, max_bytes)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.base.get_rightmost_leaf()
+    }
+
+    fn stale_versions(&self) -> Result<Vec<Version>> {
+        self.base.stale_versions()
+    }
+
+    fn get_stale_nodes_since(&self, stale_since_version: Version) -> Result<Vec<StaleNodeIndex>> {
+        self.base.get_stale_nodes_since(stale_since_version)
+    }
+
+    fn get_node_keys(&self) -> Result<Vec<NodeKey>> {
+        self.base.get_node_keys()
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        self.base.get_latest_version()
+    }
+
+    fn num_versions(&self) -> Result<u64> {
+        self.base.num_versions()
+    }
+}
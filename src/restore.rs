@@ -211,6 +211,39 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
         })
     }
 
+    /// Builds the tree at `version` from `entries` in a single pass, and returns its root hash.
+    /// The entries must be sorted by strictly increasing key hash, which is checked as they come.
+    ///
+    /// Only the rightmost path of the tree is kept in memory: each value is handed to `store` as
+    /// it comes, and the nodes of each subtree as soon as the subtree is complete, so that data
+    /// sets larger than memory can be imported. The tree is identical to the one built from the
+    /// same entries with
+    /// [`JellyfishMerkleTree::put_value_set`](crate::JellyfishMerkleTree::put_value_set).
+    pub fn stream_build_sorted<D: 'static + TreeWriter>(
+        store: Arc<D>,
+        version: Version,
+        entries: impl IntoIterator<Item = (KeyHash, OwnedValue)>,
+    ) -> Result<RootHash> {
+        // The expected root hash is only used to verify chunks, which are not verified here.
+        let mut restore =
+            Self::new_overwrite(store, version, RootHash(SPARSE_MERKLE_PLACEHOLDER_HASH))?;
+        for entry in entries {
+            restore.add_leaves(vec![entry])?;
+            if !restore.frozen_nodes.is_empty() {
+                restore.store.write_node_batch(&restore.frozen_nodes)?;
+                restore.frozen_nodes.clear();
+            }
+        }
+
+        if restore.num_keys_received == 0 {
+            let mut frozen_nodes = NodeBatch::default();
+            frozen_nodes.insert_node(NodeKey::new_empty_path(version), Node::Null);
+            restore.store.write_node_batch(&frozen_nodes)?;
+            return Ok(RootHash(SPARSE_MERKLE_PLACEHOLDER_HASH));
+        }
+        restore.finish_impl()
+    }
+
     /// Recovers partial nodes from storage. We do this by looking at all the ancestors of the
     /// rightmost leaf. The ones do not exist in storage are the partial nodes.
     fn recover_partial_nodes(
@@ -293,6 +326,41 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
         chunk: Vec<(KeyHash, OwnedValue)>,
         proof: SparseMerkleRangeProof<H>,
     ) -> Result<()> {
+        self.add_leaves(chunk)?;
+
+        // Verify what we have added so far is all correct.
+        self.verify(proof)?;
+
+        // Write the frozen nodes to storage.
+        self.store.write_node_batch(&self.frozen_nodes)?;
+        self.frozen_nodes.clear();
+
+        Ok(())
+    }
+
+    #[cfg(feature = "migration")]
+    /// Restores a chunk of accounts **without verifying it** against a range proof, then writes
+    /// things to storage.
+    ///
+    /// # Warning
+    /// This is only safe when the chunk comes from a trusted source, e.g. during a local
+    /// migration. Nothing checks that the chunk belongs to the tree with the expected root hash:
+    /// an incorrect chunk is restored as is and the resulting tree silently differs from the
+    /// expected one. For the same leaves, the restored tree is identical to the one built through
+    /// [`StateSnapshotReceiver::add_chunk`].
+    pub fn add_chunk_unverified(&mut self, chunk: Vec<(KeyHash, OwnedValue)>) -> Result<()> {
+        self.add_leaves(chunk)?;
+
+        // Write the frozen nodes to storage.
+        self.store.write_node_batch(&self.frozen_nodes)?;
+        self.frozen_nodes.clear();
+
+        Ok(())
+    }
+
+    /// Adds a chunk of accounts, which must come in increasing order of keys, to the partial
+    /// nodes.
+    fn add_leaves(&mut self, chunk: Vec<(KeyHash, OwnedValue)>) -> Result<()> {
         ensure!(!chunk.is_empty(), "Should not add empty chunks.");
 
         for (key, value) in chunk {
@@ -310,13 +378,6 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
             self.num_keys_received += 1;
         }
 
-        // Verify what we have added so far is all correct.
-        self.verify(proof)?;
-
-        // Write the frozen nodes to storage.
-        self.store.write_node_batch(&self.frozen_nodes)?;
-        self.frozen_nodes.clear();
-
         Ok(())
     }
 
@@ -647,8 +708,11 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
     }
 
     /// Finishes the restoration process. This tells the code that there is no more account,
-    /// otherwise we can not freeze the rightmost leaf and its ancestors.
-    fn finish_impl(mut self) -> Result<()> {
+    /// otherwise we can not freeze the rightmost leaf and its ancestors. Returns the root hash of
+    /// the restored tree.
+    fn finish_impl(mut self) -> Result<RootHash> {
+        let root_node_key = NodeKey::new_empty_path(self.version);
+
         // Deal with the special case when the entire tree has a single leaf.
         if self.partial_nodes.len() == 1 {
             let mut num_children = 0;
@@ -664,17 +728,24 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
 
             if num_children == 1 {
                 if let Some(node) = leaf {
-                    let node_key = NodeKey::new_empty_path(self.version);
+                    let root_hash = RootHash(node.hash::<H>());
                     assert!(self.frozen_nodes.is_empty());
-                    self.frozen_nodes.insert_node(node_key, node.into());
+                    self.frozen_nodes.insert_node(root_node_key, node.into());
                     self.store.write_node_batch(&self.frozen_nodes)?;
-                    return Ok(());
+                    return Ok(root_hash);
                 }
             }
         }
 
         self.freeze(0);
-        self.store.write_node_batch(&self.frozen_nodes)
+        let root_hash = RootHash(
+            self.frozen_nodes
+                .get_node(&root_node_key)
+                .expect("The root node must have been frozen.")
+                .hash::<H>(),
+        );
+        self.store.write_node_batch(&self.frozen_nodes)?;
+        Ok(root_hash)
     }
 }
 
@@ -701,10 +772,10 @@ impl<H: SimpleHasher> StateSnapshotReceiver<H> for JellyfishMerkleRestore<H> {
     }
 
     fn finish(self) -> Result<()> {
-        self.finish_impl()
+        self.finish_impl().map(|_| ())
     }
 
     fn finish_box(self: Box<Self>) -> Result<()> {
-        self.finish_impl()
+        self.finish_impl().map(|_| ())
     }
 }
@@ -1,8 +1,11 @@
 #![cfg(test)]
+#[cfg(feature = "std")]
+mod checkpoint;
 mod compute_vectors;
 mod helper;
 mod iterator;
 mod jellyfish_merkle;
+mod map;
 mod nibble_path;
 mod node_type;
 mod restore;
@@ -0,0 +1,142 @@
+use alloc::{vec, vec::Vec};
+
+use sha2::Sha256;
+
+use crate::{
+    checkpoint::import_checkpoint, mock::MockTreeStore, tests::helper::init_mock_db,
+    JellyfishMerkleTree, KeyHash, NamedHasher, OwnedValue,
+};
+
+#[test]
+fn test_checkpoint_roundtrip() {
+    let kvs: Vec<(KeyHash, OwnedValue)> = (0..500u32)
+        .map(|i| {
+            (
+                KeyHash::with::<Sha256>(i.to_be_bytes()),
+                i.to_le_bytes().to_vec(),
+            )
+        })
+        .collect();
+    let (db, version) = init_mock_db::<Sha256>(&kvs.iter().cloned().collect());
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let root_hash = tree.get_root_hash(version).unwrap();
+
+    let mut checkpoint = Vec::new();
+    tree.export_checkpoint(version, &mut checkpoint).unwrap();
+
+    let imported_db = MockTreeStore::default();
+    assert_eq!(
+        import_checkpoint::<Sha256, _>(checkpoint.as_slice(), &imported_db).unwrap(),
+        (version, root_hash)
+    );
+    let imported_tree = JellyfishMerkleTree::<_, Sha256>::new(&imported_db);
+    assert_eq!(imported_tree.get_root_hash(version).unwrap(), root_hash);
+    for (key, value) in &kvs {
+        let (imported_value, proof) = imported_tree.get_with_proof(*key, version).unwrap();
+        assert_eq!(imported_value.as_ref(), Some(value));
+        proof.verify_existence(root_hash, *key, value).unwrap();
+    }
+}
+
+#[test]
+fn test_checkpoint_empty_tree() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    assert!(tree.export_checkpoint(0, Vec::new()).is_err());
+
+    let (root_hash, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let mut checkpoint = Vec::new();
+    tree.export_checkpoint(0, &mut checkpoint).unwrap();
+
+    let imported_db = MockTreeStore::default();
+    import_checkpoint::<Sha256, _>(checkpoint.as_slice(), &imported_db).unwrap();
+    let imported_tree = JellyfishMerkleTree::<_, Sha256>::new(&imported_db);
+    assert_eq!(imported_tree.get_root_hash(0).unwrap(), root_hash);
+}
+
+#[test]
+fn test_checkpoint_rejects_invalid_input() {
+    let kvs: Vec<(KeyHash, OwnedValue)> = (0..20u32)
+        .map(|i| (KeyHash::with::<Sha256>(i.to_be_bytes()), vec![i as u8]))
+        .collect();
+    let (db, version) = init_mock_db::<Sha256>(&kvs.into_iter().collect());
+    let mut checkpoint = Vec::new();
+    JellyfishMerkleTree::<_, Sha256>::new(&db)
+        .export_checkpoint(version, &mut checkpoint)
+        .unwrap();
+
+    // Another hasher.
+    let err =
+        import_checkpoint::<sha2::Sha512_256, _>(checkpoint.as_slice(), &MockTreeStore::default())
+            .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Checkpoint was created with the hasher sha256, not sha512_256."
+    );
+    // Truncated.
+    assert!(import_checkpoint::<Sha256, _>(
+        &checkpoint[..checkpoint.len() - 1],
+        &MockTreeStore::default()
+    )
+    .is_err());
+    // Any altered byte after the magic bytes.
+    for i in (8..checkpoint.len()).step_by(7) {
+        let mut altered = checkpoint.clone();
+        altered[i] ^= 1;
+        let imported_db = MockTreeStore::default();
+        assert!(import_checkpoint::<Sha256, _>(altered.as_slice(), &imported_db).is_err());
+        assert_eq!(imported_db.num_nodes(), 0);
+    }
+}
+
+#[test]
+fn test_checkpoint_header_names_hasher() {
+    let kvs: Vec<(KeyHash, OwnedValue)> = (0..20u32)
+        .map(|i| (KeyHash::with::<Sha256>(i.to_be_bytes()), vec![i as u8]))
+        .collect();
+    let (db, version) = init_mock_db::<Sha256>(&kvs.into_iter().collect());
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let mut checkpoint = Vec::new();
+    tree.export_checkpoint(version, &mut checkpoint).unwrap();
+
+    assert_eq!(&checkpoint[..8], b"JMTCKPT\x03");
+    assert_eq!(Sha256::NAME, "sha256");
+    assert_eq!(checkpoint[16], 6);
+    assert_eq!(&checkpoint[17..23], b"sha256");
+    let root_hash = tree.get_root_hash(version).unwrap();
+
+    // The header of format version 2 also holds the length of the digests, which must be 32.
+    let checkpoint_v2 = |output_len: u8| {
+        let mut checkpoint_v2 = b"JMTCKPT\x02".to_vec();
+        checkpoint_v2.extend_from_slice(&checkpoint[8..23]);
+        checkpoint_v2.push(output_len);
+        checkpoint_v2.extend_from_slice(&checkpoint[23..]);
+        checkpoint_v2
+    };
+    assert_eq!(
+        import_checkpoint::<Sha256, _>(checkpoint_v2(32).as_slice(), &MockTreeStore::default())
+            .unwrap(),
+        (version, root_hash)
+    );
+    assert!(import_checkpoint::<Sha256, _>(
+        checkpoint_v2(64).as_slice(),
+        &MockTreeStore::default()
+    )
+    .is_err());
+
+    // The header of format version 1 does not name the hasher.
+    let mut checkpoint_v1 = b"JMTCKPT\x01".to_vec();
+    checkpoint_v1.extend_from_slice(&checkpoint[8..16]);
+    checkpoint_v1.extend_from_slice(&checkpoint[23..]);
+    assert_eq!(
+        import_checkpoint::<Sha256, _>(checkpoint_v1.as_slice(), &MockTreeStore::default())
+            .unwrap(),
+        (version, root_hash)
+    );
+    assert!(import_checkpoint::<sha2::Sha512_256, _>(
+        checkpoint_v1.as_slice(),
+        &MockTreeStore::default()
+    )
+    .is_err());
+}
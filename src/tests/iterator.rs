@@ -11,7 +11,7 @@ use sha2::Sha256;
 use super::helper::plus_one;
 use crate::{
     iterator::JellyfishMerkleIterator, mock::MockTreeStore, types::Version, KeyHash, OwnedValue,
-    Sha256Jmt,
+    Sha256Jmt, ValueHash,
 };
 
 #[test]
@@ -31,6 +31,67 @@ fn test_long_path() {
     test_n_consecutive_addresses(50);
 }
 
+#[test]
+fn test_starting_key_between_leaves() {
+    let db = Arc::new(MockTreeStore::default());
+    let tree = Sha256Jmt::new(&*db);
+    let keys: Vec<KeyHash> = [0x10u8, 0x20, 0x21, 0x30]
+        .iter()
+        .map(|byte| KeyHash([*byte; 32]))
+        .collect();
+    let (_root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![key.0[0]]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let keys_from = |version: Version, starting_key: KeyHash| -> Vec<KeyHash> {
+        JellyfishMerkleIterator::new(Arc::clone(&db), version, starting_key)
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect()
+    };
+    // Between two leaves under different children of the root, and of the same internal node.
+    assert_eq!(keys_from(0, KeyHash([0x18; 32])), keys[1..]);
+    assert_eq!(keys_from(0, plus_one(keys[1])), keys[2..]);
+    assert_eq!(keys_from(0, KeyHash([0x31; 32])), vec![]);
+
+    // A cursor deleted between two pages resumes at the next key.
+    let (_root_hash, batch) = tree.put_value_set(vec![(keys[1], None)], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(keys_from(1, keys[1]), keys[2..]);
+    assert_eq!(keys_from(0, keys[1]), keys[1..]);
+}
+
+#[test]
+fn test_chunks() {
+    let db = Arc::new(MockTreeStore::default());
+    let tree = Sha256Jmt::new(&*db);
+    let btree: BTreeMap<KeyHash, OwnedValue> = (0..23u32)
+        .map(|i| {
+            (
+                KeyHash::with::<Sha256>(i.to_be_bytes()),
+                i.to_be_bytes().to_vec(),
+            )
+        })
+        .collect();
+    let (_root_hash, batch) = tree
+        .put_value_set(btree.iter().map(|(k, v)| (*k, Some(v.clone()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let chunks: Vec<Vec<(KeyHash, OwnedValue)>> =
+        JellyfishMerkleIterator::new(Arc::clone(&db), 0, KeyHash([0; 32]))
+            .unwrap()
+            .chunks(5)
+            .collect::<Result<_>>()
+            .unwrap();
+    assert_eq!(
+        chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![5, 5, 5, 5, 3]
+    );
+    assert!(chunks.into_iter().flatten().eq(btree.into_iter()));
+}
+
 fn test_n_leaves_same_version(n: usize) {
     let db = Arc::new(MockTreeStore::default());
     let tree = Sha256Jmt::new(&*db);
@@ -107,6 +168,28 @@ fn run_tests(db: Arc<MockTreeStore>, btree: &BTreeMap<KeyHash, OwnedValue>, vers
         );
     }
 
+    {
+        let iter = JellyfishMerkleIterator::hashes_only(Arc::clone(&db), version).unwrap();
+        assert_eq!(
+            iter.collect::<Result<Vec<_>>>().unwrap(),
+            btree
+                .iter()
+                .map(|(key, value)| (*key, ValueHash::with::<Sha256>(value)))
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    {
+        let tree = Sha256Jmt::new(&*db);
+        assert_eq!(
+            tree.key_set(version)
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            btree.keys().copied().collect::<Vec<_>>(),
+        );
+    }
+
     for i in 0..btree.len() {
         {
             let iter = JellyfishMerkleIterator::new_by_index(Arc::clone(&db), version, i).unwrap();
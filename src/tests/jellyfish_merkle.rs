@@ -1,17 +1,26 @@
 // Copyright (c) The Diem Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use alloc::{format, vec};
+use core::cell::Cell;
+use core::sync::atomic::AtomicBool;
 
 use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::SimpleHasher;
 use crate::{
-    mock::MockTreeStore,
-    node_type::{Child, Children, Node, NodeKey, NodeType},
-    storage::{TreeReader, TreeUpdateBatch},
+    mock::{MockTreeStore, Op, TreeDriver},
+    node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey, NodeType},
+    proof::{
+        SparseMerkleInternalNode, SparseMerkleMultiproof, SparseMerkleNode, SparseMerkleProof,
+        UnchangedProof, UpdateMerkleProof,
+    },
+    storage::{
+        BatchError, CommittedRoot, NodeBatch, OverlayReader, ShardedTreeReader, StaleNodeIndex,
+        TreeReader, TreeTruncator, TreeUpdateBatch, TreeWriter, TruncationBatch,
+    },
     tests::helper::{
         arb_existent_kvs_and_deletions_and_nonexistent_keys, arb_existent_kvs_and_nonexistent_keys,
         arb_interleaved_insertions_and_deletions, arb_kv_pair_with_distinct_last_nibble,
@@ -22,10 +31,13 @@ use crate::{
     },
     types::{
         nibble::{nibble_path::NibblePath, Nibble},
-        Version,
+        Version, PRE_GENESIS_VERSION,
     },
-    JellyfishMerkleTree, KeyHash, MissingRootError, SPARSE_MERKLE_PLACEHOLDER_HASH,
+    Direction, GetProofOutcome, JellyfishMerkleTree, KeyHash, MissingRootError, OwnedValue,
+    ProofBuffer, ProofCache, ProofTooDeep, ReadError, RootHash, RootHashCache, RootRepair,
+    StorageSize, ValueHash, ValueTooLarge, SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
+use crate::{Bytes32Ext, SimpleHasher};
 
 fn update_nibble(original_key: &KeyHash, n: usize, nibble: u8) -> KeyHash {
     assert!(nibble < 16);
@@ -57,6 +69,72 @@ macro_rules! impl_jellyfish_tests_for_hasher {
             instantiate_test_for_hasher!(test_1000_versions, $hasher);
             instantiate_test_for_hasher!(test_delete_then_get_in_one, $hasher);
             instantiate_test_for_hasher!(test_two_gets_then_delete, $hasher);
+            instantiate_test_for_hasher!(test_truncate_then_reapply, $hasher);
+            instantiate_test_for_hasher!(test_tree_driver_matches_put_value_sets, $hasher);
+            instantiate_test_for_hasher!(test_reconstruct_root, $hasher);
+            instantiate_test_for_hasher!(test_validate_update_batch, $hasher);
+            instantiate_test_for_hasher!(test_delete_range_with_proof, $hasher);
+            instantiate_test_for_hasher!(test_get_with_proof_with_overlay, $hasher);
+            instantiate_test_for_hasher!(test_key_history, $hasher);
+            instantiate_test_for_hasher!(test_get_value_hash_proof, $hasher);
+            instantiate_test_for_hasher!(test_get_unchanged_proof, $hasher);
+            instantiate_test_for_hasher!(test_write_batch_twice, $hasher);
+            instantiate_test_for_hasher!(test_verify_rejects_wrong_leaf, $hasher);
+            instantiate_test_for_hasher!(test_get_with_proof_pre_genesis, $hasher);
+            instantiate_test_for_hasher!(test_proof_depth, $hasher);
+            instantiate_test_for_hasher!(test_subtree_root_hash, $hasher);
+            instantiate_test_for_hasher!(test_empty_value_is_not_deletion, $hasher);
+            instantiate_test_for_hasher!(test_overlay_reader, $hasher);
+            instantiate_test_for_hasher!(test_node_batch_get_value, $hasher);
+            instantiate_test_for_hasher!(test_conflicting_leaf, $hasher);
+            instantiate_test_for_hasher!(test_read_errors, $hasher);
+            instantiate_test_for_hasher!(test_repair_root, $hasher);
+            instantiate_test_for_hasher!(test_value_hash_cache, $hasher);
+            instantiate_test_for_hasher!(test_proof_cache, $hasher);
+            instantiate_test_for_hasher!(test_root_hash_cache, $hasher);
+            instantiate_test_for_hasher!(test_put_value_set_with_previous, $hasher);
+            instantiate_test_for_hasher!(test_sharded_tree_reader, $hasher);
+            instantiate_test_for_hasher!(test_consistency_proof, $hasher);
+            instantiate_test_for_hasher!(test_retain, $hasher);
+            instantiate_test_for_hasher!(test_get_with_proof_max_depth, $hasher);
+            instantiate_test_for_hasher!(test_stale_nodes_by_version, $hasher);
+            instantiate_test_for_hasher!(test_get_with_proof_into, $hasher);
+            instantiate_test_for_hasher!(test_find_orphans, $hasher);
+            instantiate_test_for_hasher!(test_prunable_stats, $hasher);
+            instantiate_test_for_hasher!(test_write_update_batch_atomic, $hasher);
+            instantiate_test_for_hasher!(test_get_value_option_limited, $hasher);
+            instantiate_test_for_hasher!(test_build_membership_filter, $hasher);
+            instantiate_test_for_hasher!(test_created_node_keys, $hasher);
+            instantiate_test_for_hasher!(test_merge_disjoint, $hasher);
+            instantiate_test_for_hasher!(test_commit, $hasher);
+            instantiate_test_for_hasher!(test_get_multiproof, $hasher);
+            instantiate_test_for_hasher!(test_get_adjacent_proof, $hasher);
+            instantiate_test_for_hasher!(test_put_value_set_iter, $hasher);
+            instantiate_test_for_hasher!(test_get_closest_leaf, $hasher);
+            #[cfg(feature = "access_histogram")]
+            instantiate_test_for_hasher!(test_access_counting_reader, $hasher);
+            instantiate_test_for_hasher!(test_proof_from_sibling_hashes, $hasher);
+            instantiate_test_for_hasher!(test_try_get_with_proof, $hasher);
+            instantiate_test_for_hasher!(test_storage_size, $hasher);
+            instantiate_test_for_hasher!(test_without_stale_tracking, $hasher);
+            instantiate_test_for_hasher!(test_path_versions, $hasher);
+            instantiate_test_for_hasher!(test_verify_against_bytes, $hasher);
+            instantiate_test_for_hasher!(test_what_if_root, $hasher);
+            instantiate_test_for_hasher!(test_proof_steps, $hasher);
+            instantiate_test_for_hasher!(test_compare_and_swap, $hasher);
+            instantiate_test_for_hasher!(test_get_value_bounded_staleness, $hasher);
+            instantiate_test_for_hasher!(test_roots_equal, $hasher);
+            instantiate_test_for_hasher!(test_rank, $hasher);
+            instantiate_test_for_hasher!(test_get_proof_only, $hasher);
+            instantiate_test_for_hasher!(test_recording_reader, $hasher);
+            instantiate_test_for_hasher!(test_rehash, $hasher);
+            instantiate_test_for_hasher!(test_value_proof, $hasher);
+            instantiate_test_for_hasher!(test_key_bounds, $hasher);
+            instantiate_test_for_hasher!(test_put_value_set_sorted_with_flush, $hasher);
+            instantiate_test_for_hasher!(test_put_value_set_sorted_with_flush_delete, $hasher);
+            instantiate_test_for_hasher!(test_put_value_checked, $hasher);
+            instantiate_test_for_hasher!(test_version_count, $hasher);
+            instantiate_test_for_hasher!(test_namespace_proof, $hasher);
 
 
             proptest! {
@@ -579,6 +657,38 @@ fn test_non_existence<H: SimpleHasher>() {
     }
 }
 
+fn test_reconstruct_root<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let key1 = KeyHash([0u8; 32]);
+    let key2 = update_nibble(&key1, 0, 15);
+    let key3 = update_nibble(&key1, 2, 3);
+    let (roots, batch) = tree
+        .batch_put_value_sets(
+            vec![vec![(key1, vec![1u8]), (key2, vec![2u8]), (key3, vec![3u8])]],
+            None,
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Inclusion: the root implied by the right value is the tree root, a wrong value is not.
+    let (value, proof) = tree.get_with_proof(key3, 0).unwrap();
+    assert_eq!(proof.reconstruct_root(key3, value.as_ref()), roots[0]);
+    assert_ne!(proof.reconstruct_root(key3, Some(vec![4u8])), roots[0]);
+
+    // Non-inclusion, both with a leaf and with an empty subtree in the proof.
+    for non_existing_key in [update_nibble(&key1, 2, 4), update_nibble(&key1, 0, 1)] {
+        let (value, proof) = tree.get_with_proof(non_existing_key, 0).unwrap();
+        assert_eq!(value, None);
+        assert_eq!(
+            proof.reconstruct_root(non_existing_key, None::<&[u8]>),
+            roots[0]
+        );
+    }
+}
+
 fn test_missing_root<H: SimpleHasher>() {
     let db = MockTreeStore::default();
     let tree = JellyfishMerkleTree::<_, H>::new(&db);
@@ -598,6 +708,7 @@ fn test_non_batch_empty_write_set<H: SimpleHasher>() {
     db.write_tree_update_batch(batch).unwrap();
     let root = tree.get_root_hash(0).unwrap();
     assert_eq!(root.0, SPARSE_MERKLE_PLACEHOLDER_HASH);
+    assert_eq!(root, RootHash::EMPTY);
 }
 
 fn test_put_value_sets<H: SimpleHasher>() {
@@ -785,6 +896,2955 @@ fn test_two_gets_then_delete<H: SimpleHasher>() {
     db.write_tree_update_batch(batch).unwrap();
 }
 
+fn test_truncate_then_reapply<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..20)
+        .map(|i| KeyHash::with::<H>(format!("key{}", i)))
+        .collect();
+    let value_sets = |tag: &str| {
+        (0..6)
+            .map(|version| {
+                keys.iter()
+                    .enumerate()
+                    .filter(|(i, _)| i % 6 <= version)
+                    .map(|(i, key)| {
+                        if (i + version) % 5 == 0 {
+                            (*key, None)
+                        } else {
+                            (*key, Some(format!("{}{}-{}", tag, i, version).into_bytes()))
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let original = value_sets("original");
+    let replacement = value_sets("replacement");
+
+    // Commit versions 0..=5, then roll back to version 2.
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    for (version, value_set) in original.iter().enumerate() {
+        let (_, batch) = tree
+            .put_value_set(value_set.clone(), version as Version)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    tree.truncate_to(&db, 2).unwrap();
+    assert!(tree.get_root_hash_option(3).unwrap().is_none());
+
+    // A tree that was only ever committed up to version 2 must read identically.
+    let expected_db = MockTreeStore::default();
+    let expected_tree = JellyfishMerkleTree::<_, H>::new(&expected_db);
+    for (version, value_set) in original.iter().take(3).enumerate() {
+        let (_, batch) = expected_tree
+            .put_value_set(value_set.clone(), version as Version)
+            .unwrap();
+        expected_db.write_tree_update_batch(batch).unwrap();
+    }
+    assert_eq!(db.num_nodes(), expected_db.num_nodes());
+    assert_eq!(
+        tree.get_root_hash(2).unwrap(),
+        expected_tree.get_root_hash(2).unwrap()
+    );
+    for key in keys.iter() {
+        for version in 0..6 {
+            assert_eq!(
+                tree.get(*key, version).unwrap(),
+                expected_tree.get(*key, version).unwrap()
+            );
+        }
+    }
+
+    // Re-applying different versions on top of the truncated tree must match a fresh tree.
+    for (version, value_set) in replacement.iter().enumerate().skip(3) {
+        let (root, batch) = tree
+            .put_value_set(value_set.clone(), version as Version)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let (expected_root, batch) = expected_tree
+            .put_value_set(value_set.clone(), version as Version)
+            .unwrap();
+        expected_db.write_tree_update_batch(batch).unwrap();
+        assert_eq!(root, expected_root);
+    }
+    assert_eq!(db.num_nodes(), expected_db.num_nodes());
+
+    // The stale node indices must line up as well, otherwise pruning would fail or diverge.
+    db.purge_stale_nodes(5).unwrap();
+    expected_db.purge_stale_nodes(5).unwrap();
+    assert_eq!(db.num_nodes(), expected_db.num_nodes());
+    for key in keys.iter() {
+        assert_eq!(
+            tree.get(*key, 5).unwrap(),
+            expected_tree.get(*key, 5).unwrap()
+        );
+    }
+}
+
+fn test_tree_driver_matches_put_value_sets<H: SimpleHasher>() {
+    let key1 = KeyHash([1; 32]);
+    let key2 = KeyHash([2; 32]);
+    let ops = vec![
+        Op::Put(key1, vec![1]),
+        Op::Put(key2, vec![2]),
+        Op::Commit,
+        Op::Delete(key1),
+        Op::Commit,
+        Op::Commit,
+        Op::Put(key1, vec![3]),
+    ];
+
+    let mut driver = TreeDriver::<H>::new();
+    let root_hashes = driver.apply(ops);
+    assert_eq!(driver.next_version(), 3);
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (expected_root_hashes, _) = tree
+        .put_value_sets(
+            vec![
+                vec![(key1, Some(vec![1])), (key2, Some(vec![2]))],
+                vec![(key1, None)],
+                vec![],
+            ],
+            0, /* version */
+        )
+        .unwrap();
+    assert_eq!(root_hashes, expected_root_hashes);
+
+    // The staged put is carried over to the next call.
+    let root_hashes = driver.apply(vec![Op::Commit]);
+    let driver_tree = JellyfishMerkleTree::<_, H>::new(driver.store());
+    assert_eq!(root_hashes, vec![driver_tree.get_root_hash(3).unwrap()]);
+    assert_eq!(driver_tree.get(key1, 3).unwrap(), Some(vec![3]));
+}
+
+fn test_validate_update_batch<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..10)
+        .map(|i| KeyHash::with::<H>(format!("key{}", i)))
+        .collect();
+    let value_sets: Vec<Vec<_>> = (0..4)
+        .map(|version| {
+            keys.iter()
+                .enumerate()
+                .map(|(i, key)| {
+                    if (i + version) % 3 == 0 {
+                        (*key, None)
+                    } else {
+                        (*key, Some(vec![i as u8, version as u8]))
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    // Batches produced by the tree are always valid, whether they span one version or several.
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    for (version, value_set) in value_sets.iter().enumerate() {
+        let (_, batch) = tree
+            .put_value_set(value_set.clone(), version as Version)
+            .unwrap();
+        batch.validate().unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    let (_, batch) = tree.put_value_sets(value_sets.clone(), 4).unwrap();
+    batch.validate().unwrap();
+
+    let stale_node_index = batch.stale_node_index_batch.iter().next().unwrap().clone();
+    let mut stale_before_creation = batch.clone();
+    stale_before_creation
+        .stale_node_index_batch
+        .insert(StaleNodeIndex {
+            stale_since_version: 7,
+            node_key: NodeKey::new_empty_path(7),
+        });
+    stale_before_creation.node_stats[0].stale_nodes += 1;
+    assert_eq!(
+        stale_before_creation.validate(),
+        Err(BatchError::StaleBeforeCreation {
+            node_key: NodeKey::new_empty_path(7),
+            stale_since_version: 7,
+        })
+    );
+
+    // A node of the batch can only be replaced by a later version of the same batch.
+    let mut stale_new_node = batch.clone();
+    stale_new_node
+        .stale_node_index_batch
+        .insert(StaleNodeIndex {
+            stale_since_version: 9,
+            node_key: NodeKey::new_empty_path(4),
+        });
+    stale_new_node.node_stats[0].stale_nodes += 1;
+    assert_eq!(
+        stale_new_node.validate(),
+        Err(BatchError::StaleNewNode {
+            node_key: NodeKey::new_empty_path(4),
+            stale_since_version: 9,
+        })
+    );
+
+    let mut missing_stale_node = batch.clone();
+    missing_stale_node
+        .stale_node_index_batch
+        .remove(&stale_node_index);
+    assert!(matches!(
+        missing_stale_node.validate(),
+        Err(BatchError::StaleNodeCountMismatch { .. })
+    ));
+
+    let mut miscounted_leaves = batch.clone();
+    miscounted_leaves.node_stats[0].new_leaves += 1;
+    assert!(matches!(
+        miscounted_leaves.validate(),
+        Err(BatchError::LeafCountMismatch { .. })
+    ));
+
+    let mut miscounted_nodes = batch;
+    miscounted_nodes.node_stats.pop();
+    assert!(matches!(
+        miscounted_nodes.validate(),
+        Err(BatchError::NodeCountMismatch { .. })
+    ));
+}
+
+fn test_delete_range_with_proof<H: SimpleHasher>() {
+    let mut keys: Vec<KeyHash> = (0..40)
+        .map(|i| KeyHash::with::<H>(format!("key{}", i)))
+        .collect();
+    keys.sort();
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (old_root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Delete a range in the middle of the tree. The bounds themselves need not be keys.
+    let mut left = keys[10];
+    left.0[31] = left.0[31].wrapping_add(1);
+    let right = keys[25];
+    let (new_root, proof, batch) = tree.delete_range_with_proof(left, right, 1).unwrap();
+    assert_eq!(proof.deleted_keys(), keys[11..=25].to_vec());
+    proof.verify(old_root, new_root, left, right).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    for (i, key) in keys.iter().enumerate() {
+        let value = tree.get(*key, 1).unwrap();
+        assert_eq!(value.is_none(), (11..=25).contains(&i));
+    }
+
+    // The proof does not hold for a wider range, which would include remaining keys, nor for other
+    // roots.
+    let prove = || tree.delete_range_with_proof(left, right, 1).unwrap().1;
+    assert!(prove().verify(old_root, new_root, keys[10], right).is_err());
+    assert!(prove().verify(old_root, new_root, left, keys[26]).is_err());
+    assert!(prove().verify(old_root, old_root, left, right).is_err());
+
+    // Deleting an empty range changes nothing.
+    let (root, proof, _) = tree.delete_range_with_proof(left, right, 2).unwrap();
+    assert_eq!(root, new_root);
+    assert!(proof.deleted_keys().is_empty());
+    proof.verify(new_root, root, left, right).unwrap();
+
+    // Deleting the whole key space empties the tree.
+    let (root, proof, _) = tree
+        .delete_range_with_proof(KeyHash([0x00; 32]), KeyHash([0xff; 32]), 2)
+        .unwrap();
+    assert_eq!(root, RootHash::EMPTY);
+    proof
+        .verify(new_root, root, KeyHash([0x00; 32]), KeyHash([0xff; 32]))
+        .unwrap();
+}
+
+fn test_get_with_proof_with_overlay<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..20)
+        .map(|i| KeyHash::with::<H>(format!("key{}", i)))
+        .collect();
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(keys[..10].iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Version 1 is only held in memory, so its nodes must come from the overlay.
+    let (root, batch) = tree
+        .put_value_set(keys[10..].iter().map(|key| (*key, Some(vec![1]))), 1)
+        .unwrap();
+    let overlay = batch.node_batch;
+    assert!(tree.get_with_proof(keys[0], 1).is_err());
+    for key in &keys[..10] {
+        let (value, proof) = tree.get_with_proof_with_overlay(*key, 1, &overlay).unwrap();
+        assert_eq!(value, Some(vec![0]));
+        proof.verify_existence(root, *key, vec![0]).unwrap();
+    }
+    // So must the values of version 1.
+    let (value, proof) = tree
+        .get_with_proof_with_overlay(keys[10], 1, &overlay)
+        .unwrap();
+    assert_eq!(value, Some(vec![1]));
+    proof.verify_existence(root, keys[10], vec![1]).unwrap();
+
+    // Nodes missing from the overlay are read from storage.
+    let old_root = tree.get_root_hash(0).unwrap();
+    let (value, proof) = tree
+        .get_with_proof_with_overlay(keys[0], 0, &overlay)
+        .unwrap();
+    assert_eq!(value, Some(vec![0]));
+    proof.verify_existence(old_root, keys[0], vec![0]).unwrap();
+}
+
+fn test_key_history<H: SimpleHasher>() {
+    let key = KeyHash::with::<H>("key");
+    let others: Vec<KeyHash> = (0..30)
+        .map(|i| KeyHash::with::<H>(format!("other{}", i)))
+        .collect();
+
+    // The key is set at version 0, updated at 3, deleted at 5, set again at 7 and rewritten
+    // with the same value at 8. Other keys change at every version.
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    for version in 0..10 {
+        let mut value_set: Vec<_> = others
+            .iter()
+            .skip(version * 3)
+            .take(3)
+            .map(|other| (*other, Some(vec![version as u8])))
+            .collect();
+        match version {
+            0 => value_set.push((key, Some(vec![0]))),
+            3 => value_set.push((key, Some(vec![3]))),
+            5 => value_set.push((key, None)),
+            7 | 8 => value_set.push((key, Some(vec![7]))),
+            _ => {}
+        }
+        let (_, batch) = tree.put_value_set(value_set, version as Version).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    assert_eq!(
+        tree.key_history(key, 9).unwrap(),
+        vec![
+            (0, Some(vec![0])),
+            (3, Some(vec![3])),
+            (5, None),
+            (7, Some(vec![7])),
+        ]
+    );
+    assert_eq!(
+        tree.key_history(key, 4).unwrap(),
+        vec![(0, Some(vec![0])), (3, Some(vec![3]))]
+    );
+    assert!(tree
+        .key_history(KeyHash::with::<H>("absent"), 9)
+        .unwrap()
+        .is_empty());
+    assert!(tree.key_history(key, 10).is_err());
+}
+
+fn test_get_value_hash_proof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = KeyHash::with::<H>("key2");
+    let value = b"secret".to_vec();
+    let (root, batch) = tree
+        .put_value_set(vec![(key1, Some(value.clone())), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (value_hash, proof) = tree.get_value_hash_proof(key1, 0).unwrap();
+    let value_hash = value_hash.unwrap();
+    assert_eq!(value_hash, ValueHash::with::<H>(&value));
+    proof.verify_value_hash(root, key1, value_hash).unwrap();
+    assert_eq!(proof, tree.get_with_proof(key1, 0).unwrap().1);
+
+    assert!(proof
+        .verify_value_hash(root, key1, ValueHash::with::<H>(b"other"))
+        .is_err());
+    assert!(proof.verify_value_hash(root, key2, value_hash).is_err());
+
+    // A missing key has no value hash, and its proof does not verify as an inclusion.
+    let absent = KeyHash::with::<H>("absent");
+    let (value_hash, proof) = tree.get_value_hash_proof(absent, 0).unwrap();
+    assert!(value_hash.is_none());
+    proof.verify_nonexistence(root, absent).unwrap();
+    assert!(proof
+        .verify_value_hash(root, absent, ValueHash::with::<H>(&value))
+        .is_err());
+}
+
+fn test_verify_rejects_wrong_leaf<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = KeyHash::with::<H>("key2");
+    let value1 = vec![1u8];
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root, batch) = tree
+        .put_value_set(vec![(key1, Some(value1.clone())), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, proof1) = tree.get_with_proof(key1, 0).unwrap();
+    let (_, proof2) = tree.get_with_proof(key2, 0).unwrap();
+
+    // An absent key whose path ends at the leaf of `key1`, which proves it absent but does not
+    // prove it present with the value of `key1`.
+    let colliding_key = update_nibble(&key1, 63, (key1.0[31] % 16 + 1) % 16);
+    assert_eq!(tree.get_with_proof(colliding_key, 0).unwrap().1, proof1);
+    proof1.verify_nonexistence(root, colliding_key).unwrap();
+    assert!(proof1
+        .verify_existence(root, colliding_key, &value1)
+        .is_err());
+
+    // The proof of another key does not prove `key1` present, nor `key1` absent.
+    assert!(proof2.verify_existence(root, key1, &value1).is_err());
+    assert!(proof1.verify_nonexistence(root, key1).is_err());
+
+    // A leaf off the path of the queried key does not prove it absent.
+    let off_path_key = update_nibble(&key1, 0, (key1.0[0] / 16) ^ 8);
+    assert!(proof1.verify_nonexistence(root, off_path_key).is_err());
+}
+
+fn test_get_unchanged_proof<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = KeyHash::with::<H>("key2");
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (roots, batch) = tree
+        .put_value_sets(
+            vec![
+                vec![(key1, Some(vec![1])), (key2, Some(vec![2]))],
+                vec![(key2, Some(vec![3]))],
+                vec![(KeyHash::with::<H>("key3"), Some(vec![4]))],
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let proof = tree.get_unchanged_proof(key1, 0, 2).unwrap();
+    assert_eq!(
+        proof.verify(roots[0], roots[2], key1).unwrap(),
+        ValueHash::with::<H>([1])
+    );
+    assert!(proof.verify(roots[0], roots[1], key1).is_err());
+    assert!(proof.verify(roots[0], roots[2], key2).is_err());
+
+    // A changed, absent or misordered key has no such proof.
+    assert!(tree.get_unchanged_proof(key2, 0, 2).is_err());
+    tree.get_unchanged_proof(key2, 1, 2).unwrap();
+    assert!(tree
+        .get_unchanged_proof(KeyHash::with::<H>("key3"), 1, 2)
+        .is_err());
+    assert!(tree.get_unchanged_proof(key1, 2, 0).is_err());
+
+    // Proofs of different values do not verify as unchanged.
+    let forged = tree.get_unchanged_proof(key2, 1, 2).unwrap();
+    let forged = UnchangedProof::new(
+        tree.get_with_proof(key2, 0).unwrap().1,
+        forged.to_proof().clone(),
+    );
+    assert!(forged.verify(roots[0], roots[2], key2).is_err());
+}
+
+fn test_write_batch_twice<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = KeyHash::with::<H>("key2");
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(vec![(key1, Some(vec![1])), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch.clone()).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // The second version marks nodes of the first one stale. Redelivering it is a no-op.
+    let (root, batch) = tree.put_value_set(vec![(key1, None)], 1).unwrap();
+    assert!(!batch.stale_node_index_batch.is_empty());
+    db.write_tree_update_batch(batch.clone()).unwrap();
+    let num_nodes = db.num_nodes();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(db.num_nodes(), num_nodes);
+    assert_eq!(tree.get_root_hash(1).unwrap(), root);
+    assert_eq!(tree.get(key2, 1).unwrap(), Some(vec![2]));
+
+    db.purge_stale_nodes(1).unwrap();
+    assert_eq!(tree.get(key2, 1).unwrap(), Some(vec![2]));
+
+    // Redelivering a batch spanning several versions after them leaves the values unchanged.
+    let (_, batch) = tree
+        .put_value_sets(
+            vec![vec![(key1, Some(vec![3]))], vec![(key1, Some(vec![4]))]],
+            2,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch.clone()).unwrap();
+    assert_eq!(db.num_values(key1), 4);
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(db.num_values(key1), 4);
+    assert_eq!(tree.get(key1, 2).unwrap(), Some(vec![3]));
+    assert_eq!(tree.get(key1, 3).unwrap(), Some(vec![4]));
+
+    // A batch overwriting a node with another one fails before writing anything.
+    let (_, mut batch) = tree.put_value_set(vec![(key2, Some(vec![5]))], 4).unwrap();
+    batch
+        .node_batch
+        .insert_node(NodeKey::new_empty_path(3), Node::new_null());
+    let num_nodes = db.num_nodes();
+    assert!(db.write_tree_update_batch(batch).is_err());
+    assert_eq!(db.num_nodes(), num_nodes);
+    assert_eq!(db.num_values(key2), 1);
+    assert!(tree.get_root_hash(4).is_err());
+}
+
+fn test_get_with_proof_pre_genesis<H: SimpleHasher>() {
+    let key = KeyHash::with::<H>("key");
+    let value = vec![1u8, 2, 3];
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    assert!(tree.get_with_proof_pre_genesis(key).is_err());
+
+    let leaf = LeafNode::new(key, ValueHash::with::<H>(&value));
+    let root_hash = RootHash(Node::from(leaf.clone()).hash::<H>());
+    db.put_leaf(
+        NodeKey::new_empty_path(PRE_GENESIS_VERSION),
+        leaf,
+        value.clone(),
+    )
+    .unwrap();
+
+    let (found, proof) = tree.get_with_proof_pre_genesis(key).unwrap();
+    assert_eq!(found, Some(value.clone()));
+    proof.verify_existence(root_hash, key, &value).unwrap();
+
+    let other_key = update_nibble(&key, 0, 15 - key.0[0] / 16);
+    let (found, proof) = tree.get_with_proof_pre_genesis(other_key).unwrap();
+    assert_eq!(found, None);
+    proof.verify_nonexistence(root_hash, other_key).unwrap();
+
+    // Genesis is applied on top of the pre-genesis state.
+    let (genesis_root, batch) = tree
+        .put_value_set(vec![(other_key, Some(vec![4]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_ne!(genesis_root, root_hash);
+    let (found, proof) = tree.get_with_proof_pre_genesis(key).unwrap();
+    assert_eq!(found, Some(value.clone()));
+    proof.verify_existence(root_hash, key, &value).unwrap();
+}
+
+fn test_proof_depth<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key = KeyHash::with::<H>("key0");
+    assert_eq!(
+        tree.proof_depth(key, 0)
+            .unwrap_err()
+            .downcast::<MissingRootError>()
+            .unwrap()
+            .version,
+        0
+    );
+
+    let (_, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.proof_depth(key, 0).unwrap(), 0);
+
+    let mut rng: StdRng = StdRng::from_seed([0; 32]);
+    let kvs: Vec<_> = (0..200u32)
+        .map(|i| {
+            (
+                KeyHash::with::<H>(i.to_be_bytes()),
+                Some(vec![rng.gen::<u8>()]),
+            )
+        })
+        .collect();
+    let (_, batch) = tree.put_value_set(kvs.clone(), 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let non_existing_keys = (200..300u32).map(|i| KeyHash::with::<H>(i.to_be_bytes()));
+    for key in kvs.iter().map(|(key, _)| *key).chain(non_existing_keys) {
+        let (_, proof) = tree.get_with_proof(key, 1).unwrap();
+        let expected = proof
+            .siblings()
+            .iter()
+            .filter(|sibling| sibling.hash::<H>() != SPARSE_MERKLE_PLACEHOLDER_HASH)
+            .count();
+        assert_eq!(tree.proof_depth(key, 1).unwrap(), expected);
+    }
+}
+
+fn test_subtree_root_hash<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    assert!(tree.subtree_root_hash(&[], 0).is_err());
+
+    let (_, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        tree.subtree_root_hash(&[], 0).unwrap(),
+        SPARSE_MERKLE_PLACEHOLDER_HASH
+    );
+    assert_eq!(
+        tree.subtree_root_hash(&[Nibble::from(3)], 0).unwrap(),
+        SPARSE_MERKLE_PLACEHOLDER_HASH
+    );
+
+    let keys: Vec<_> = (0..100u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.subtree_root_hash(&[], 1).unwrap(), root.0);
+
+    // Combining the top-level subtrees reproduces the root hash.
+    let mut children = Children::new();
+    for n in 0..16u8 {
+        let hash = tree.subtree_root_hash(&[Nibble::from(n)], 1).unwrap();
+        let leaf_count = keys.iter().filter(|key| key.0[0] >> 4 == n).count();
+        match leaf_count {
+            0 => assert_eq!(hash, SPARSE_MERKLE_PLACEHOLDER_HASH),
+            1 => children.insert(n.into(), Child::new(hash, 1, NodeType::Leaf)),
+            _ => children.insert(
+                n.into(),
+                Child::new(hash, 1, NodeType::Internal { leaf_count }),
+            ),
+        }
+    }
+    assert_eq!(InternalNode::new(children).hash::<H>(), root.0);
+
+    // The full path of a key is the subtree holding its leaf only.
+    let nibbles: Vec<Nibble> = NibblePath::new(keys[0].0.to_vec()).nibbles().collect();
+    let leaf_hash = LeafNode::new(keys[0], ValueHash::with::<H>([1])).hash::<H>();
+    assert_eq!(tree.subtree_root_hash(&nibbles, 1).unwrap(), leaf_hash);
+    let mut other_nibbles = nibbles.clone();
+    other_nibbles[63] = Nibble::from(15 - u8::from(nibbles[63]));
+    assert_eq!(
+        tree.subtree_root_hash(&other_nibbles, 1).unwrap(),
+        SPARSE_MERKLE_PLACEHOLDER_HASH
+    );
+    other_nibbles.push(Nibble::from(0));
+    assert!(tree.subtree_root_hash(&other_nibbles, 1).is_err());
+}
+
+fn test_empty_value_is_not_deletion<H: SimpleHasher>() {
+    let key = KeyHash::with::<H>("key");
+    let other_key = KeyHash::with::<H>("other_key");
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_with_empty, batch) = tree
+        .put_value_set(vec![(key, Some(vec![])), (other_key, Some(vec![1]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (root_deleted, batch) = tree.put_value_set(vec![(key, None)], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_ne!(root_with_empty, root_deleted);
+
+    let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+    assert_eq!(value, Some(vec![]));
+    proof.verify(root_with_empty, key, Some(vec![])).unwrap();
+    assert!(proof.verify(root_with_empty, key, None::<Vec<u8>>).is_err());
+
+    let (value, proof) = tree.get_with_proof(key, 1).unwrap();
+    assert_eq!(value, None);
+    proof.verify(root_deleted, key, None::<Vec<u8>>).unwrap();
+    assert!(proof.verify(root_deleted, key, Some(vec![])).is_err());
+}
+
+fn test_overlay_reader<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = KeyHash::with::<H>("key2");
+    let key3 = KeyHash::with::<H>("key3");
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root0, batch) = tree
+        .put_value_set(vec![(key1, Some(vec![1])), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Version 1 is proposed but not committed.
+    let (root1, batch) = tree
+        .put_value_set(vec![(key1, None), (key3, Some(vec![3]))], 1)
+        .unwrap();
+    assert!(tree.get_root_hash(1).is_err());
+
+    let node_batch = batch.node_batch.clone();
+    let overlay = OverlayReader::new(&db, &node_batch);
+    let speculative_tree = JellyfishMerkleTree::<_, H>::new(&overlay);
+    assert_eq!(speculative_tree.get_root_hash(0).unwrap(), root0);
+    assert_eq!(speculative_tree.get_root_hash(1).unwrap(), root1);
+    for (key, expected) in [(key1, None), (key2, Some(vec![2])), (key3, Some(vec![3]))] {
+        let (value, proof) = speculative_tree.get_with_proof(key, 1).unwrap();
+        assert_eq!(value, expected);
+        proof.verify(root1, key, value).unwrap();
+    }
+    assert_eq!(speculative_tree.get(key1, 0).unwrap(), Some(vec![1]));
+    assert_eq!(speculative_tree.get(key3, 0).unwrap(), None);
+
+    // Committing the batch yields the same state.
+    db.write_tree_update_batch(batch).unwrap();
+    for key in [key1, key2, key3] {
+        assert_eq!(
+            tree.get(key, 1).unwrap(),
+            speculative_tree.get(key, 1).unwrap()
+        );
+    }
+}
+
+fn test_node_batch_get_value<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..4u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let mut values = BTreeMap::new();
+    values.insert((1, keys[0]), Some(vec![1]));
+    values.insert((1, keys[1]), Some(vec![1]));
+    values.insert((3, keys[0]), None);
+    values.insert((3, keys[2]), Some(vec![3]));
+    values.insert((6, keys[1]), Some(vec![6]));
+    let batch = NodeBatch::new(BTreeMap::new(), values);
+
+    assert_eq!(batch.get_value(0, keys[0]), None);
+    assert_eq!(batch.get_value(2, keys[0]), Some(&Some(vec![1])));
+    assert_eq!(batch.get_value(3, keys[0]), Some(&None));
+    assert_eq!(batch.get_value(10, keys[0]), Some(&None));
+    assert_eq!(batch.get_value(5, keys[1]), Some(&Some(vec![1])));
+    assert_eq!(batch.get_value(6, keys[1]), Some(&Some(vec![6])));
+    assert_eq!(batch.get_value(2, keys[2]), None);
+    assert_eq!(batch.get_value(4, keys[2]), Some(&Some(vec![3])));
+    assert_eq!(batch.get_value(10, keys[3]), None);
+}
+
+fn test_conflicting_leaf<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let (nibble0, nibble1) = (key1.0[0] >> 4, key1.0[0] & 0x0f);
+    // Both leaves are in the same half of the root, so the other half is an empty subtree.
+    let key2 = update_nibble(&key1, 0, nibble0 ^ 1);
+    // Absent keys ending at the leaf of `key1` and `key2` respectively.
+    let absent_key1 = update_nibble(&key1, 1, nibble1 ^ 1);
+    let absent_key2 = update_nibble(&key2, 1, nibble1 ^ 1);
+    let empty_subtree_key = update_nibble(&key1, 0, nibble0 ^ 8);
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root, batch) = tree
+        .put_value_set(vec![(key1, Some(vec![1])), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (_, proof) = tree.get_with_proof(key1, 0).unwrap();
+    assert_eq!(proof.conflicting_leaf(key1), None);
+
+    for (absent_key, occupant_key, occupant_value) in
+        [(absent_key1, key1, [1]), (absent_key2, key2, [2])]
+    {
+        let (value, proof) = tree.get_with_proof(absent_key, 0).unwrap();
+        assert_eq!(value, None);
+        let occupant = proof.conflicting_leaf(absent_key).unwrap();
+        assert_eq!(occupant.key_hash(), occupant_key);
+        assert_eq!(occupant.value_hash(), ValueHash::with::<H>(occupant_value));
+        proof.verify_nonexistence(root, absent_key).unwrap();
+    }
+
+    let (_, proof) = tree.get_with_proof(empty_subtree_key, 0).unwrap();
+    assert_eq!(proof.conflicting_leaf(empty_subtree_key), None);
+    proof.verify_nonexistence(root, empty_subtree_key).unwrap();
+}
+
+/// Reads from `store`, except for the node at `node_key` whose read fails if `fail` is set, and
+/// which is missing otherwise.
+struct FaultyReader<'a> {
+    store: &'a MockTreeStore,
+    node_key: NodeKey,
+    fail: bool,
+}
+
+impl TreeReader for FaultyReader<'_> {
+    fn get_node_option(&self, node_key: &NodeKey) -> anyhow::Result<Option<Node>> {
+        if *node_key != self.node_key {
+            self.store.get_node_option(node_key)
+        } else if self.fail {
+            Err(anyhow::format_err!("backend down"))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> anyhow::Result<Option<crate::OwnedValue>> {
+        self.store.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> anyhow::Result<Option<(NodeKey, LeafNode)>> {
+        self.store.get_rightmost_leaf()
+    }
+}
+
+fn test_read_errors<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = update_nibble(&key1, 0, (key1.0[0] >> 4) ^ 1);
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(vec![(key1, Some(vec![1])), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let root_key = NodeKey::new_empty_path(0);
+    let leaf_key = root_key.gen_child_node_key(0, Nibble::from(key1.0[0] >> 4));
+    let read_error = |node_key: &NodeKey, fail| {
+        let reader = FaultyReader {
+            store: &db,
+            node_key: node_key.clone(),
+            fail,
+        };
+        JellyfishMerkleTree::<_, H>::new(&reader)
+            .get_with_proof(key1, 0)
+            .unwrap_err()
+    };
+
+    // A missing root is reported as such, unlike a failure to read it.
+    let err = read_error(&root_key, false);
+    assert_eq!(err.downcast_ref::<MissingRootError>().unwrap().version, 0);
+    let err = read_error(&root_key, true);
+    assert!(err.downcast_ref::<MissingRootError>().is_none());
+    assert_eq!(
+        err.downcast_ref::<ReadError>(),
+        Some(&ReadError::Backend(root_key.clone()))
+    );
+    assert_eq!(err.root_cause().to_string(), "backend down");
+
+    let err = read_error(&leaf_key, false);
+    assert_eq!(
+        err.downcast_ref::<ReadError>(),
+        Some(&ReadError::UnexpectedMissing(leaf_key.clone()))
+    );
+    let err = read_error(&leaf_key, true);
+    assert_eq!(
+        err.downcast_ref::<ReadError>(),
+        Some(&ReadError::Backend(leaf_key))
+    );
+
+    let absent_key = update_nibble(&key1, 63, (key1.0[31] & 0x0f) ^ 1);
+    let err = db.get_value(0, absent_key).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ReadError>(),
+        Some(ReadError::UnexpectedMissingValue { max_version: 0, .. })
+    ));
+
+    // Reads of roots are tagged too.
+    let reader = FaultyReader {
+        store: &db,
+        node_key: root_key.clone(),
+        fail: true,
+    };
+    let err = JellyfishMerkleTree::<_, H>::new(&reader)
+        .get_root_hash(0)
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ReadError>(),
+        Some(&ReadError::Backend(root_key))
+    );
+
+    // So are the failures to read values.
+    let reader = FailingValueReader(&db);
+    let backend_value = ReadError::BackendValue {
+        max_version: 0,
+        key_hash: key1,
+    };
+    let err = reader.get_value(0, key1).unwrap_err();
+    assert_eq!(err.downcast_ref::<ReadError>(), Some(&backend_value));
+    assert_eq!(err.root_cause().to_string(), "backend down");
+    let err = JellyfishMerkleTree::<_, H>::new(&reader)
+        .get(key1, 0)
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<ReadError>(), Some(&backend_value));
+}
+
+/// Reads nodes from the wrapped store, but fails to read any value.
+struct FailingValueReader<'a>(&'a MockTreeStore);
+
+impl TreeReader for FailingValueReader<'_> {
+    fn get_node_option(&self, node_key: &NodeKey) -> anyhow::Result<Option<Node>> {
+        self.0.get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        _max_version: Version,
+        _key_hash: KeyHash,
+    ) -> anyhow::Result<Option<crate::OwnedValue>> {
+        Err(anyhow::format_err!("backend down"))
+    }
+
+    fn get_rightmost_leaf(&self) -> anyhow::Result<Option<(NodeKey, LeafNode)>> {
+        self.0.get_rightmost_leaf()
+    }
+}
+
+fn test_repair_root<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let nibble = key1.0[0] >> 4;
+    let key2 = update_nibble(&key1, 0, nibble ^ 1);
+    let key3 = update_nibble(&key1, 0, nibble ^ 2);
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let value_sets = vec![
+        vec![
+            (key1, Some(vec![1])),
+            (key2, Some(vec![2])),
+            (key3, Some(vec![3])),
+        ],
+        vec![(key1, Some(vec![4]))],
+        vec![(key3, None)],
+    ];
+    let (root_hashes, batch) = tree.put_value_sets(value_sets, 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let drop_root = |version| {
+        db.truncate(&TruncationBatch {
+            version: 2,
+            node_keys: [NodeKey::new_empty_path(version)].into_iter().collect(),
+        })
+        .unwrap();
+    };
+
+    assert_eq!(
+        tree.repair_root(&db, 1, 0, root_hashes[1]).unwrap(),
+        RootRepair::Intact
+    );
+
+    assert!(tree.repair_root(&db, 1, 2, root_hashes[1]).is_err());
+
+    // The root is only written once it matches the expected root hash.
+    drop_root(1);
+    // The children of `key2` and `key3` were last updated before the minimum version.
+    match tree.repair_root(&db, 1, 1, root_hashes[1]).unwrap() {
+        RootRepair::Unrepairable { missing } => {
+            assert_eq!(missing.len(), 15);
+            assert!(!missing.contains(&Nibble::from(nibble)));
+        }
+        repair => panic!("unexpected {:?}", repair),
+    }
+    assert!(matches!(
+        tree.repair_root(&db, 1, 0, root_hashes[0]).unwrap(),
+        RootRepair::Unrepairable { .. }
+    ));
+    assert_eq!(tree.get_root_hash_option(1).unwrap(), None);
+    assert_eq!(
+        tree.repair_root(&db, 1, 0, root_hashes[1]).unwrap(),
+        RootRepair::Repaired
+    );
+    assert_eq!(tree.get_root_hash(1).unwrap(), root_hashes[1]);
+    assert_eq!(tree.get(key1, 1).unwrap(), Some(vec![4]));
+
+    // At version 2, the stale leaf of `key3` is mistaken for a child.
+    drop_root(2);
+    match tree.repair_root(&db, 2, 0, root_hashes[2]).unwrap() {
+        RootRepair::Unrepairable { missing } => {
+            assert_eq!(missing.len(), 13);
+            assert!(!missing.contains(&Nibble::from(nibble ^ 2)));
+        }
+        repair => panic!("unexpected {:?}", repair),
+    }
+    assert_eq!(tree.get_root_hash_option(2).unwrap(), None);
+}
+
+fn test_value_hash_cache<H: SimpleHasher>() {
+    // Values sharing their length and their sampled bytes only differ in the middle.
+    let values: Vec<Vec<u8>> = (0..3u8)
+        .map(|i| {
+            let mut value = vec![7u8; 1000];
+            value[300] = i;
+            value
+        })
+        .collect();
+    let value_sets: Vec<Vec<(KeyHash, Option<Vec<u8>>)>> = (0..4u8)
+        .map(|version| {
+            (0..20u8)
+                .map(|i| {
+                    let key = KeyHash::with::<H>([version, i]);
+                    (key, Some(values[(i % 3) as usize].clone()))
+                })
+                .collect()
+        })
+        .collect();
+
+    let db = MockTreeStore::default();
+    let expected = JellyfishMerkleTree::<_, H>::new(&db)
+        .put_value_sets(value_sets.clone(), 0)
+        .unwrap();
+    // A capacity smaller than the number of distinct values forces evictions.
+    for capacity in [0, 2, 100] {
+        let tree = JellyfishMerkleTree::<_, H>::new(&db).with_value_hash_cache(capacity);
+        assert_eq!(
+            tree.put_value_sets(value_sets.clone(), 0).unwrap(),
+            expected
+        );
+    }
+}
+
+fn test_proof_cache<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = KeyHash::with::<H>("key2");
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hashes, batch) = tree
+        .put_value_sets(
+            vec![
+                vec![(key1, Some(vec![1])), (key2, Some(vec![2]))],
+                vec![(key1, Some(vec![3]))],
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let cache = ProofCache::new(10);
+    for _ in 0..2 {
+        let (value, proof) = tree.get_with_proof_cached(key1, 1, &cache).unwrap();
+        assert_eq!(value, Some(vec![3]));
+        proof.verify(root_hashes[1], key1, value).unwrap();
+        assert_eq!(cache.len(), 1);
+    }
+
+    // Older versions bypass the cache.
+    let (value, proof) = tree.get_with_proof_cached(key1, 0, &cache).unwrap();
+    assert_eq!(value, Some(vec![1]));
+    proof.verify(root_hashes[0], key1, value).unwrap();
+    assert_eq!(cache.len(), 1);
+
+    // A newer version invalidates the cached proofs.
+    let (root_hash, batch) = tree.put_value_set(vec![(key2, None)], 2).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (value, proof) = tree.get_with_proof_cached(key2, 2, &cache).unwrap();
+    assert_eq!(value, None);
+    proof.verify(root_hash, key2, value).unwrap();
+    let (value, proof) = tree.get_with_proof_cached(key1, 2, &cache).unwrap();
+    proof.verify(root_hash, key1, value).unwrap();
+    assert_eq!(cache.len(), 2);
+    let (value, proof) = tree.get_with_proof_cached(key1, 1, &cache).unwrap();
+    proof.verify(root_hashes[1], key1, value).unwrap();
+    assert_eq!(cache.len(), 2);
+
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+fn test_root_hash_cache<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hashes, batch) = tree
+        .put_value_sets(
+            (0..4u8).map(|i| vec![(KeyHash::with::<H>([i]), Some(vec![i]))]),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Only the two most recent versions are kept.
+    let cache = RootHashCache::new(2);
+    for (version, root_hash) in root_hashes.iter().enumerate() {
+        let version = version as Version;
+        assert_eq!(cache.cached_root_hash(version), None);
+        assert_eq!(
+            tree.get_root_hash_cached(version, &cache).unwrap(),
+            *root_hash
+        );
+        assert_eq!(cache.cached_root_hash(version), Some(*root_hash));
+    }
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.cached_root_hash(1), None);
+    tree.get_root_hash_cached(1, &cache).unwrap();
+    assert_eq!(cache.cached_root_hash(1), None);
+
+    // Rolled back versions are dropped, and read again once committed anew.
+    tree.truncate_to(&db, 2).unwrap();
+    cache.truncate_to(2);
+    assert_eq!(cache.cached_root_hash(3), None);
+    assert_eq!(cache.cached_root_hash(2), Some(root_hashes[2]));
+    let (root_hash, batch) = tree
+        .put_value_set(vec![(KeyHash::with::<H>([4]), Some(vec![4]))], 3)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_ne!(root_hash, root_hashes[3]);
+    assert_eq!(tree.get_root_hash_cached(3, &cache).unwrap(), root_hash);
+
+    cache.clear();
+    assert!(cache.is_empty());
+
+    // Committing fills the cache, and rolling back through the tree invalidates it, so that a
+    // version committed again with other data is served its new root hash.
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let cache = RootHashCache::new(4);
+    let (root_hashes, batch) = tree
+        .put_value_sets(
+            (0..3u8).map(|i| vec![(KeyHash::with::<H>([i]), Some(vec![i]))]),
+            0,
+        )
+        .unwrap();
+    tree.commit_cached(&db, root_hashes.clone(), batch, &cache)
+        .unwrap();
+    for (version, root_hash) in root_hashes.iter().enumerate() {
+        assert_eq!(cache.cached_root_hash(version as Version), Some(*root_hash));
+    }
+
+    tree.truncate_to_cached(&db, 1, &cache).unwrap();
+    assert_eq!(cache.cached_root_hash(2), None);
+    let (root_hash, batch) = tree
+        .put_value_set(vec![(KeyHash::with::<H>([5]), Some(vec![5]))], 2)
+        .unwrap();
+    assert_ne!(root_hash, root_hashes[2]);
+    tree.commit_cached(&db, vec![root_hash], batch, &cache)
+        .unwrap();
+    assert_eq!(cache.cached_root_hash(2), Some(root_hash));
+    assert_eq!(tree.get_root_hash_cached(2, &cache).unwrap(), root_hash);
+}
+
+fn test_put_value_set_with_previous<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = KeyHash::with::<H>("key2");
+    let key3 = KeyHash::with::<H>("key3");
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch, previous_values) = tree
+        .put_value_set_with_previous(vec![(key1, Some(vec![1])), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    assert_eq!(previous_values.len(), 2);
+    assert!(previous_values.values().all(Option::is_none));
+    db.write_tree_update_batch(batch).unwrap();
+
+    // A key written twice reports its value from before the set.
+    let value_set = vec![
+        (key1, Some(vec![3])),
+        (key1, Some(vec![4])),
+        (key2, None),
+        (key3, Some(vec![5])),
+    ];
+    let (root_hash, batch, previous_values) = tree
+        .put_value_set_with_previous(value_set.clone(), 1)
+        .unwrap();
+    assert_eq!(
+        (root_hash, batch),
+        tree.put_value_set(value_set, 1).unwrap()
+    );
+    assert_eq!(previous_values.len(), 3);
+    assert_eq!(previous_values[&key1], Some(vec![1]));
+    assert_eq!(previous_values[&key2], Some(vec![2]));
+    assert_eq!(previous_values[&key3], None);
+}
+
+fn test_sharded_tree_reader<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch.clone()).unwrap();
+
+    let sharded = ShardedTreeReader::new(
+        core::array::from_fn::<_, 4, _>(|_| MockTreeStore::default()),
+        1,
+    );
+    for (node_key, node) in batch.node_batch.nodes() {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(node_key.clone(), node.clone());
+        sharded.shards[sharded.node_shard(node_key)]
+            .write_node_batch(&NodeBatch::new(nodes, BTreeMap::new()))
+            .unwrap();
+    }
+    for ((version, key_hash), value) in batch.node_batch.values() {
+        let mut values = BTreeMap::new();
+        values.insert((*version, *key_hash), value.clone());
+        sharded.shards[sharded.value_shard(*key_hash)]
+            .write_node_batch(&NodeBatch::new(BTreeMap::new(), values))
+            .unwrap();
+    }
+    assert!(sharded.shards.iter().all(|shard| shard.num_nodes() > 0));
+
+    let sharded_tree = JellyfishMerkleTree::<_, H>::new(&sharded);
+    assert_eq!(sharded_tree.get_root_hash(0).unwrap(), root_hash);
+    for key in &keys {
+        let (value, proof) = sharded_tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(value, Some(key.0.to_vec()));
+        proof.verify(root_hash, *key, value).unwrap();
+    }
+    assert_eq!(
+        sharded.get_rightmost_leaf().unwrap(),
+        db.get_rightmost_leaf().unwrap()
+    );
+}
+
+fn test_consistency_proof<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..100u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let value_sets = vec![
+        keys.iter().map(|key| (*key, Some(vec![0u8]))).collect(),
+        vec![
+            (keys[1], Some(vec![1u8])),
+            (keys[2], None),
+            (KeyHash::with::<H>("new key"), Some(vec![2u8])),
+        ],
+        keys.iter().map(|key| (*key, None)).collect::<Vec<_>>(),
+    ];
+    let (root_hashes, batch) = tree.put_value_sets(value_sets, 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let proof = tree.get_consistency_proof(0, 1).unwrap();
+    let changes = proof.verify(root_hashes[0], root_hashes[1]).unwrap();
+    let value_hash = |value: &[u8]| Some(ValueHash::with::<H>(value));
+    assert_eq!(changes.len(), 3);
+    assert_eq!(changes[&keys[1]], (value_hash(&[0]), value_hash(&[1])));
+    assert_eq!(changes[&keys[2]], (value_hash(&[0]), None));
+    assert_eq!(
+        changes[&KeyHash::with::<H>("new key")],
+        (None, value_hash(&[2]))
+    );
+    assert!(proof.verify(root_hashes[1], root_hashes[0]).is_err());
+    assert!(proof.verify(root_hashes[0], root_hashes[2]).is_err());
+
+    // Deleting the keys leaves a single one behind.
+    let changes = tree
+        .get_consistency_proof(1, 2)
+        .unwrap()
+        .verify(root_hashes[1], root_hashes[2])
+        .unwrap();
+    assert_eq!(changes.len(), keys.len() - 1);
+
+    // The proof between a version and itself is empty.
+    let proof = tree.get_consistency_proof(1, 1).unwrap();
+    assert!(proof
+        .verify(root_hashes[1], root_hashes[1])
+        .unwrap()
+        .is_empty());
+}
+
+fn test_retain<H: SimpleHasher>() {
+    let kvs: Vec<(KeyHash, Vec<u8>)> = (0..100u32)
+        .map(|i| (KeyHash::with::<H>(i.to_be_bytes()), vec![(i % 3) as u8]))
+        .collect();
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree.retain(0, |_, _| unreachable!()).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree
+        .put_value_set(
+            kvs.iter().map(|(key, value)| (*key, Some(value.clone()))),
+            1,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let mut visited = Vec::new();
+    let retained = tree
+        .retain(2, |key, value| {
+            visited.push(*key);
+            value != [0]
+        })
+        .unwrap();
+    let mut keys: Vec<KeyHash> = kvs.iter().map(|(key, _)| *key).collect();
+    keys.sort();
+    assert_eq!(visited, keys);
+
+    let deletions = kvs
+        .iter()
+        .filter(|(_, value)| *value == [0])
+        .map(|(key, _)| (*key, None));
+    assert_eq!(retained, tree.put_value_set(deletions, 2).unwrap());
+}
+
+fn test_get_with_proof_max_depth<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let kvs: Vec<(KeyHash, Option<Vec<u8>>)> = (0..50u32)
+        .map(|i| (KeyHash::with::<H>(i.to_be_bytes()), Some(vec![i as u8])))
+        .collect();
+    let (root_hash, batch) = tree.put_value_set(kvs.clone(), 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    for (key, value) in kvs {
+        let (expected_value, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(expected_value, value);
+        let depth = proof.siblings().len();
+
+        assert_eq!(
+            tree.get_with_proof_max_depth(key, 0, depth).unwrap(),
+            (expected_value, proof)
+        );
+        let err = tree
+            .get_with_proof_max_depth(key, 0, depth - 1)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProofTooDeep>(),
+            Some(&ProofTooDeep {
+                key_hash: key,
+                max_depth: depth - 1,
+            })
+        );
+    }
+
+    let (value, proof) = tree
+        .get_with_proof_max_depth(KeyHash([0; 32]), 0, usize::MAX)
+        .unwrap();
+    assert_eq!(value, None);
+    proof
+        .verify_nonexistence(root_hash, KeyHash([0; 32]))
+        .unwrap();
+}
+
+fn test_stale_nodes_by_version<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    assert!(db.stale_versions().unwrap().is_empty());
+
+    let mut expected = BTreeMap::new();
+    for version in 0..10u64 {
+        let key = KeyHash::with::<H>((version % 4).to_be_bytes());
+        let (_, batch) = tree
+            .put_value_set(vec![(key, Some(version.to_be_bytes().to_vec()))], version)
+            .unwrap();
+        if !batch.stale_node_index_batch.is_empty() {
+            expected.insert(version, batch.stale_node_index_batch.clone());
+        }
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    // Nothing is stale at the first version, and the root at least is stale at every other one.
+    assert_eq!(
+        expected.keys().copied().collect::<Vec<_>>(),
+        (1..10).collect::<Vec<_>>()
+    );
+
+    let stale_versions = db.stale_versions().unwrap();
+    assert_eq!(stale_versions, expected.keys().copied().collect::<Vec<_>>());
+    for (version, stale_nodes) in expected {
+        assert_eq!(
+            db.get_stale_nodes_since(version).unwrap(),
+            stale_nodes.into_iter().collect::<Vec<_>>()
+        );
+        db.purge_stale_nodes(version).unwrap();
+        assert!(db.get_stale_nodes_since(version).unwrap().is_empty());
+    }
+    assert!(db.stale_versions().unwrap().is_empty());
+    assert!(db.get_stale_nodes_since(10).unwrap().is_empty());
+}
+
+fn test_get_with_proof_into<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let mut buffer = ProofBuffer::<H>::new();
+    tree.get_with_proof_into(KeyHash([1; 32]), 0, &mut buffer)
+        .unwrap_err();
+
+    let kvs: Vec<(KeyHash, Option<Vec<u8>>)> = (0..20u32)
+        .map(|i| (KeyHash::with::<H>(i.to_be_bytes()), Some(vec![i as u8])))
+        .collect();
+    let (root_hash, batch) = tree.put_value_set(kvs.clone(), 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // The same buffer serves proofs of existing and missing keys alike.
+    let keys = kvs
+        .iter()
+        .map(|(key, _)| *key)
+        .chain((20..30u32).map(|i| KeyHash::with::<H>(i.to_be_bytes())));
+    for key in keys {
+        tree.get_with_proof_into(key, 0, &mut buffer).unwrap();
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(buffer.value(), value.as_deref());
+        assert_eq!(buffer.proof(), &proof);
+        buffer
+            .proof()
+            .verify(root_hash, key, buffer.value())
+            .unwrap();
+    }
+}
+
+fn test_find_orphans<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let mut stale_node_keys = Vec::new();
+    for version in 0..3u64 {
+        let kvs = (0..10u64).map(|i| {
+            (
+                KeyHash::with::<H>((i + 5 * version).to_be_bytes()),
+                Some(vec![version as u8]),
+            )
+        });
+        let (_, batch) = tree.put_value_set(kvs, version).unwrap();
+        stale_node_keys.extend(
+            batch
+                .stale_node_index_batch
+                .iter()
+                .map(|index| index.node_key.clone()),
+        );
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    stale_node_keys.sort();
+
+    assert_eq!(tree.find_orphans(&[0, 1, 2]).unwrap(), vec![]);
+    // Without the older versions, the nodes they no longer share with the latest one are
+    // orphans, which are exactly the stale nodes.
+    assert_eq!(tree.find_orphans(&[2]).unwrap(), stale_node_keys);
+    db.purge_stale_nodes(2).unwrap();
+    assert_eq!(tree.find_orphans(&[2, 2]).unwrap(), vec![]);
+
+    let key_hash = KeyHash([7; 32]);
+    let node_key = NodeKey::new(9, NibblePath::new(key_hash.0[..1].to_vec()));
+    let leaf = LeafNode::new(key_hash, ValueHash::with::<H>([7]));
+    db.put_leaf(node_key.clone(), leaf, vec![7]).unwrap();
+    assert_eq!(tree.find_orphans(&[2]).unwrap(), vec![node_key]);
+
+    let err = tree.find_orphans(&[1]).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<MissingRootError>()
+            .map(|err| err.version),
+        Some(1)
+    );
+}
+
+fn test_prunable_stats<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    assert_eq!(tree.prunable_stats(0).unwrap(), (0, 0));
+
+    // The expected stats of pruning up to each version.
+    let mut expected = vec![(0, 0)];
+    for version in 0..4u64 {
+        let kvs = (0..10u64).map(|i| {
+            (
+                KeyHash::with::<H>((i + 5 * version).to_be_bytes()),
+                Some(vec![version as u8]),
+            )
+        });
+        let (_, batch) = tree.put_value_set(kvs, version).unwrap();
+        db.write_tree_update_batch(batch.clone()).unwrap();
+        if version > 0 {
+            let (mut num_nodes, mut num_bytes) = expected[version as usize - 1];
+            for index in batch.stale_node_index_batch {
+                num_nodes += 1;
+                num_bytes += index.node_key.to_bytes().len()
+                    + db.get_node(&index.node_key).unwrap().to_bytes().len();
+            }
+            expected.push((num_nodes, num_bytes));
+        }
+    }
+
+    for (version, stats) in expected.iter().enumerate() {
+        assert_eq!(tree.prunable_stats(version as Version).unwrap(), *stats);
+    }
+    assert_eq!(tree.prunable_stats(10).unwrap(), expected[3]);
+
+    // Pruning frees exactly what was announced.
+    let num_nodes = db.num_nodes();
+    db.purge_stale_nodes(2).unwrap();
+    assert_eq!(db.num_nodes(), num_nodes - expected[2].0);
+    assert_eq!(tree.prunable_stats(2).unwrap(), (0, 0));
+    assert_eq!(
+        tree.prunable_stats(3).unwrap(),
+        (expected[3].0 - expected[2].0, expected[3].1 - expected[2].1)
+    );
+}
+
+fn test_write_update_batch_atomic<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key1 = KeyHash::with::<H>(b"key1");
+    let key2 = KeyHash::with::<H>(b"key2");
+
+    // A batch covering several versions is committed in one write.
+    let (root_hashes, batch) = tree
+        .put_value_sets(
+            vec![
+                vec![(key1, Some(vec![1u8]))],
+                vec![(key2, Some(vec![2u8]))],
+                vec![(key1, Some(vec![3u8]))],
+            ],
+            0,
+        )
+        .unwrap();
+    let num_stale = batch.stale_node_index_batch.len();
+    db.write_update_batch_atomic(batch).unwrap();
+    for (version, root_hash) in root_hashes.iter().enumerate() {
+        assert_eq!(tree.get_root_hash(version as Version).unwrap(), *root_hash);
+    }
+    assert_eq!(tree.get(key1, 2).unwrap(), Some(vec![3u8]));
+    assert_eq!(tree.get(key2, 2).unwrap(), Some(vec![2u8]));
+    assert_eq!(db.stale_versions().unwrap(), vec![1, 2]);
+    assert_eq!(
+        db.get_stale_nodes_since(1).unwrap().len() + db.get_stale_nodes_since(2).unwrap().len(),
+        num_stale
+    );
+
+    // A batch that cannot be written leaves the store unchanged.
+    let (_, batch) = tree
+        .put_value_set(vec![(key2, Some(vec![4u8]))], 3)
+        .unwrap();
+    let mut bad_batch = batch.clone();
+    bad_batch.node_batch.insert_value(1, key1, vec![5u8]);
+    let num_nodes = db.num_nodes();
+    assert!(db.write_update_batch_atomic(bad_batch).is_err());
+    assert_eq!(db.num_nodes(), num_nodes);
+    assert_eq!(db.stale_versions().unwrap(), vec![1, 2]);
+    assert!(tree.get_root_hash(3).is_err());
+
+    db.write_update_batch_atomic(batch).unwrap();
+    assert_eq!(tree.get(key2, 3).unwrap(), Some(vec![4u8]));
+    assert_eq!(db.stale_versions().unwrap(), vec![1, 2, 3]);
+}
+
+fn test_get_value_option_limited<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key1 = KeyHash::with::<H>(b"key1");
+    let key2 = KeyHash::with::<H>(b"key2");
+    let (_, batch) = tree
+        .put_value_sets(
+            vec![
+                vec![(key1, Some(vec![1u8; 10]))],
+                vec![(key1, Some(vec![1u8; 100]))],
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    assert_eq!(
+        db.get_value_option_limited(0, key1, 10).unwrap(),
+        Some(vec![1u8; 10])
+    );
+    assert_eq!(db.get_value_option_limited(1, key2, 0).unwrap(), None);
+    let err = db.get_value_option_limited(1, key1, 99).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ValueTooLarge>(),
+        Some(&ValueTooLarge {
+            key_hash: key1,
+            max_version: 1,
+            size: 100,
+            max_bytes: 99,
+        })
+    );
+
+    // Values in an overlay are checked as well, and shadow those of the base.
+    let mut overlay = NodeBatch::default();
+    overlay.insert_value(2, key2, vec![2u8; 20]);
+    let reader = OverlayReader::new(&db, &overlay);
+    assert!(reader.get_value_option_limited(2, key2, 19).is_err());
+    assert_eq!(
+        reader.get_value_option_limited(2, key2, 20).unwrap(),
+        Some(vec![2u8; 20])
+    );
+    assert!(reader.get_value_option_limited(2, key1, 99).is_err());
+}
+
+fn test_build_membership_filter<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    assert!(tree.build_membership_filter(0).is_err());
+
+    let key_hashes: Vec<KeyHash> = (0..1000u64)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let (_, batch) = tree
+        .put_value_sets(
+            vec![
+                key_hashes[..500]
+                    .iter()
+                    .map(|key_hash| (*key_hash, Some(vec![1u8])))
+                    .collect::<Vec<_>>(),
+                key_hashes[500..]
+                    .iter()
+                    .map(|key_hash| (*key_hash, Some(vec![2u8])))
+                    .collect(),
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let filter = tree.build_membership_filter(0).unwrap();
+    assert_eq!(filter.version(), 0);
+    assert!(key_hashes[..500]
+        .iter()
+        .all(|key_hash| filter.might_contain(*key_hash)));
+    // The keys added at version 1 are absent from the filter of version 0, except for false
+    // positives.
+    let false_positives = key_hashes[500..]
+        .iter()
+        .filter(|key_hash| filter.might_contain(**key_hash))
+        .count();
+    assert!(false_positives < 25);
+
+    let filter = tree.build_membership_filter(1).unwrap();
+    assert!(key_hashes
+        .iter()
+        .all(|key_hash| filter.might_contain(*key_hash)));
+}
+
+fn test_created_node_keys<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(
+            (0..10u8).map(|i| (KeyHash::with::<H>([i]), Some(vec![i]))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (_, batch) = tree
+        .put_value_set(vec![(KeyHash::with::<H>([0u8]), Some(vec![10u8]))], 1)
+        .unwrap();
+    let created_node_keys: Vec<&NodeKey> = batch.created_node_keys().collect();
+    assert_eq!(
+        created_node_keys,
+        batch.node_batch.nodes().keys().collect::<Vec<_>>()
+    );
+    assert!(created_node_keys.contains(&&NodeKey::new_empty_path(1)));
+    assert!(created_node_keys
+        .iter()
+        .all(|node_key| node_key.version() == 1));
+}
+
+// Stores the subtree of `kvs` under `prefix` in `db` at version 0, laid out as in a tree built
+// from more keys, and returns the hash of its root.
+fn put_subtree<H: SimpleHasher>(
+    db: &MockTreeStore,
+    prefix: &[u8],
+    kvs: &[(KeyHash, OwnedValue)],
+) -> (NibblePath, RootHash) {
+    let nibble_path: NibblePath = prefix.iter().map(|nibble| Nibble::from(*nibble)).collect();
+    let subtree_db = MockTreeStore::default();
+    let (_, batch) = JellyfishMerkleTree::<_, H>::new(&subtree_db)
+        .put_value_set(
+            kvs.iter().map(|(key, value)| (*key, Some(value.clone()))),
+            0,
+        )
+        .unwrap();
+    subtree_db.write_tree_update_batch(batch).unwrap();
+
+    let subtree_root_key = NodeKey::new(0, nibble_path.clone());
+    let mut node_batch = NodeBatch::default();
+    for (key, value) in kvs {
+        node_batch.insert_value(0, *key, value.clone());
+    }
+    match subtree_db.get_node(&NodeKey::new_empty_path(0)).unwrap() {
+        // A single leaf is at the root of its own tree.
+        Node::Leaf(leaf_node) => {
+            node_batch.insert_node(subtree_root_key.clone(), leaf_node.into());
+        }
+        _ => {
+            for node_key in subtree_db.get_node_keys().unwrap() {
+                if node_key.nibble_path().num_nibbles() >= prefix.len()
+                    && node_key
+                        .nibble_path()
+                        .nibbles()
+                        .take(prefix.len())
+                        .eq(nibble_path.nibbles())
+                {
+                    let node = subtree_db.get_node(&node_key).unwrap();
+                    node_batch.insert_node(node_key, node);
+                }
+            }
+        }
+    }
+    let root_hash = RootHash(node_batch.get_node(&subtree_root_key).unwrap().hash::<H>());
+    db.write_node_batch(&node_batch).unwrap();
+    (nibble_path, root_hash)
+}
+
+fn test_merge_disjoint<H: SimpleHasher>() {
+    let kvs: Vec<(KeyHash, OwnedValue)> = (0..300u32)
+        .map(|i| (KeyHash::with::<H>(i.to_be_bytes()), vec![i as u8]))
+        // Keys under 0x4 and 0x5 are chosen below.
+        .filter(|(key, _)| !(0x40..0x60).contains(&key.0[0]))
+        .chain(
+            [[0x30; 32], [0x41; 32], [0x42; 32], [0x51; 32]]
+                .into_iter()
+                .map(|key| (KeyHash(key), vec![0u8])),
+        )
+        .collect();
+    let under = |prefix: &[u8]| -> Vec<(KeyHash, OwnedValue)> {
+        kvs.iter()
+            .filter(|(key, _)| {
+                NibblePath::new(key.0.to_vec())
+                    .nibbles()
+                    .take(prefix.len())
+                    .eq(prefix.iter().map(|nibble| Nibble::from(*nibble)))
+            })
+            .cloned()
+            .collect()
+    };
+    let expected_root_hash = |kvs: &[(KeyHash, OwnedValue)]| {
+        JellyfishMerkleTree::<_, H>::new(&MockTreeStore::default())
+            .put_value_set(
+                kvs.iter().map(|(key, value)| (*key, Some(value.clone()))),
+                0,
+            )
+            .unwrap()
+            .0
+    };
+
+    // Prefixes of different lengths, including subtrees made of a single leaf that has siblings
+    // (under 0x4), or has none and is lifted (under 0x5).
+    let mut prefixes: Vec<Vec<u8>> = (0..16u8)
+        .filter(|n| ![3, 4, 5].contains(n))
+        .map(|n| vec![n])
+        .collect();
+    prefixes.extend((0..16u8).map(|n| vec![3, n]));
+    prefixes.extend([vec![4, 1], vec![4, 2], vec![5, 1]]);
+    prefixes.retain(|prefix| !under(prefix).is_empty());
+
+    let db = MockTreeStore::default();
+    let trees: Vec<(NibblePath, RootHash)> = prefixes
+        .iter()
+        .map(|prefix| put_subtree::<H>(&db, prefix, &under(prefix)))
+        .collect();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hash, batch) = tree.merge_disjoint(&trees, 0).unwrap();
+    batch.validate().unwrap();
+    assert_eq!(root_hash, expected_root_hash(&kvs));
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+    for (key, value) in &kvs {
+        let (found, proof) = tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(found.as_ref(), Some(value));
+        proof.verify_existence(root_hash, *key, value).unwrap();
+    }
+
+    // A single subtree, lifted up to the root.
+    let db = MockTreeStore::default();
+    let single = under(&[5, 1]);
+    let trees = [put_subtree::<H>(&db, &[5, 1], &single)];
+    let (root_hash, _) = JellyfishMerkleTree::<_, H>::new(&db)
+        .merge_disjoint(&trees, 0)
+        .unwrap();
+    assert_eq!(root_hash, expected_root_hash(&single));
+
+    // Overlapping prefixes, and wrong root hashes.
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let wide = put_subtree::<H>(&db, &[4], &under(&[4]));
+    let narrow_prefix = NibblePath::new(vec![0x41]);
+    let narrow_hash = db
+        .get_node(&NodeKey::new(0, narrow_prefix.clone()))
+        .unwrap()
+        .hash::<H>();
+    let narrow = (narrow_prefix, RootHash(narrow_hash));
+    assert!(tree
+        .merge_disjoint(core::slice::from_ref(&narrow), 0)
+        .is_ok());
+    assert!(tree.merge_disjoint(&[wide.clone(), narrow], 0).is_err());
+    assert!(tree
+        .merge_disjoint(&[wide.clone(), wide.clone()], 0)
+        .is_err());
+    assert!(tree
+        .merge_disjoint(&[(wide.0, RootHash([0; 32]))], 0)
+        .is_err());
+}
+
+fn test_commit<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key1 = KeyHash::with::<H>(b"key1");
+    let key2 = KeyHash::with::<H>(b"key2");
+    let (root_hashes, batch) = tree
+        .put_value_sets(
+            vec![vec![(key1, Some(vec![1u8]))], vec![(key2, Some(vec![2u8]))]],
+            0,
+        )
+        .unwrap();
+
+    // The batch covers two versions.
+    assert!(db.commit(root_hashes[..1].to_vec(), batch.clone()).is_err());
+    assert_eq!(db.num_nodes(), 0);
+
+    let committed_roots = db.commit(root_hashes.clone(), batch).unwrap();
+    assert_eq!(
+        committed_roots
+            .iter()
+            .map(CommittedRoot::root_hash)
+            .collect::<Vec<_>>(),
+        root_hashes
+    );
+    for (version, committed_root) in committed_roots.iter().enumerate() {
+        assert_eq!(
+            tree.get_root_hash(version as Version).unwrap(),
+            committed_root.root_hash()
+        );
+    }
+}
+
+fn test_get_multiproof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let keys: Vec<KeyHash> = (0..200u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let (root_hash, batch) = tree
+        .put_value_set(
+            keys[..100]
+                .iter()
+                .enumerate()
+                .map(|(i, key)| (*key, Some(vec![i as u8]))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Half of the keys are present, half absent, and one is requested twice.
+    let mut requested: Vec<KeyHash> = keys.iter().step_by(5).cloned().collect();
+    requested.push(keys[0]);
+    let multiproof = tree.get_multiproof(&requested, 0).unwrap();
+    let items: Vec<(KeyHash, Option<Vec<u8>>)> = keys
+        .iter()
+        .enumerate()
+        .step_by(5)
+        .map(|(i, key)| (*key, (i < 100).then(|| vec![i as u8])))
+        .rev()
+        .collect();
+    multiproof.verify_multiproof(root_hash, &items).unwrap();
+
+    // Siblings shared by several paths are stored once.
+    let num_independent_siblings: usize = items
+        .iter()
+        .map(|(key, _)| tree.get_with_proof(*key, 0).unwrap().1.siblings().len())
+        .sum();
+    assert!(multiproof.num_siblings() < num_independent_siblings);
+
+    let decoded: SparseMerkleMultiproof<H> =
+        borsh::from_slice(&borsh::to_vec(&multiproof).unwrap()).unwrap();
+    assert_eq!(decoded, multiproof);
+
+    // Wrong values, presence, keys or roots are rejected.
+    let mut wrong_value = items.clone();
+    wrong_value[0].1 = Some(vec![0xff]);
+    assert!(multiproof
+        .verify_multiproof(root_hash, &wrong_value)
+        .is_err());
+    let mut wrong_presence = items.clone();
+    let absent = wrong_presence.iter().position(|(_, value)| value.is_none());
+    wrong_presence[absent.unwrap()].1 = Some(vec![0]);
+    assert!(multiproof
+        .verify_multiproof(root_hash, &wrong_presence)
+        .is_err());
+    assert!(multiproof
+        .verify_multiproof(root_hash, &items[1..])
+        .is_err());
+    let mut wrong_key = items.clone();
+    wrong_key[0].0 = keys[1];
+    assert!(multiproof.verify_multiproof(root_hash, &wrong_key).is_err());
+    assert!(multiproof
+        .verify_multiproof(RootHash([0; 32]), &items)
+        .is_err());
+
+    // Trees with a single leaf or none.
+    for num_keys in [0, 1] {
+        let db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::<_, H>::new(&db);
+        let (root_hash, batch) = tree
+            .put_value_set(
+                keys[..num_keys].iter().map(|key| (*key, Some(vec![1u8]))),
+                0,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let multiproof = tree.get_multiproof(&keys[..3], 0).unwrap();
+        let items: Vec<(KeyHash, Option<Vec<u8>>)> = keys[..3]
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (*key, (i < num_keys).then(|| vec![1u8])))
+            .collect();
+        multiproof.verify_multiproof(root_hash, &items).unwrap();
+    }
+}
+
+fn test_get_adjacent_proof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let mut keys: Vec<KeyHash> = (0..100u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    keys.sort();
+    let (root_hash, batch) = tree
+        .put_value_set(
+            keys.iter()
+                .enumerate()
+                .map(|(i, key)| (*key, Some(vec![i as u8]))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let items = |range: core::ops::Range<usize>| -> Vec<(KeyHash, Option<Vec<u8>>)> {
+        range.map(|i| (keys[i], Some(vec![i as u8]))).collect()
+    };
+
+    // A window of adjacent keys, including the smallest and the largest ones.
+    for window in [0..8, 40..49, 92..100, 0..100] {
+        let proof = tree.get_adjacent_proof(&keys[window.clone()], 0).unwrap();
+        assert_eq!(
+            proof,
+            tree.get_multiproof(&keys[window.clone()], 0).unwrap()
+        );
+        proof.verify_multiproof(root_hash, &items(window)).unwrap();
+    }
+
+    // Keys that are not adjacent get a multiproof.
+    let spread: Vec<KeyHash> = keys.iter().step_by(7).cloned().collect();
+    assert_eq!(
+        tree.get_adjacent_proof(&spread, 0).unwrap(),
+        tree.get_multiproof(&spread, 0).unwrap()
+    );
+    let with_absent = [keys[10], keys[11], KeyHash([0xff; 32])];
+    assert_eq!(
+        tree.get_adjacent_proof(&with_absent, 0).unwrap(),
+        tree.get_multiproof(&with_absent, 0).unwrap()
+    );
+    assert!(tree.get_adjacent_proof(&[keys[2], keys[1]], 0).is_err());
+}
+
+fn test_put_value_set_iter<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Unsorted updates with deletions, and a key updated twice.
+    let mut updates: Vec<(KeyHash, Option<OwnedValue>)> = keys
+        .iter()
+        .enumerate()
+        .rev()
+        .step_by(3)
+        .map(|(i, key)| (*key, (i % 2 == 0).then(|| vec![i as u8])))
+        .collect();
+    updates.push((keys[1], Some(vec![0xff])));
+    let expected = tree.put_value_set(updates.clone(), 1).unwrap();
+    assert_eq!(
+        tree.put_value_set_iter(updates.clone().into_iter(), 1)
+            .unwrap(),
+        expected
+    );
+
+    let mut sorted_updates = updates;
+    sorted_updates.remove(sorted_updates.len() - 2);
+    sorted_updates.sort_by_key(|(key, _)| *key);
+    assert_eq!(
+        tree.put_value_set_sorted(sorted_updates.clone().into_iter(), 1)
+            .unwrap(),
+        expected
+    );
+
+    // Sorted updates are applied as they are pulled: when the first one fails, the others are
+    // never pulled.
+    let reader = FaultyReader {
+        store: &db,
+        node_key: NodeKey::new_empty_path(0),
+        fail: true,
+    };
+    let pulled = Cell::new(0);
+    let updates = sorted_updates
+        .into_iter()
+        .inspect(|_| pulled.set(pulled.get() + 1));
+    assert!(JellyfishMerkleTree::<_, H>::new(&reader)
+        .put_value_set_sorted(updates, 1)
+        .is_err());
+    assert_eq!(pulled.get(), 1);
+}
+
+fn test_get_closest_leaf<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        tree.get_closest_leaf(KeyHash([0; 32]), 0, Direction::Above)
+            .unwrap(),
+        None
+    );
+
+    let keys: BTreeSet<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let closest_key = |target: KeyHash, direction: Direction| {
+        tree.get_closest_leaf(target, 1, direction)
+            .unwrap()
+            .map(|leaf| leaf.key_hash())
+    };
+    let targets = (0..50u32)
+        .map(|i| KeyHash::with::<H>((i + 1000).to_be_bytes()))
+        .chain(keys.iter().cloned())
+        .chain([KeyHash([0; 32]), KeyHash([0xff; 32])]);
+    for target in targets {
+        assert_eq!(
+            closest_key(target, Direction::Below),
+            keys.range(..=target).next_back().cloned()
+        );
+        assert_eq!(
+            closest_key(target, Direction::Above),
+            keys.range(target..).next().cloned()
+        );
+    }
+}
+
+#[cfg(feature = "access_histogram")]
+fn test_access_counting_reader<H: SimpleHasher>() {
+    use crate::storage::AccessCountingReader;
+
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let reader = AccessCountingReader::new(&db);
+    assert!(reader.access_histogram().is_empty());
+    let counted_tree = JellyfishMerkleTree::<_, H>::new(&reader);
+    for key in &keys {
+        let (value, _) = counted_tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(value, Some(key.0.to_vec()));
+    }
+    let histogram = reader.access_histogram();
+    assert_eq!(histogram[&NodeKey::new_empty_path(0)], keys.len() as u64);
+    assert!(histogram
+        .keys()
+        .all(|node_key| db.get_node(node_key).is_ok()));
+    assert!(histogram.values().sum::<u64>() > keys.len() as u64);
+
+    reader.reset_access_histogram();
+    assert!(reader.access_histogram().is_empty());
+}
+
+fn test_proof_from_sibling_hashes<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let sibling_hashes = |proof: &SparseMerkleProof<H>| -> Vec<[u8; 32]> {
+        proof
+            .siblings()
+            .iter()
+            .map(|sibling| sibling.hash::<H>())
+            .collect()
+    };
+    for key in &keys {
+        let (_, proof) = tree.get_with_proof(*key, 0).unwrap();
+        let rebuilt = SparseMerkleProof::<H>::new(proof.leaf(), sibling_hashes(&proof));
+        assert_eq!(rebuilt.root_hash(), root_hash);
+        rebuilt.verify_existence(root_hash, *key, key.0).unwrap();
+
+        let mut tampered_siblings = sibling_hashes(&proof);
+        tampered_siblings[0][0] ^= 1;
+        assert!(SparseMerkleProof::<H>::new(proof.leaf(), tampered_siblings)
+            .verify_existence(root_hash, *key, key.0)
+            .is_err());
+    }
+
+    let absent_key = KeyHash::with::<H>(b"absent");
+    let (_, proof) = tree.get_with_proof(absent_key, 0).unwrap();
+    SparseMerkleProof::<H>::new(proof.leaf(), sibling_hashes(&proof))
+        .verify_nonexistence(root_hash, absent_key)
+        .unwrap();
+
+    // The type of opaque siblings is unknown, so deletions cannot be verified against them.
+    let (_, proof) = tree.get_with_proof(keys[0], 0).unwrap();
+    let rebuilt = SparseMerkleProof::<H>::new(proof.leaf(), sibling_hashes(&proof));
+    let (new_root_hash, _) = tree.put_value_set(vec![(keys[0], None)], 1).unwrap();
+    assert!(UpdateMerkleProof::new(vec![proof])
+        .verify_update(root_hash, new_root_hash, vec![(keys[0], None::<Vec<u8>>)])
+        .is_ok());
+    assert!(UpdateMerkleProof::new(vec![rebuilt])
+        .verify_update(root_hash, new_root_hash, vec![(keys[0], None::<Vec<u8>>)])
+        .is_err());
+}
+
+fn test_try_get_with_proof<H: SimpleHasher>() {
+    let key1 = KeyHash::with::<H>("key1");
+    let key2 = update_nibble(&key1, 0, (key1.0[0] >> 4) ^ 1);
+    let absent_key = update_nibble(&key1, 63, (key1.0[31] & 0x0f) ^ 1);
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(vec![(key1, Some(vec![1])), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    match tree.try_get_with_proof(key1, 0).unwrap() {
+        GetProofOutcome::Found { value, proof } => {
+            assert_eq!(value, vec![1]);
+            proof.verify_existence(root_hash, key1, value).unwrap();
+        }
+        outcome => panic!("Unexpected outcome {:?}.", outcome),
+    }
+    match tree.try_get_with_proof(absent_key, 0).unwrap() {
+        GetProofOutcome::NotFound { proof } => {
+            proof.verify_nonexistence(root_hash, absent_key).unwrap();
+        }
+        outcome => panic!("Unexpected outcome {:?}.", outcome),
+    }
+
+    let root_key = NodeKey::new_empty_path(0);
+    let leaf_key = root_key.gen_child_node_key(0, Nibble::from(key1.0[0] >> 4));
+    let reader = |node_key: &NodeKey, fail| FaultyReader {
+        store: &db,
+        node_key: node_key.clone(),
+        fail,
+    };
+
+    // The leaf of `key1` is on the path to `key1`, and is the only leaf in the sibling subtree of
+    // `key2`, which the proof of `key2` holds in full.
+    let missing_leaf = reader(&leaf_key, false);
+    let partial_tree = JellyfishMerkleTree::<_, H>::new(&missing_leaf);
+    for key in [key1, key2] {
+        assert!(matches!(
+            partial_tree.try_get_with_proof(key, 0).unwrap(),
+            GetProofOutcome::Incomplete { missing } if missing == leaf_key
+        ));
+    }
+
+    let missing_root = reader(&root_key, false);
+    assert!(matches!(
+        JellyfishMerkleTree::<_, H>::new(&missing_root)
+            .try_get_with_proof(key2, 0)
+            .unwrap(),
+        GetProofOutcome::Incomplete { missing } if missing == root_key
+    ));
+
+    // Failures of the storage backend are still errors.
+    let failing_leaf = reader(&leaf_key, true);
+    assert!(JellyfishMerkleTree::<_, H>::new(&failing_leaf)
+        .try_get_with_proof(key1, 0)
+        .is_err());
+}
+
+fn test_storage_size<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0; 10]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let not_cancelled = AtomicBool::new(false);
+    let node_bytes: usize = db
+        .get_node_keys()
+        .unwrap()
+        .iter()
+        .map(|node_key| node_key.to_bytes().len() + db.get_node(node_key).unwrap().to_bytes().len())
+        .sum();
+    assert_eq!(
+        tree.storage_size(0, &not_cancelled).unwrap(),
+        Some(StorageSize {
+            node_bytes,
+            value_bytes: 500,
+        })
+    );
+
+    let (_, batch) = tree
+        .put_value_set(vec![(keys[0], Some(vec![0; 100])), (keys[1], None)], 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let size = tree.storage_size(1, &not_cancelled).unwrap().unwrap();
+    assert_eq!(size.value_bytes, 500 + 90 - 10);
+    assert!(size.node_bytes < node_bytes);
+
+    assert_eq!(tree.storage_size(0, &AtomicBool::new(true)).unwrap(), None);
+    assert!(tree
+        .storage_size(2, &not_cancelled)
+        .unwrap_err()
+        .downcast_ref::<MissingRootError>()
+        .is_some());
+}
+
+fn test_without_stale_tracking<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let updates = vec![(keys[0], Some(vec![1])), (keys[1], None)];
+    let (root_hash, batch) = tree.put_value_set(updates.clone(), 1).unwrap();
+    assert!(!batch.stale_node_index_batch.is_empty());
+
+    let ephemeral_tree = JellyfishMerkleTree::<_, H>::new(&db).without_stale_tracking();
+    let (ephemeral_root_hash, ephemeral_batch) = ephemeral_tree.put_value_set(updates, 1).unwrap();
+    assert_eq!(ephemeral_root_hash, root_hash);
+    assert_eq!(ephemeral_batch.node_batch, batch.node_batch);
+    assert!(ephemeral_batch.stale_node_index_batch.is_empty());
+    assert_eq!(ephemeral_batch.node_stats[0].stale_nodes, 0);
+    assert_eq!(ephemeral_batch.node_stats[0].stale_leaves, 0);
+    assert_eq!(
+        ephemeral_batch.node_stats[0].new_nodes,
+        batch.node_stats[0].new_nodes
+    );
+}
+
+fn test_path_versions<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    for version in 1..4 {
+        let (_, batch) = tree
+            .put_value_set(vec![(keys[version as usize], Some(vec![1]))], version)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    for (i, key) in keys.iter().enumerate() {
+        let path = tree.path_versions(*key, 3).unwrap();
+        assert_eq!(path[0], (NodeKey::new_empty_path(3), 3));
+        assert!(path
+            .iter()
+            .all(|(node_key, version)| node_key.version() == *version));
+        assert!(path.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+        let (leaf_key, leaf_version) = path.last().unwrap();
+        let Node::Leaf(leaf_node) = db.get_node(leaf_key).unwrap() else {
+            panic!("Path to {:?} does not end at a leaf.", key);
+        };
+        assert_eq!(leaf_node.key_hash(), *key);
+        let expected_version = if (1..4).contains(&i) { i as Version } else { 0 };
+        assert!(*leaf_version >= expected_version);
+    }
+
+    assert!(tree
+        .path_versions(keys[0], 4)
+        .unwrap_err()
+        .downcast_ref::<MissingRootError>()
+        .is_some());
+}
+
+fn test_verify_against_bytes<H: SimpleHasher>() {
+    let key = KeyHash::with::<H>("key");
+    let absent_key = KeyHash::with::<H>("absent");
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hash, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+    proof
+        .verify_against_bytes(&root_hash.0, key, value.as_ref())
+        .unwrap();
+    assert!(proof
+        .verify_against_bytes(&[0; 32], key, value.as_ref())
+        .is_err());
+
+    let (value, proof) = tree.get_with_proof(absent_key, 0).unwrap();
+    assert_eq!(value, None);
+    proof
+        .verify_against_bytes(&root_hash.0, absent_key, None::<Vec<u8>>)
+        .unwrap();
+}
+
+fn test_what_if_root<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let initial_set: Vec<_> = keys
+        .iter()
+        .map(|key| (*key, Some(key.0.to_vec())))
+        .collect();
+    let (root_hash, batch) = tree.put_value_set(initial_set.clone(), 0).unwrap();
+    assert_eq!(tree.what_if_root(initial_set, 0).unwrap(), root_hash);
+    db.write_tree_update_batch(batch).unwrap();
+
+    let updates = vec![
+        (keys[0], Some(vec![1])),
+        (keys[1], None),
+        (KeyHash::with::<H>("new"), Some(vec![2])),
+    ];
+    let num_nodes = db.num_nodes();
+    let root_hash = tree.what_if_root(updates.clone(), 1).unwrap();
+    assert_eq!(db.num_nodes(), num_nodes);
+    assert_eq!(tree.put_value_set(updates, 1).unwrap().0, root_hash);
+    assert_eq!(
+        tree.what_if_root(vec![], 1).unwrap(),
+        tree.get_root_hash(0).unwrap()
+    );
+}
+
+fn test_proof_steps<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let keys: Vec<KeyHash> = (0..20u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    for key in keys {
+        let (_, proof) = tree.get_with_proof(key, 0).unwrap();
+        let steps = proof.steps(key);
+        assert_eq!(steps.len(), proof.siblings().len());
+
+        // The steps alone are enough to walk back from the leaf to the root.
+        let leaf_hash = proof.leaf().unwrap().hash::<H>();
+        let computed_root = steps.iter().rev().fold(leaf_hash, |hash, step| {
+            if step.bit {
+                SparseMerkleInternalNode::new(step.sibling, hash).hash::<H>()
+            } else {
+                SparseMerkleInternalNode::new(hash, step.sibling).hash::<H>()
+            }
+        });
+        assert_eq!(RootHash(computed_root), root_hash);
+
+        for (depth, step) in steps.iter().enumerate() {
+            assert_eq!(step.nibble, key.0.get_nibble(depth / 4));
+            assert_eq!(
+                step.sibling_is_leaf,
+                matches!(
+                    proof.siblings()[steps.len() - 1 - depth],
+                    SparseMerkleNode::Leaf(_)
+                )
+            );
+        }
+    }
+}
+
+fn test_compare_and_swap<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key = KeyHash::with::<H>("key");
+    let other_key = KeyHash::with::<H>("other");
+
+    // Expecting the key to be absent succeeds on an empty tree.
+    let (root_hash, swapped, batch) = tree.compare_and_swap(key, None, Some(vec![1]), 0).unwrap();
+    assert!(swapped);
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+
+    // A mismatch leaves the tree unchanged, but still commits the version.
+    let (root_hash, swapped, batch) = tree.compare_and_swap(key, None, Some(vec![2]), 1).unwrap();
+    assert!(!swapped);
+    assert_eq!(root_hash, tree.get_root_hash(0).unwrap());
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(key, 1).unwrap(), Some(vec![1]));
+
+    let (_, swapped, batch) = tree
+        .compare_and_swap(key, Some(ValueHash::with::<H>([1]).0), Some(vec![2]), 2)
+        .unwrap();
+    assert!(swapped);
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(key, 2).unwrap(), Some(vec![2]));
+
+    let (_, swapped, batch) = tree
+        .compare_and_swap(
+            other_key,
+            Some(ValueHash::with::<H>([2]).0),
+            Some(vec![3]),
+            3,
+        )
+        .unwrap();
+    assert!(!swapped);
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(other_key, 3).unwrap(), None);
+
+    // Swapping to `None` deletes the key.
+    let (root_hash, swapped, batch) = tree
+        .compare_and_swap(key, Some(ValueHash::with::<H>([2]).0), None, 4)
+        .unwrap();
+    assert!(swapped);
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(root_hash, RootHash::EMPTY);
+    assert_eq!(tree.get(key, 4).unwrap(), None);
+}
+
+fn test_get_value_bounded_staleness<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key = KeyHash::with::<H>("key");
+    for version in 0..4u8 {
+        let (_, batch) = tree
+            .put_value_set(vec![(key, Some(vec![version]))], version.into())
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    assert_eq!(
+        tree.get_value_bounded_staleness(key, 3, 0).unwrap(),
+        (Some(vec![3]), 3)
+    );
+    assert_eq!(
+        tree.get_value_bounded_staleness(key, 3, 2).unwrap(),
+        (Some(vec![1]), 1)
+    );
+    assert_eq!(
+        tree.get_value_bounded_staleness(key, 3, 10).unwrap(),
+        (Some(vec![0]), 0)
+    );
+
+    // Falls back to the latest version when the older root is not available.
+    db.purge_stale_nodes(1).unwrap();
+    assert_eq!(
+        tree.get_value_bounded_staleness(key, 3, 3).unwrap(),
+        (Some(vec![3]), 3)
+    );
+
+    // Only the first read at a version reads its root from storage.
+    use crate::storage::RecordingReader;
+    let cache = RootHashCache::new(4);
+    let reader = RecordingReader::new(&db);
+    let recording_tree = JellyfishMerkleTree::<_, H>::new(&reader);
+    assert_eq!(
+        recording_tree
+            .get_value_bounded_staleness_cached(key, 3, 3, &cache)
+            .unwrap(),
+        (Some(vec![3]), 3)
+    );
+    assert_eq!(
+        recording_tree
+            .get_value_bounded_staleness_cached(key, 3, 2, &cache)
+            .unwrap(),
+        (Some(vec![1]), 1)
+    );
+    assert_eq!(
+        cache.cached_root_hash(1),
+        Some(tree.get_root_hash(1).unwrap())
+    );
+    assert_eq!(reader.into_read_set().len(), 2);
+    let reader = RecordingReader::new(&db);
+    assert_eq!(
+        JellyfishMerkleTree::<_, H>::new(&reader)
+            .get_value_bounded_staleness_cached(key, 3, 2, &cache)
+            .unwrap(),
+        (Some(vec![1]), 1)
+    );
+    assert!(reader.into_read_set().is_empty());
+}
+
+fn test_roots_equal<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key = KeyHash::with::<H>("key");
+    let value_sets = vec![
+        vec![(key, Some(vec![1]))],
+        vec![],
+        vec![(key, Some(vec![2]))],
+        vec![(key, Some(vec![1]))],
+    ];
+    let (_, batch) = tree.put_value_sets(value_sets, 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    assert!(tree.roots_equal(0, 0).unwrap());
+    assert!(tree.roots_equal(0, 1).unwrap());
+    assert!(!tree.roots_equal(1, 2).unwrap());
+    assert!(tree.roots_equal(3, 0).unwrap());
+    assert!(tree.roots_equal(0, 4).is_err());
+}
+
+fn test_rank<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key = KeyHash::with::<H>("key");
+    assert!(tree.rank(key, 0).is_err());
+
+    let (_, batch) = tree.put_value_set(vec![(key, None)], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.rank(key, 0).unwrap(), 0);
+
+    let mut keys: Vec<KeyHash> = (0..100u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    keys.sort();
+
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(tree.rank(*key, 1).unwrap(), i);
+    }
+    for i in 100..200u32 {
+        let absent_key = KeyHash::with::<H>(i.to_be_bytes());
+        let expected = keys.partition_point(|key| *key < absent_key);
+        assert_eq!(tree.rank(absent_key, 1).unwrap(), expected);
+    }
+    assert_eq!(tree.rank(KeyHash([0xff; 32]), 1).unwrap(), keys.len());
+}
+
+fn test_get_proof_only<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key = KeyHash::with::<H>("key");
+    let absent_key = KeyHash::with::<H>("absent");
+    let value = vec![7; 1024];
+    let (root_hash, batch) = tree
+        .put_value_set(
+            vec![
+                (key, Some(value.clone())),
+                (KeyHash::with::<H>("other"), Some(vec![1])),
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let proof = tree.get_proof_only(key, 0).unwrap();
+    assert_eq!(proof, tree.get_with_proof(key, 0).unwrap().1);
+    proof.verify(root_hash, key, Some(&value)).unwrap();
+    assert!(proof.verify(root_hash, key, Some(&[7])).is_err());
+
+    let proof = tree.get_proof_only(absent_key, 0).unwrap();
+    proof.verify_nonexistence(root_hash, absent_key).unwrap();
+}
+
+fn test_recording_reader<H: SimpleHasher>() {
+    use crate::storage::RecordingReader;
+
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let read_keys = [keys[3], keys[17], KeyHash::with::<H>("absent")];
+    let reader = RecordingReader::new(&db);
+    let recording_tree = JellyfishMerkleTree::<_, H>::new(&reader);
+    for key in &read_keys {
+        recording_tree.get_with_proof(*key, 0).unwrap();
+    }
+    let read_set = reader.into_read_set();
+    assert!(read_set.contains(&NodeKey::new_empty_path(0)));
+    assert!(read_set.len() < db.num_nodes());
+
+    // The nodes read are enough to prove the keys read.
+    let mut node_batch = NodeBatch::default();
+    for node_key in read_set {
+        let node = db.get_node(&node_key).unwrap();
+        node_batch.insert_node(node_key, node);
+    }
+    let read_set_db = MockTreeStore::default();
+    read_set_db.write_node_batch(&node_batch).unwrap();
+    let multiproof = JellyfishMerkleTree::<_, H>::new(&read_set_db)
+        .get_multiproof(&read_keys, 0)
+        .unwrap();
+    assert_eq!(multiproof, tree.get_multiproof(&read_keys, 0).unwrap());
+    multiproof
+        .verify_multiproof(
+            root_hash,
+            &[
+                (keys[3], Some(keys[3].0.to_vec())),
+                (keys[17], Some(keys[17].0.to_vec())),
+                (read_keys[2], None),
+            ],
+        )
+        .unwrap();
+}
+
+fn test_rehash<H: SimpleHasher>() {
+    type NewH = sha2::Sha512_256;
+
+    let keys: Vec<KeyHash> = (0..50u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let value_sets = vec![
+        keys[..30]
+            .iter()
+            .map(|key| (*key, Some(vec![0])))
+            .collect::<Vec<_>>(),
+        keys[20..]
+            .iter()
+            .map(|key| (*key, Some(key.0.to_vec())))
+            .collect(),
+        vec![(keys[0], None), (keys[40], None)],
+    ];
+    let (_, batch) = tree.put_value_sets(value_sets, 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let rehashed_db = MockTreeStore::default();
+    let root_hash = tree.rehash::<NewH, _>(&rehashed_db, 2).unwrap();
+
+    // The rehashed tree is the one built from scratch under the new hasher.
+    let kvs: Vec<_> = keys
+        .iter()
+        .map(|key| (*key, tree.get(*key, 2).unwrap()))
+        .filter(|(_, value)| value.is_some())
+        .collect();
+    assert_eq!(kvs.len(), 48);
+    let fresh_db = MockTreeStore::default();
+    let fresh_tree = JellyfishMerkleTree::<_, NewH>::new(&fresh_db);
+    let (fresh_root_hash, batch) = fresh_tree.put_value_set(kvs.clone(), 0).unwrap();
+    fresh_db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(root_hash, fresh_root_hash);
+    assert_eq!(rehashed_db.num_nodes(), fresh_db.num_nodes());
+    assert_ne!(root_hash, tree.get_root_hash(2).unwrap());
+
+    let rehashed_tree = JellyfishMerkleTree::<_, NewH>::new(&rehashed_db);
+    assert_eq!(rehashed_tree.get_root_hash(2).unwrap(), root_hash);
+    for (key, value) in kvs {
+        let (rehashed_value, proof) = rehashed_tree.get_with_proof(key, 2).unwrap();
+        assert_eq!(rehashed_value, value);
+        proof.verify(root_hash, key, value).unwrap();
+    }
+}
+
+fn test_value_proof<H: SimpleHasher>() {
+    use crate::proof::ValueProof;
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let key = KeyHash::with::<H>("key");
+    let absent_key = KeyHash::with::<H>("absent");
+    let (root_hash, batch) = tree
+        .put_value_set(
+            vec![
+                (key, Some(vec![1, 2, 3])),
+                (KeyHash::with::<H>("other"), Some(vec![4])),
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let value_proof = tree.get_value_proof(key, 0).unwrap();
+    assert_eq!(
+        value_proof.verify_and_get(root_hash, key).unwrap(),
+        Some(vec![1, 2, 3])
+    );
+    assert!(value_proof.verify_and_get(root_hash, absent_key).is_err());
+
+    let value_proof = tree.get_value_proof(absent_key, 0).unwrap();
+    assert_eq!(
+        value_proof.verify_and_get(root_hash, absent_key).unwrap(),
+        None
+    );
+
+    // A value that does not match the proof is rejected.
+    let (_, proof) = tree.get_with_proof(key, 0).unwrap();
+    let forged_proof = ValueProof::new(Some(vec![1, 2]), proof.clone());
+    assert!(forged_proof.verify_and_get(root_hash, key).is_err());
+    let forged_proof = ValueProof::new(None, proof);
+    assert!(forged_proof.verify_and_get(root_hash, key).is_err());
+}
+
+fn test_key_bounds<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    assert!(tree.key_bounds(0).is_err());
+
+    let mut keys: Vec<KeyHash> = (0..100u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let value_sets = vec![
+        vec![(keys[0], None)],
+        vec![(keys[0], Some(vec![0]))],
+        keys.iter().map(|key| (*key, Some(vec![1]))).collect(),
+    ];
+    let (_, batch) = tree.put_value_sets(value_sets, 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    keys.sort();
+
+    assert_eq!(tree.key_bounds(0).unwrap(), None);
+    let first_key = KeyHash::with::<H>(0u32.to_be_bytes());
+    assert_eq!(tree.key_bounds(1).unwrap(), Some((first_key, first_key)));
+    assert_eq!(
+        tree.key_bounds(2).unwrap(),
+        Some((keys[0], keys[keys.len() - 1]))
+    );
+}
+
+fn test_put_value_set_sorted_with_flush<H: SimpleHasher>() {
+    let keys: Vec<KeyHash> = (0..300u32)
+        .map(|i| KeyHash::with::<H>(i.to_be_bytes()))
+        .collect();
+    let base: Vec<_> = keys[..200]
+        .iter()
+        .map(|key| (*key, Some(vec![0])))
+        .collect();
+    let mut updates: BTreeMap<_, _> = keys[100..]
+        .iter()
+        .map(|key| (*key, Some(vec![1])))
+        .collect();
+    for key in &keys[..50] {
+        updates.insert(*key, None);
+    }
+
+    let expected_db = MockTreeStore::default();
+    let expected_tree = JellyfishMerkleTree::<_, H>::new(&expected_db);
+    let (_, batch) = expected_tree.put_value_set(base.clone(), 0).unwrap();
+    expected_db.write_tree_update_batch(batch).unwrap();
+    let (expected_root, batch) = expected_tree
+        .put_value_set_sorted(updates.clone().into_iter(), 1)
+        .unwrap();
+    expected_db.write_tree_update_batch(batch).unwrap();
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree.put_value_set(base, 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert!(tree
+        .put_value_set_sorted_with_flush(updates.clone().into_iter(), 1, &db, 0)
+        .is_err());
+    let (root, batch) = tree
+        .put_value_set_sorted_with_flush(updates.clone().into_iter(), 1, &db, 7)
+        .unwrap();
+    assert_eq!(root, expected_root);
+    db.write_tree_update_batch(batch).unwrap();
+
+    assert_eq!(tree.get_root_hash(1).unwrap(), expected_root);
+    for key in &keys {
+        let (value, proof) = tree.get_with_proof(*key, 1).unwrap();
+        assert_eq!(value, expected_tree.get(*key, 1).unwrap());
+        assert!(proof.verify(root, *key, value.as_ref()).is_ok());
+    }
+
+    let (low, high) = (keys[0].min(keys[1]), keys[0].max(keys[1]));
+    let unsorted = vec![(high, Some(vec![2])), (low, Some(vec![2]))];
+    assert!(tree
+        .put_value_set_sorted_with_flush(unsorted.into_iter(), 2, &db, 1)
+        .is_err());
+}
+
+fn test_put_value_set_sorted_with_flush_delete<H: SimpleHasher>() {
+    // Deleting `high` lifts the leaf of `low`, which was put right before the flush.
+    let (low, high) = (KeyHash([0x00; 32]), KeyHash([0xff; 32]));
+    let base = vec![(low, Some(vec![0])), (high, Some(vec![0]))];
+    let updates = vec![(low, Some(vec![1])), (high, None)];
+
+    let expected_db = MockTreeStore::default();
+    let expected_tree = JellyfishMerkleTree::<_, H>::new(&expected_db);
+    let (_, batch) = expected_tree.put_value_set(base.clone(), 0).unwrap();
+    expected_db.write_tree_update_batch(batch).unwrap();
+    let (expected_root, expected_batch) = expected_tree.put_value_set(updates.clone(), 1).unwrap();
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree.put_value_set(base, 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (root, batch) = tree
+        .put_value_set_sorted_with_flush(updates.into_iter(), 1, &db, 1)
+        .unwrap();
+    assert_eq!(root, expected_root);
+    assert_eq!(batch.validate(), Ok(()));
+    assert_eq!(batch.node_stats, expected_batch.node_stats);
+    assert_eq!(
+        batch.stale_node_index_batch,
+        expected_batch.stale_node_index_batch
+    );
+    db.write_tree_update_batch(batch).unwrap();
+
+    assert_eq!(tree.get_root_hash(1).unwrap(), expected_root);
+    assert_eq!(tree.get(low, 1).unwrap(), Some(vec![1]));
+    assert_eq!(tree.get(high, 1).unwrap(), None);
+}
+
+fn test_put_value_checked<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let expected_db = MockTreeStore::default();
+    let expected_tree = JellyfishMerkleTree::<_, H>::new(&expected_db);
+
+    let updates = [
+        (b"alice".as_slice(), Some(vec![1])),
+        (b"bob".as_slice(), Some(vec![2])),
+        (b"alice".as_slice(), None),
+    ];
+    for (version, (raw_key, value)) in updates.into_iter().enumerate() {
+        let version = version as Version;
+        let (root, batch) = tree
+            .put_value_checked(raw_key, value.clone(), version)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let (expected_root, batch) = expected_tree
+            .put_value_set(vec![(KeyHash::with::<H>(raw_key), value.clone())], version)
+            .unwrap();
+        expected_db.write_tree_update_batch(batch).unwrap();
+        assert_eq!(root, expected_root);
+        assert_eq!(
+            tree.get(KeyHash::with::<H>(raw_key), version).unwrap(),
+            value
+        );
+    }
+}
+
+fn test_version_count<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    assert_eq!(tree.version_count().unwrap(), 0);
+
+    // The root put by a restoration is not a committed version.
+    let mut nodes = BTreeMap::new();
+    nodes.insert(
+        NodeKey::new_empty_path(PRE_GENESIS_VERSION),
+        Node::new_null(),
+    );
+    db.write_node_batch(&NodeBatch::new(nodes, BTreeMap::new()))
+        .unwrap();
+    assert_eq!(db.get_latest_version().unwrap(), None);
+    assert_eq!(tree.version_count().unwrap(), 0);
+
+    for version in 0..3u64 {
+        let key = KeyHash::with::<H>(version.to_be_bytes());
+        let (_, batch) = tree
+            .put_value_set(vec![(key, Some(vec![1]))], version)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        assert_eq!(db.get_latest_version().unwrap(), Some(version));
+        assert_eq!(tree.version_count().unwrap(), version + 1);
+    }
+
+    // The versions of an overlay count as committed.
+    let key = KeyHash::with::<H>(3u64.to_be_bytes());
+    let (_, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 3).unwrap();
+    let overlay = OverlayReader::new(&db, &batch.node_batch);
+    assert_eq!(overlay.num_versions().unwrap(), 4);
+
+    // Pruning does not change the number of versions committed.
+    db.purge_stale_nodes(2).unwrap();
+    assert_eq!(tree.version_count().unwrap(), 3);
+
+    // A reader that does not keep track of its latest version cannot count them.
+    let reader = FaultyReader {
+        store: &db,
+        node_key: NodeKey::new_empty_path(0),
+        fail: false,
+    };
+    assert!(reader.get_latest_version().is_err());
+    assert!(JellyfishMerkleTree::<_, H>::new(&reader)
+        .version_count()
+        .is_err());
+}
+
+fn test_namespace_proof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let kvs: Vec<(KeyHash, OwnedValue)> = (0..300u32)
+        .map(|i| {
+            (
+                KeyHash::with::<H>(i.to_be_bytes()),
+                i.to_be_bytes().to_vec(),
+            )
+        })
+        .collect();
+    let (root, batch) = tree
+        .put_value_set(kvs.iter().map(|(k, v)| (*k, Some(v.clone()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let prefix = vec![Nibble::from(kvs[0].0 .0[0] >> 4)];
+    let mut items: Vec<(KeyHash, Option<OwnedValue>)> = kvs
+        .iter()
+        .filter(|(key, _)| key.0[0] >> 4 == kvs[0].0 .0[0] >> 4)
+        .map(|(key, value)| (*key, Some(value.clone())))
+        .collect();
+    assert!(items.len() >= 2);
+    let mut absent_key = kvs[0].0;
+    absent_key.0[31] ^= 1;
+    assert!(kvs.iter().all(|(key, _)| *key != absent_key));
+    items.push((absent_key, None));
+    let keys: Vec<KeyHash> = items.iter().map(|(key, _)| *key).collect();
+
+    let proof = tree.get_namespace_proof(&prefix, &keys, 0).unwrap();
+    proof.verify(root, &items).unwrap();
+    assert_eq!(
+        proof.subtree_root_hash(),
+        tree.subtree_root_hash(&prefix, 0).unwrap()
+    );
+    assert!(proof.verify_subtree_root(RootHash([0; 32])).is_err());
+    // The siblings above the subtree are the ones the keys share in a multiproof.
+    assert_eq!(
+        proof.subtree_proof().num_siblings() + 4,
+        tree.get_multiproof(&keys, 0).unwrap().num_siblings()
+    );
+
+    let mut wrong_items = items.clone();
+    wrong_items[0].1 = Some(vec![0]);
+    assert!(proof.verify_in_subtree(&wrong_items).is_err());
+
+    let (outside_key, _) = kvs
+        .iter()
+        .find(|(key, _)| key.0[0] >> 4 != kvs[0].0 .0[0] >> 4)
+        .unwrap();
+    assert!(proof
+        .verify_in_subtree(&[(*outside_key, None::<OwnedValue>)])
+        .is_err());
+    assert!(tree
+        .get_namespace_proof(&prefix, &[*outside_key], 0)
+        .is_err());
+    assert!(tree.get_namespace_proof(&prefix, &[], 0).is_err());
+
+    // The whole tree is the namespace of the empty prefix.
+    tree.get_namespace_proof(&[], &keys, 0)
+        .unwrap()
+        .verify(root, &items)
+        .unwrap();
+    // A namespace holding a single key has no subtree root of its own.
+    let full_prefix: Vec<Nibble> = NibblePath::new(kvs[0].0 .0.to_vec()).nibbles().collect();
+    assert!(tree
+        .get_namespace_proof(&full_prefix, &[kvs[0].0], 0)
+        .is_err());
+}
+
 // Implement the test suite for sha256
 impl_jellyfish_tests_for_hasher!(sha256_tests, sha2::Sha256);
 
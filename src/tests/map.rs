@@ -0,0 +1,107 @@
+use alloc::vec;
+
+use sha2::Sha256;
+
+use anyhow::Result;
+
+use crate::{
+    mock::MockTreeStore,
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{NodeBatch, TreeReader, TreeUpdateBatch, TreeWriter},
+    JellyfishMerkleTree, JmtMap, KeyHash, OwnedValue, Version,
+};
+
+#[test]
+fn test_jmt_map() {
+    let key1 = KeyHash::with::<Sha256>("key1");
+    let key2 = KeyHash::with::<Sha256>("key2");
+    let db = MockTreeStore::default();
+    let mut map = JmtMap::<_, Sha256>::new(&db).unwrap();
+    assert_eq!(map.version(), None);
+    assert_eq!(map.get(key1).unwrap(), None);
+
+    // Pending changes are read back before being committed.
+    map.insert(key1, vec![1]);
+    map.insert(key2, vec![2]);
+    assert!(map.has_pending_changes());
+    assert_eq!(map.get(key1).unwrap(), Some(vec![1]));
+    let root_hash = map.commit().unwrap();
+    assert!(!map.has_pending_changes());
+    assert_eq!(map.version(), Some(0));
+
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+
+    map.remove(key1);
+    map.insert(key2, vec![3]);
+    assert_eq!(map.get(key1).unwrap(), None);
+    map.commit().unwrap();
+    assert_eq!(map.version(), Some(1));
+    assert_eq!(tree.get(key1, 0).unwrap(), Some(vec![1]));
+    assert_eq!(tree.get(key1, 1).unwrap(), None);
+    assert_eq!(tree.get(key2, 1).unwrap(), Some(vec![3]));
+
+    // A new map would overwrite the committed versions, a map opened over them picks up from the
+    // latest one.
+    assert!(JmtMap::<_, Sha256>::new(&db).is_err());
+    let mut reopened = JmtMap::<_, Sha256>::open(&db).unwrap();
+    assert_eq!(reopened.version(), Some(1));
+    assert_eq!(reopened.get(key2).unwrap(), Some(vec![3]));
+    reopened.insert(key1, vec![4]);
+    reopened.commit().unwrap();
+    assert_eq!(tree.get(key1, 2).unwrap(), Some(vec![4]));
+}
+
+/// A store implementing only the required methods of [`TreeReader`] and [`TreeWriter`], along
+/// with [`TreeReader::get_latest_version`], which [`JmtMap`] needs.
+struct MinimalStore(MockTreeStore);
+
+impl TreeReader for MinimalStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.0.get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.0.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.0.get_rightmost_leaf()
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        self.0.get_latest_version()
+    }
+}
+
+impl TreeWriter for MinimalStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.0.write_node_batch(node_batch)
+    }
+
+    fn write_update_batch_atomic(&self, batch: TreeUpdateBatch) -> Result<()> {
+        self.0.write_update_batch_atomic(batch)
+    }
+}
+
+#[test]
+fn test_jmt_map_commit_with_minimal_store() {
+    let key = KeyHash::with::<Sha256>("key");
+    let db = MinimalStore(MockTreeStore::default());
+    let mut map = JmtMap::<_, Sha256>::new(&db).unwrap();
+    map.insert(key, vec![1]);
+    map.commit().unwrap();
+
+    // Overwriting the value marks the previous leaf and root stale.
+    map.insert(key, vec![2]);
+    let root_hash = map.commit().unwrap();
+    assert_eq!(db.0.stale_versions().unwrap(), vec![1]);
+
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    assert_eq!(tree.get_root_hash(1).unwrap(), root_hash);
+    assert_eq!(tree.get(key, 1).unwrap(), Some(vec![2]));
+}
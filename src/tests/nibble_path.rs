@@ -5,9 +5,13 @@ use alloc::vec::Vec;
 use alloc::{format, vec};
 use proptest::prelude::*;
 
-use crate::types::nibble::{
-    nibble_path::{arb_internal_nibble_path, skip_common_prefix, NibblePath},
-    Nibble,
+use crate::{
+    types::nibble::{
+        common_prefix_nibbles,
+        nibble_path::{arb_internal_nibble_path, skip_common_prefix, NibblePath},
+        Nibble, ROOT_NIBBLE_HEIGHT,
+    },
+    KeyHash,
 };
 
 #[test]
@@ -178,6 +182,21 @@ prop_compose! {
     }
 }
 
+#[test]
+fn test_common_prefix_nibbles() {
+    let key = KeyHash([0x12; 32]);
+    assert_eq!(common_prefix_nibbles(&key, &key), ROOT_NIBBLE_HEIGHT);
+
+    let mut other = key;
+    other.0[0] = 0x22;
+    assert_eq!(common_prefix_nibbles(&key, &other), 0);
+    other.0[0] = 0x13;
+    assert_eq!(common_prefix_nibbles(&key, &other), 1);
+    other = key;
+    other.0[31] = 0x13;
+    assert_eq!(common_prefix_nibbles(&key, &other), ROOT_NIBBLE_HEIGHT - 1);
+}
+
 proptest! {
     #[test]
     fn test_push(
@@ -202,6 +221,16 @@ proptest! {
         prop_assert_eq!(nibble_from_nibbles, nibble_from_nibble_path);
     }
 
+    #[test]
+    fn test_truncate(
+        mut nibble_path in any::<NibblePath>(),
+        num_nibbles in 0..=ROOT_NIBBLE_HEIGHT + 1
+    ) {
+        let nibble_path2: NibblePath = nibble_path.nibbles().take(num_nibbles).collect();
+        nibble_path.truncate(num_nibbles);
+        prop_assert_eq!(nibble_path, nibble_path2);
+    }
+
     #[test]
     fn test_last(mut nibble_path in any::<NibblePath>()) {
         let nibble1 = nibble_path.last();
@@ -11,7 +11,7 @@ use rand::rngs::OsRng;
 use sha2::Sha256;
 
 use crate::{
-    node_type::{Child, Children, InternalNode, Node, NodeKey, NodeType},
+    node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey, NodeType},
     storage::TreeReader,
     types::{
         nibble::{nibble_path::NibblePath, Nibble},
@@ -86,7 +86,8 @@ fn get_only_child_with_siblings_helper<H: SimpleHasher>(
         merkle_tree_reader,
         internal_node_key,
         i.into(),
-    );
+    )
+    .unwrap();
     (
         child,
         siblings.into_iter().map(|sib| sib.hash::<H>()).collect(),
@@ -143,6 +144,117 @@ fn test_internal_validity() {
     assert!(result.is_err());
 }
 
+proptest! {
+    #[test]
+    fn test_node_key_hash_consistent_with_eq(a in any::<NodeKey>(), b in any::<NodeKey>()) {
+        use core::hash::BuildHasher;
+        let build_hasher = hashbrown::hash_map::DefaultHashBuilder::default();
+        let hash = |node_key: &NodeKey| build_hasher.hash_one(node_key);
+        prop_assert_eq!(hash(&a), hash(&a.clone()));
+        if a == b {
+            prop_assert_eq!(hash(&a), hash(&b));
+        }
+        let mut cache = hashbrown::HashMap::new();
+        cache.insert(a.clone(), 0);
+        cache.insert(b.clone(), 1);
+        prop_assert_eq!(cache.len(), if a == b { 1 } else { 2 });
+        let rebuilt = NodeKey::new(a.version(), a.nibble_path().clone());
+        prop_assert_eq!(cache[&rebuilt], if a == b { 1 } else { 0 });
+    }
+
+    #[test]
+    fn test_node_key_bytes_roundtrip(node_key in any::<NodeKey>()) {
+        let bytes = node_key.to_bytes();
+        prop_assert_eq!(&NodeKey::from_bytes(&bytes).unwrap(), &node_key);
+        prop_assert_eq!(bytes.len(), 8 + 8 + 4 + node_key.nibble_path().bytes().len());
+        prop_assert_eq!(&bytes[..8], &node_key.version().to_le_bytes()[..]);
+    }
+
+    #[test]
+    fn test_internal_node_bytes_roundtrip(node in any::<InternalNode>()) {
+        let node = Node::from(node);
+        prop_assert_eq!(Node::from_bytes(&node.to_bytes()).unwrap(), node);
+    }
+
+    #[test]
+    fn test_children_bitmap_and_hashes(node in any::<InternalNode>()) {
+        let bitmap = node.children_bitmap();
+        let mut expected = Vec::new();
+        for i in 0..16u8 {
+            let child = node.child(Nibble::from(i));
+            prop_assert_eq!(bitmap & (1 << i) != 0, child.is_some());
+            if let Some(child) = child {
+                expected.push((Nibble::from(i), child.hash, child.is_leaf()));
+            }
+        }
+        prop_assert_eq!(node.child_hashes().collect::<Vec<_>>(), expected);
+    }
+}
+
+#[test]
+fn test_node_key_from_bytes_rejects_non_canonical() {
+    let node_key = NodeKey::new(1, NibblePath::new_odd(vec![0x12, 0x30]));
+    let bytes = node_key.to_bytes();
+    assert_eq!(
+        bytes,
+        [
+            &1u64.to_le_bytes()[..],
+            &3u64.to_le_bytes(),
+            &2u32.to_le_bytes(),
+            &[0x12, 0x30]
+        ]
+        .concat()
+    );
+
+    let mut dirty_padding = bytes.clone();
+    *dirty_padding.last_mut().unwrap() = 0x31;
+    assert!(NodeKey::from_bytes(&dirty_padding).is_err());
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(NodeKey::from_bytes(&trailing).is_err());
+    assert!(NodeKey::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn test_node_bytes() {
+    let leaf = Node::new_leaf(KeyHash([1; 32]), ValueHash([2; 32]));
+    let bytes = leaf.to_bytes();
+    assert_eq!(bytes, [&[2u8][..], &[1; 32], &[2; 32]].concat());
+    assert_eq!(Node::from_bytes(&bytes).unwrap(), leaf);
+    assert_eq!(Node::Null.to_bytes(), [0]);
+    assert_eq!(Node::from_bytes(&[0]).unwrap(), Node::Null);
+
+    let mut children = Children::new();
+    children.insert(Nibble::from(3), Child::new([3; 32], 1, NodeType::Leaf));
+    children.insert(
+        Nibble::from(4),
+        Child::new([4; 32], 2, NodeType::Internal { leaf_count: 5 }),
+    );
+    let internal = Node::new_internal(children);
+    let bytes = internal.to_bytes();
+    assert_eq!(Node::from_bytes(&bytes).unwrap(), internal);
+
+    // The encoding ends with the number of children and the leaf count.
+    let leaf_count_offset = bytes.len() - 8;
+    let mut wrong_leaf_count = bytes.clone();
+    wrong_leaf_count[leaf_count_offset] = 7;
+    assert!(Node::from_bytes(&wrong_leaf_count).is_err());
+    let mut wrong_num_children = bytes.clone();
+    wrong_num_children[leaf_count_offset - 8] = 3;
+    assert!(Node::from_bytes(&wrong_num_children).is_err());
+
+    let single_leaf = [
+        &[1u8, 1][..],
+        &[3; 32],
+        &1u64.to_le_bytes(),
+        &[0; 16],
+        &1u64.to_le_bytes(),
+        &1u64.to_le_bytes(),
+    ]
+    .concat();
+    assert!(Node::from_bytes(&single_leaf).is_err());
+}
+
 #[test]
 fn test_leaf_hash() {
     {
@@ -155,6 +267,46 @@ fn test_leaf_hash() {
     }
 }
 
+#[test]
+fn test_hash_preimages() {
+    use sha2::Digest;
+
+    let key1 = KeyHash([1; 32]);
+    let key2 = KeyHash([2; 32]);
+    let value_hash = ValueHash([3; 32]);
+    let leaf_hash = |key: KeyHash| {
+        let mut preimage = b"JMT::LeafNode".to_vec();
+        preimage.extend_from_slice(&key.0);
+        preimage.extend_from_slice(&value_hash.0);
+        <[u8; 32]>::from(Sha256::digest(&preimage))
+    };
+    assert_eq!(
+        LeafNode::hash_from_parts::<Sha256>(key1, value_hash),
+        leaf_hash(key1)
+    );
+
+    // Each half holds a single leaf, which stands for the half.
+    let mut children = Children::new();
+    children.insert(
+        Nibble::from(0),
+        Child::new(leaf_hash(key1), 0, NodeType::Leaf),
+    );
+    children.insert(
+        Nibble::from(15),
+        Child::new(leaf_hash(key2), 0, NodeType::Leaf),
+    );
+    let mut preimage = b"JMT::IntrnalNode".to_vec();
+    preimage.extend_from_slice(&leaf_hash(key1));
+    preimage.extend_from_slice(&leaf_hash(key2));
+    let hash = InternalNode::hash_from_children::<Sha256>(&children);
+    assert_eq!(hash, <[u8; 32]>::from(Sha256::digest(&preimage)));
+    assert_eq!(
+        Node::from(InternalNode::new(children)).hash::<Sha256>(),
+        hash
+    );
+    assert_eq!(Node::Null.hash::<Sha256>(), SPARSE_MERKLE_PLACEHOLDER_HASH);
+}
+
 proptest! {
     #[test]
     fn two_leaves_test1(index1 in (0..8u8).prop_map(Nibble::from), index2 in (8..16u8).prop_map(Nibble::from),
@@ -106,6 +106,74 @@ proptest! {
         // overwrite, an entirely different tree
         restore_without_interruption::<Sha256>(&btree2, target_version, &restore_db, false);
     }
+
+    #[test]
+    fn test_stream_build_sorted_sha256(
+        btree in btree_map(any::<KeyHash>(), any::<OwnedValue>(), 0..1000),
+        target_version in 0u64..2000,
+    ) {
+        let db = MockTreeStore::default();
+        let (expected_root_hash, batch) = JellyfishMerkleTree::<_, Sha256>::new(&db)
+            .put_value_set(btree.iter().map(|(key, value)| (*key, Some(value.clone()))), 0)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        let restore_db = Arc::new(MockTreeStore::default());
+        let root_hash = JellyfishMerkleRestore::<Sha256>::stream_build_sorted(
+            Arc::clone(&restore_db),
+            target_version,
+            btree.clone(),
+        )
+        .unwrap();
+        prop_assert_eq!(root_hash, expected_root_hash);
+        prop_assert_eq!(restore_db.num_nodes(), db.num_nodes());
+        assert_success::<Sha256>(&restore_db, expected_root_hash, &btree, target_version);
+    }
+}
+
+#[test]
+fn test_stream_build_unsorted() {
+    let entries = alloc::vec![
+        (KeyHash([2; 32]), alloc::vec![2]),
+        (KeyHash([1; 32]), alloc::vec![1]),
+    ];
+    let restore_db = Arc::new(MockTreeStore::default());
+    assert!(JellyfishMerkleRestore::<Sha256>::stream_build_sorted(restore_db, 0, entries).is_err());
+}
+
+#[cfg(feature = "migration")]
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_restore_unverified_sha256(
+        (entries, chunk_size) in btree_map(any::<KeyHash>(), any::<OwnedValue>(), 1..1000)
+            .prop_flat_map(|btree| {
+                let len = btree.len();
+                (Just(btree), 1..=len)
+            }),
+        target_version in 0u64..2000,
+    ) {
+        let (db, source_version) = init_mock_db::<Sha256>(&entries.clone().into_iter().collect());
+        let expected_root_hash = JellyfishMerkleTree::<_, Sha256>::new(&db)
+            .get_root_hash(source_version)
+            .unwrap();
+
+        let restore_db = Arc::new(MockTreeStore::default());
+        let mut restore = JellyfishMerkleRestore::<Sha256>::new(
+            Arc::clone(&restore_db),
+            target_version,
+            expected_root_hash,
+        )
+        .unwrap();
+        let entries_vec: Vec<_> = entries.clone().into_iter().collect();
+        for chunk in entries_vec.chunks(chunk_size) {
+            restore.add_chunk_unverified(chunk.to_vec()).unwrap();
+        }
+        restore.finish().unwrap();
+
+        assert_success::<Sha256>(&restore_db, expected_root_hash, &entries, target_version);
+    }
 }
 
 #[cfg(feature = "blake3_tests")]
@@ -7,10 +7,10 @@ use sha2::Sha256;
 use crate::{
     mock::MockTreeStore,
     node_type::{Node, NodeKey},
-    storage::LeafNode,
-    tree_cache::TreeCache,
+    storage::{LeafNode, Nibble, TreeReader, TreeUpdateBatch},
+    tree_cache::{CacheLayer, TreeCache},
     types::{nibble::nibble_path::NibblePath, Version, PRE_GENESIS_VERSION},
-    KeyHash, OwnedValue, ValueHash,
+    JellyfishMerkleTree, KeyHash, NodeAlreadyExists, OwnedValue, RootHash, ValueHash,
 };
 
 fn random_leaf_with_key(next_version: Version) -> (LeafNode, OwnedValue, NodeKey) {
@@ -94,3 +94,291 @@ fn test_freeze_with_delete() {
     assert_eq!(update_batch.node_batch.nodes().len(), 3);
     assert_eq!(update_batch.stale_node_index_batch.len(), 1);
 }
+
+#[test]
+fn test_advance() {
+    let next_version = 0;
+    let db = MockTreeStore::default();
+    let mut cache = TreeCache::new(&db, next_version).unwrap();
+    assert!(cache.advance().is_err());
+
+    let (node, value, _) = random_leaf_with_key(next_version);
+    let node: Node = node.into();
+    let root_node_key = NodeKey::new_empty_path(next_version);
+    cache.delete_node(&root_node_key, false /* is_leaf */);
+    cache.put_node(root_node_key.clone(), node.clone()).unwrap();
+    cache.put_value(next_version, KeyHash([1; 32]), Some(value));
+    assert!(cache.advance().is_err());
+    cache.freeze::<Sha256>().unwrap();
+    cache.advance().unwrap();
+    assert_eq!(*cache.get_root_node_key(), root_node_key);
+
+    // A version without any change still gets its own root to advance to.
+    cache.freeze::<Sha256>().unwrap();
+    cache.advance().unwrap();
+    assert_eq!(
+        *cache.get_root_node_key(),
+        NodeKey::new_empty_path(next_version + 1)
+    );
+    assert_eq!(cache.get_node(cache.get_root_node_key()).unwrap(), node);
+}
+
+#[test]
+fn test_snapshot_sees_only_frozen_nodes() {
+    let next_version = 0;
+    let db = MockTreeStore::default();
+    let mut cache = TreeCache::new(&db, next_version).unwrap();
+
+    let (leaf1, value1, node1_key) = random_leaf_with_key(next_version);
+    let node1: Node = leaf1.clone().into();
+    cache.put_node(node1_key.clone(), node1.clone()).unwrap();
+    cache.put_value(next_version, KeyHash([1; 32]), Some(value1.clone()));
+    cache.freeze::<Sha256>().unwrap();
+
+    let snapshot = cache.snapshot();
+
+    // Changes made after taking the snapshot, frozen or not, are invisible to it.
+    let (leaf2, _, node2_key) = random_leaf_with_key(next_version + 1);
+    cache
+        .put_node(node2_key.clone(), leaf2.clone().into())
+        .unwrap();
+    assert!(snapshot.get_node_option(&node2_key).unwrap().is_none());
+    cache.freeze::<Sha256>().unwrap();
+    assert!(snapshot.get_node_option(&node2_key).unwrap().is_none());
+    assert!(cache.get_node_option(&node2_key).unwrap().is_some());
+
+    // The rightmost leaf is looked up among the nodes each reader sees.
+    assert_eq!(
+        snapshot.get_rightmost_leaf().unwrap(),
+        Some((node1_key.clone(), leaf1.clone()))
+    );
+    let rightmost_leaf = if leaf1.key_hash() > leaf2.key_hash() {
+        (node1_key.clone(), leaf1)
+    } else {
+        (node2_key.clone(), leaf2)
+    };
+    assert_eq!(cache.get_rightmost_leaf().unwrap(), Some(rightmost_leaf));
+
+    // The snapshot can be read from another thread.
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            assert_eq!(snapshot.get_node(&node1_key).unwrap(), node1);
+            assert_eq!(
+                snapshot
+                    .get_value_option(next_version, KeyHash([1; 32]))
+                    .unwrap(),
+                Some(value1)
+            );
+        });
+    });
+
+    // The cache still produces the full batch even though a snapshot is alive.
+    let (_, update_batch) = cache.into();
+    assert!(update_batch.node_batch.get_node(&node2_key).is_some());
+}
+
+#[test]
+fn test_warm() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let key_hashes: Vec<KeyHash> = (0..10u8).map(|i| KeyHash::with::<Sha256>([i])).collect();
+    let (_, batch) = tree
+        .put_value_set(
+            key_hashes
+                .iter()
+                .map(|key_hash| (*key_hash, Some(vec![1u8]))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let freeze_unchanged = |prefix: Option<&[Nibble]>| -> (Vec<RootHash>, TreeUpdateBatch) {
+        let mut cache = TreeCache::new(&db, 1).unwrap();
+        if let Some(prefix) = prefix {
+            cache.warm(prefix).unwrap();
+        }
+        cache.freeze::<Sha256>().unwrap();
+        cache.into()
+    };
+
+    // Warming reads the nodes down to the leaf of the key, but
+    // none of them end up in the batch.
+    let nibbles: Vec<Nibble> = NibblePath::new(key_hashes[0].0.to_vec())
+        .nibbles()
+        .collect();
+    assert_eq!(freeze_unchanged(Some(&nibbles)), freeze_unchanged(None));
+
+    let mut cache = TreeCache::new(&db, 1).unwrap();
+    cache.warm(&nibbles[..1]).unwrap();
+    cache.warm(&[]).unwrap();
+    let root_node_key = cache.get_root_node_key().clone();
+    assert_eq!(
+        cache.get_node(&root_node_key).unwrap(),
+        db.get_node(&root_node_key).unwrap()
+    );
+}
+
+// Replaces the root of `cache` with `leaf` at `version`.
+fn put_root_leaf(
+    cache: &mut TreeCache<MockTreeStore>,
+    version: Version,
+    (node, value, _): &(LeafNode, OwnedValue, NodeKey),
+) {
+    let root_node_key = cache.get_root_node_key().clone();
+    cache.delete_node(&root_node_key, version > 0 /* is_leaf */);
+    let root_node_key = NodeKey::new_empty_path(version);
+    cache.put_value(version, node.key_hash(), Some(value.clone()));
+    cache
+        .put_node(root_node_key.clone(), node.clone().into())
+        .unwrap();
+    cache.set_root_node_key(root_node_key);
+}
+
+#[test]
+fn test_rollback_to_checkpoint() {
+    let db = MockTreeStore::default();
+    let leaves: Vec<_> = (0..3).map(|_| random_leaf_with_key(0)).collect();
+
+    let mut cache = TreeCache::new(&db, 0).unwrap();
+    put_root_leaf(&mut cache, 0, &leaves[0]);
+    cache.freeze::<Sha256>().unwrap();
+    cache.advance().unwrap();
+    let checkpoint = cache.checkpoint();
+
+    // Speculatively apply two versions and start a third one, then abort them all.
+    put_root_leaf(&mut cache, 1, &leaves[1]);
+    cache.freeze::<Sha256>().unwrap();
+    cache.advance().unwrap();
+    let later_checkpoint = cache.checkpoint();
+    put_root_leaf(&mut cache, 2, &leaves[2]);
+    cache.freeze::<Sha256>().unwrap();
+    cache.advance().unwrap();
+    put_root_leaf(&mut cache, 3, &leaves[1]);
+    cache.rollback_to(checkpoint).unwrap();
+    assert_eq!(*cache.get_root_node_key(), NodeKey::new_empty_path(0));
+    assert!(cache.rollback_to(later_checkpoint).is_err());
+
+    let mut expected = TreeCache::new(&db, 0).unwrap();
+    put_root_leaf(&mut expected, 0, &leaves[0]);
+    expected.freeze::<Sha256>().unwrap();
+    expected.advance().unwrap();
+    for tree_cache in [&mut cache, &mut expected] {
+        put_root_leaf(tree_cache, 1, &leaves[2]);
+        tree_cache.freeze::<Sha256>().unwrap();
+    }
+    let (root_hashes, batch): (Vec<RootHash>, TreeUpdateBatch) = cache.into();
+    assert_eq!((root_hashes, batch), expected.into());
+}
+
+#[test]
+fn test_put_node_idempotent() {
+    let next_version = 0;
+    let db = MockTreeStore::default();
+    let mut cache = TreeCache::new(&db, next_version).unwrap();
+
+    let (node, _, node_key) = random_leaf_with_key(next_version);
+    let (other_node, _, _) = random_leaf_with_key(next_version);
+    cache
+        .put_node_idempotent(node_key.clone(), node.clone().into())
+        .unwrap();
+    cache
+        .put_node_idempotent(node_key.clone(), node.clone().into())
+        .unwrap();
+    assert_eq!(cache.get_node(&node_key).unwrap(), node.clone().into());
+
+    let err = cache
+        .put_node_idempotent(node_key.clone(), other_node.clone().into())
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<NodeAlreadyExists>(),
+        Some(&NodeAlreadyExists {
+            node_key: node_key.clone()
+        })
+    );
+    let err = cache
+        .put_node(node_key.clone(), node.clone().into())
+        .unwrap_err();
+    assert!(err.downcast_ref::<NodeAlreadyExists>().is_some());
+    assert_eq!(cache.get_node(&node_key).unwrap(), node.into());
+
+    // The node is only counted once in the frozen batch.
+    cache.freeze::<Sha256>().unwrap();
+    let (_, batch): (Vec<RootHash>, TreeUpdateBatch) = cache.into();
+    assert_eq!(batch.node_stats[0].new_leaves, 1);
+}
+
+#[test]
+fn test_load_frozen() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(
+            (0..10u8).map(|i| (KeyHash::with::<Sha256>([i]), Some(vec![i]))),
+            0,
+        )
+        .unwrap();
+    let stats = batch.node_stats[0].clone();
+
+    let mut wrong_stats = stats.clone();
+    wrong_stats.new_leaves += 1;
+    let mut cache = TreeCache::new(&db, 0).unwrap();
+    assert!(cache
+        .load_frozen::<Sha256>(batch.node_batch.clone(), wrong_stats)
+        .is_err());
+    let mut cache = TreeCache::new(&db, 1).unwrap();
+    assert!(cache
+        .load_frozen::<Sha256>(batch.node_batch.clone(), stats.clone())
+        .is_err());
+
+    // The loaded nodes are visible, and the cache continues from the loaded root.
+    let mut cache = TreeCache::new(&db, 0).unwrap();
+    cache
+        .load_frozen::<Sha256>(batch.node_batch.clone(), stats)
+        .unwrap();
+    assert_eq!(*cache.get_root_node_key(), NodeKey::new_empty_path(0));
+    for (node_key, node) in batch.node_batch.nodes() {
+        assert_eq!(cache.get_node(node_key).unwrap(), *node);
+    }
+    cache.freeze::<Sha256>().unwrap();
+    let (root_hashes, loaded_batch): (Vec<RootHash>, TreeUpdateBatch) = cache.into();
+    assert_eq!(root_hashes, vec![root_hash, root_hash]);
+    assert_eq!(loaded_batch.node_stats[0], batch.node_stats[0]);
+    assert!(loaded_batch
+        .node_batch
+        .get_node(&NodeKey::new_empty_path(1))
+        .is_some());
+}
+
+#[test]
+fn test_read_hook() {
+    let next_version = 0;
+    let db = MockTreeStore::default();
+    let (stored, value, stored_key) = random_leaf_with_key(next_version);
+    db.put_leaf(stored_key.clone(), stored, value).unwrap();
+
+    let reads = std::sync::Mutex::new(Vec::new());
+    let mut cache = TreeCache::new(&db, next_version).unwrap();
+    cache.set_read_hook(|node_key, layer| reads.lock().unwrap().push((node_key.clone(), layer)));
+    let (frozen, _, frozen_key) = random_leaf_with_key(next_version);
+    cache.put_node(frozen_key.clone(), frozen.into()).unwrap();
+    cache.freeze::<Sha256>().unwrap();
+    let (new, _, new_key) = random_leaf_with_key(next_version + 1);
+    cache.put_node(new_key.clone(), new.into()).unwrap();
+    reads.lock().unwrap().clear();
+
+    cache.get_node(&new_key).unwrap();
+    cache.get_node(&frozen_key).unwrap();
+    cache.get_node_option(&stored_key).unwrap();
+    let missing_key = NodeKey::new_empty_path(next_version + 2);
+    assert!(cache.get_node_option(&missing_key).unwrap().is_none());
+    drop(cache);
+    assert_eq!(
+        reads.into_inner().unwrap(),
+        vec![
+            (new_key, CacheLayer::NodeCache),
+            (frozen_key, CacheLayer::FrozenCache),
+            (stored_key, CacheLayer::Reader),
+            (missing_key, CacheLayer::Reader),
+        ]
+    );
+}
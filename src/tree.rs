@@ -1,9 +1,13 @@
 use crate::storage::Node::Leaf;
-use alloc::{collections::BTreeMap, vec::Vec};
-use alloc::{format, vec};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use alloc::{format, sync::Arc, vec};
 use anyhow::{bail, ensure, format_err, Context, Result};
 use core::marker::PhantomData;
-use core::{cmp::Ordering, convert::TryInto};
+use core::sync::atomic::{self, AtomicBool};
+use core::{cmp::Ordering, convert::TryInto, ops::ControlFlow};
 #[cfg(not(feature = "std"))]
 use hashbrown::HashMap;
 #[cfg(feature = "std")]
@@ -11,20 +15,38 @@ use std::collections::HashMap;
 
 use crate::proof::definition::UpdateMerkleProof;
 use crate::proof::{SparseMerkleLeafNode, SparseMerkleNode};
+#[cfg(feature = "std")]
+use crate::storage::CommittedRoot;
 use crate::{
+    iterator::JellyfishMerkleIterator,
     node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey, NodeType},
-    storage::{TreeReader, TreeUpdateBatch},
+    storage::{
+        NodeBatch, NodeStats, OverlayReader, StaleNodeIndexBatch, TreeReader, TreeTruncator,
+        TreeUpdateBatch, TreeWriter, TruncationBatch,
+    },
     tree_cache::TreeCache,
     types::{
         nibble::{
             nibble_path::{skip_common_prefix, NibbleIterator, NibblePath},
             Nibble, NibbleRangeIterator, ROOT_NIBBLE_HEIGHT,
         },
-        proof::{SparseMerkleProof, SparseMerkleRangeProof},
-        Version,
+        proof::{
+            ConsistencyProof, DeleteRangeProof, NamespaceProof, SparseMerkleMultiproof,
+            SparseMerkleProof, SparseMerkleRangeProof, UnchangedProof, ValueProof,
+        },
+        Version, PRE_GENESIS_VERSION,
     },
-    Bytes32Ext, KeyHash, MissingRootError, OwnedValue, RootHash, SimpleHasher, ValueHash,
+    Bytes32Ext, KeyHash, MissingRootError, OwnedValue, ProofTooDeep, ReadError, RootHash,
+    SimpleHasher, ValueHash, SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
+pub use membership_filter::MembershipFilter;
+pub use proof_buffer::ProofBuffer;
+#[cfg(feature = "std")]
+pub use proof_cache::ProofCache;
+#[cfg(feature = "std")]
+pub use root_hash_cache::RootHashCache;
+#[cfg(feature = "std")]
+use value_hash_cache::ValueHashCache;
 
 /// A [`JellyfishMerkleTree`] instantiated using the `sha2::Sha256` hasher.
 /// This is a sensible default choice for most applications.
@@ -34,12 +56,23 @@ pub type Sha256Jmt<'a, R> = JellyfishMerkleTree<'a, R, sha2::Sha256>;
 /// A Jellyfish Merkle tree data structure, parameterized by a [`TreeReader`] `R`
 /// and a [`SimpleHasher`] `H`. See [`crate`] for description.
 pub struct JellyfishMerkleTree<'a, R, H: SimpleHasher> {
-    reader: &'a R,
+    pub(crate) reader: &'a R,
+    #[cfg(feature = "std")]
+    value_hash_cache: Option<ValueHashCache>,
+    track_stale_nodes: bool,
     _phantom_hasher: PhantomData<H>,
 }
 
 #[cfg(feature = "ics23")]
 pub mod ics23_impl;
+mod membership_filter;
+mod proof_buffer;
+#[cfg(feature = "std")]
+mod proof_cache;
+#[cfg(feature = "std")]
+mod root_hash_cache;
+#[cfg(feature = "std")]
+mod value_hash_cache;
 
 impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
 where
@@ -50,10 +83,47 @@ where
     pub fn new(reader: &'a R) -> Self {
         Self {
             reader,
+            #[cfg(feature = "std")]
+            value_hash_cache: None,
+            track_stale_nodes: true,
             _phantom_hasher: Default::default(),
         }
     }
 
+    /// Remembers the hashes of up to `capacity` values written through this tree, so that writing
+    /// the same value under many keys only hashes it once. This does not change any hash.
+    #[cfg(feature = "std")]
+    pub fn with_value_hash_cache(mut self, capacity: usize) -> Self {
+        self.value_hash_cache = Some(ValueHashCache::new(capacity));
+        self
+    }
+
+    /// Stops recording the nodes that become stale in the batches written through this tree, for
+    /// short-lived trees that are never pruned, see [`TreeCache::without_stale_tracking`].
+    pub fn without_stale_tracking(mut self) -> Self {
+        self.track_stale_nodes = false;
+        self
+    }
+
+    /// Creates the [`TreeCache`] to write `next_version` through.
+    fn new_tree_cache(&self, next_version: Version) -> Result<TreeCache<'a, R>> {
+        let tree_cache = TreeCache::new(self.reader, next_version)?;
+        Ok(if self.track_stale_nodes {
+            tree_cache
+        } else {
+            tree_cache.without_stale_tracking()
+        })
+    }
+
+    /// Hashes `value`, through the value hash cache if there is one.
+    fn value_hash(&self, value: &[u8]) -> ValueHash {
+        #[cfg(feature = "std")]
+        if let Some(cache) = &self.value_hash_cache {
+            return cache.value_hash::<H>(value);
+        }
+        ValueHash::with::<H>(value)
+    }
+
     /// Get the node hash from the cache if exists, otherwise compute it.
     fn get_hash(
         node_key: &NodeKey,
@@ -77,7 +147,7 @@ where
         node_hashes: Option<Vec<&HashMap<NibblePath, [u8; 32]>>>,
         first_version: Version,
     ) -> Result<(Vec<RootHash>, TreeUpdateBatch)> {
-        let mut tree_cache = TreeCache::new(self.reader, first_version)?;
+        let mut tree_cache = self.new_tree_cache(first_version)?;
         let hash_sets: Vec<_> = match node_hashes {
             Some(hashes) => hashes.into_iter().map(Some).collect(),
             None => (0..value_sets.len()).map(|_| None).collect(),
@@ -96,7 +166,7 @@ where
                 .collect::<BTreeMap<_, _>>()
                 .into_iter()
                 .map(|(key, value)| {
-                    let value_hash = ValueHash::with::<H>(value.as_slice());
+                    let value_hash = self.value_hash(value.as_slice());
                     tree_cache.put_value(version, key, Some(value));
                     (key, value_hash)
                 })
@@ -116,7 +186,9 @@ where
             tree_cache.freeze::<H>()?;
         }
 
-        Ok(tree_cache.into())
+        let (root_hashes, tree_update_batch) = tree_cache.into();
+        debug_assert_eq!(tree_update_batch.validate(), Ok(()));
+        Ok((root_hashes, tree_update_batch))
     }
 
     fn batch_insert_at(
@@ -355,6 +427,143 @@ where
         Ok((root_hashes[0], tree_update_batch))
     }
 
+    /// Same as [`put_value_set`](JellyfishMerkleTree::put_value_set) for the single key
+    /// `raw_key`, hashed with the tree's hasher as [`KeyHash::with`] would, so that the key hash
+    /// cannot be derived from another key or with another hasher by mistake.
+    ///
+    /// As with [`KeyHash::with`], `raw_key` must be non-empty if ics23 non-existence proofs are used.
+    pub fn put_value_checked(
+        &self,
+        raw_key: &[u8],
+        value: Option<OwnedValue>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        self.put_value_set(vec![(KeyHash::with::<H>(raw_key), value)], version)
+    }
+
+    /// Returns the root hash that [`put_value_set`](JellyfishMerkleTree::put_value_set) would
+    /// return for `value_set` at `version`, for callers that only validate updates and never
+    /// commit them. The values and the stale nodes are not recorded, and the nodes are dropped.
+    pub fn what_if_root(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<RootHash> {
+        let mut tree_cache = TreeCache::new(self.reader, version)?.without_stale_tracking();
+        for (i, (key, value)) in value_set.into_iter().enumerate() {
+            let action = if value.is_some() { "insert" } else { "delete" };
+            let value_hash = value.as_ref().map(|v| self.value_hash(v));
+            self.put(key, value_hash, version, &mut tree_cache, false)
+                .with_context(|| {
+                    format!(
+                        "failed to {} key {} for version {}, key = {:?}",
+                        action, i, version, key
+                    )
+                })?;
+        }
+        tree_cache.freeze::<H>()?;
+
+        let (root_hashes, _) = tree_cache.into();
+        Ok(root_hashes[0])
+    }
+
+    /// Same as [`put_value_set`](JellyfishMerkleTree::put_value_set), but applies `updates` in
+    /// increasing key order, so that consecutive updates share most of their path in the tree.
+    /// The last update of a key overrides the previous ones.
+    pub fn put_value_set_iter(
+        &self,
+        updates: impl Iterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let sorted_updates: BTreeMap<_, _> = updates.collect();
+        self.put_value_set_sorted(sorted_updates.into_iter(), version)
+    }
+
+    /// Same as [`put_value_set_iter`](JellyfishMerkleTree::put_value_set_iter), for `updates`
+    /// already sorted by key and distinct, which are applied to the tree one by one as they are
+    /// pulled from the iterator, without being sorted or collected first. The order is only
+    /// checked in debug builds.
+    pub fn put_value_set_sorted(
+        &self,
+        updates: impl Iterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let mut tree_cache = self.new_tree_cache(version)?;
+        let mut previous_key = None;
+        for (i, (key, value)) in updates.enumerate() {
+            debug_assert!(
+                previous_key < Some(key),
+                "Updates must be sorted by key and distinct."
+            );
+            previous_key = Some(key);
+
+            let action = if value.is_some() { "insert" } else { "delete" };
+            let value_hash = value.as_ref().map(|v| self.value_hash(v));
+            tree_cache.put_value(version, key, value);
+            self.put(key, value_hash, version, &mut tree_cache, false)
+                .with_context(|| {
+                    format!(
+                        "failed to {} key {} for version {}, key = {:?}",
+                        action, i, version, key
+                    )
+                })?;
+        }
+        tree_cache.freeze::<H>()?;
+
+        let (root_hashes, tree_update_batch) = tree_cache.into();
+        debug_assert_eq!(tree_update_batch.validate(), Ok(()));
+        Ok((root_hashes[0], tree_update_batch))
+    }
+
+    /// Same as [`put_value_set_sorted`](JellyfishMerkleTree::put_value_set_sorted), but bounds the
+    /// memory used by a huge update: every `flush_interval` updates, the nodes and values of the
+    /// subtrees left of the next key, which later updates cannot change, are written to `writer`
+    /// with [`TreeCache::flush_finalized`]. The returned [`TreeUpdateBatch`] holds the rest of the
+    /// update, and must still be written for the version to be complete.
+    ///
+    /// `writer` must write to the storage this tree reads from. Fails if `updates` are not sorted
+    /// by key and distinct, or if `flush_interval` is zero.
+    pub fn put_value_set_sorted_with_flush<W: TreeWriter>(
+        &self,
+        updates: impl Iterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+        writer: &W,
+        flush_interval: usize,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        ensure!(flush_interval > 0, "The flush interval must be positive.");
+        let mut tree_cache = self.new_tree_cache(version)?;
+        let mut updates = updates.peekable();
+        let mut i = 0;
+        while let Some((key, value)) = updates.next() {
+            let action = if value.is_some() { "insert" } else { "delete" };
+            let value_hash = value.as_ref().map(|v| self.value_hash(v));
+            tree_cache.put_value(version, key, value);
+            self.put(key, value_hash, version, &mut tree_cache, false)
+                .with_context(|| {
+                    format!(
+                        "failed to {} key {} for version {}, key = {:?}",
+                        action, i, version, key
+                    )
+                })?;
+            i += 1;
+
+            if let Some((next_key, _)) = updates.peek() {
+                ensure!(
+                    key < *next_key,
+                    "Updates must be sorted by key and distinct."
+                );
+                if i % flush_interval == 0 {
+                    tree_cache.flush_finalized(writer, *next_key)?;
+                }
+            }
+        }
+        tree_cache.freeze::<H>()?;
+
+        let (root_hashes, tree_update_batch) = tree_cache.into();
+        debug_assert_eq!(tree_update_batch.validate(), Ok(()));
+        Ok((root_hashes[0], tree_update_batch))
+    }
+
     /// This is a convenient function that calls
     /// [`put_value_sets_with_proof`](struct.JellyfishMerkleTree.html#method.put_value_sets) with a single
     /// `keyed_value_set`.
@@ -376,6 +585,118 @@ where
         Ok((hash, proof, batch_update))
     }
 
+    /// Same as [`put_value_set`](JellyfishMerkleTree::put_value_set), but also returns the value
+    /// each key of `value_set` had before `version`, or `None` if it had none.
+    ///
+    /// The previous values are read from the tree at the version `value_set` is applied on, so
+    /// they only cost a value read for the keys that existed.
+    pub fn put_value_set_with_previous(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(
+        RootHash,
+        TreeUpdateBatch,
+        BTreeMap<KeyHash, Option<OwnedValue>>,
+    )> {
+        let value_set: Vec<_> = value_set.into_iter().collect();
+        let previous_version = self.previous_version(version)?;
+        let mut previous_values = BTreeMap::new();
+        for (key, _) in &value_set {
+            if !previous_values.contains_key(key) {
+                let previous_value = match previous_version {
+                    Some(previous_version) => self.get(*key, previous_version)?,
+                    None => None,
+                };
+                previous_values.insert(*key, previous_value);
+            }
+        }
+
+        let (root_hash, batch) = self.put_value_set(value_set, version)?;
+        Ok((root_hash, batch, previous_values))
+    }
+
+    /// Sets `key` to `new_value` at `version` (deleting it if `new_value` is `None`), but only if
+    /// its value hash in the tree `version` is applied on is `expected_value_hash`, `None` meaning
+    /// that `key` must be absent. The returned flag tells whether the swap happened.
+    ///
+    /// On a mismatch the tree is left unchanged, but `version` is still committed with the same
+    /// root as the previous version, as [`put_value_set`](JellyfishMerkleTree::put_value_set)
+    /// would for an empty value set, so that the returned batch can be written either way.
+    pub fn compare_and_swap(
+        &self,
+        key: KeyHash,
+        expected_value_hash: Option<[u8; 32]>,
+        new_value: Option<OwnedValue>,
+        version: Version,
+    ) -> Result<(RootHash, bool, TreeUpdateBatch)> {
+        let current_value_hash = match self.previous_version(version)? {
+            Some(previous_version) => self
+                .get_leaf_and_last_update(key, previous_version)?
+                .0
+                .map(|leaf| leaf.value_hash().0),
+            None => None,
+        };
+        let swapped = current_value_hash == expected_value_hash;
+        let value_set = if swapped {
+            vec![(key, new_value)]
+        } else {
+            vec![]
+        };
+
+        let (root_hash, batch) = self.put_value_set(value_set, version)?;
+        Ok((root_hash, swapped, batch))
+    }
+
+    /// Deletes at `version` every key for which `f` returns `false`, given the key and its value in
+    /// the tree `version` is applied on. Returns the new root hash and the corresponding
+    /// [`TreeUpdateBatch`], the same as [`put_value_set`](JellyfishMerkleTree::put_value_set)
+    /// deleting those keys would.
+    ///
+    /// The leaves are visited one at a time in key order, so only the keys to delete are held in
+    /// memory.
+    pub fn retain(
+        &self,
+        version: Version,
+        mut f: impl FnMut(&KeyHash, &[u8]) -> bool,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let mut deletions = Vec::new();
+        if let Some(previous_version) = self.previous_version(version)? {
+            let mut stack = vec![NodeKey::new_empty_path(previous_version)];
+            while let Some(node_key) = stack.pop() {
+                match self.reader.get_node(&node_key)? {
+                    Node::Internal(internal_node) => {
+                        // Children are pushed in reverse to be popped in key order.
+                        let children: Vec<_> = internal_node.children_sorted().collect();
+                        for (nibble, child) in children.into_iter().rev() {
+                            stack.push(node_key.gen_child_node_key(child.version, nibble));
+                        }
+                    }
+                    Node::Leaf(leaf_node) => {
+                        let key = leaf_node.key_hash();
+                        let value = self.reader.get_value(previous_version, key)?;
+                        if !f(&key, &value) {
+                            deletions.push((key, None));
+                        }
+                    }
+                    Node::Null => {}
+                }
+            }
+        }
+        self.put_value_set(deletions, version)
+    }
+
+    /// Returns the version of the tree that `version` is applied on, if there is one. The first
+    /// version is applied on the pre-genesis tree, if there is one.
+    fn previous_version(&self, version: Version) -> Result<Option<Version>> {
+        match version.checked_sub(1) {
+            Some(previous_version) => Ok(Some(previous_version)),
+            None => Ok(self
+                .get_root_node_option(PRE_GENESIS_VERSION)?
+                .map(|_| PRE_GENESIS_VERSION)),
+        }
+    }
+
     /// Returns the new nodes and values in a batch after applying `value_set`. For
     /// example, if after transaction `T_i` the committed state of tree in the persistent storage
     /// looks like the following structure:
@@ -422,12 +743,12 @@ where
         value_sets: impl IntoIterator<Item = impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>>,
         first_version: Version,
     ) -> Result<(Vec<RootHash>, TreeUpdateBatch)> {
-        let mut tree_cache = TreeCache::new(self.reader, first_version)?;
+        let mut tree_cache = self.new_tree_cache(first_version)?;
         for (idx, value_set) in value_sets.into_iter().enumerate() {
             let version = first_version + idx as u64;
             for (i, (key, value)) in value_set.into_iter().enumerate() {
                 let action = if value.is_some() { "insert" } else { "delete" };
-                let value_hash = value.as_ref().map(|v| ValueHash::with::<H>(v));
+                let value_hash = value.as_ref().map(|v| self.value_hash(v));
                 tree_cache.put_value(version, key, value);
                 self.put(key, value_hash, version, &mut tree_cache, false)
                     .with_context(|| {
@@ -442,7 +763,9 @@ where
             tree_cache.freeze::<H>()?;
         }
 
-        Ok(tree_cache.into())
+        let (root_hashes, tree_update_batch) = tree_cache.into();
+        debug_assert_eq!(tree_update_batch.validate(), Ok(()));
+        Ok((root_hashes, tree_update_batch))
     }
 
     #[cfg(feature = "migration")]
@@ -453,9 +776,12 @@ where
         latest_version: Version,
     ) -> Result<(RootHash, TreeUpdateBatch)> {
         let mut tree_cache = TreeCache::new_overwrite(self.reader, latest_version)?;
+        if !self.track_stale_nodes {
+            tree_cache = tree_cache.without_stale_tracking();
+        }
         for (i, (key, value)) in value_set.into_iter().enumerate() {
             let action = if value.is_some() { "insert" } else { "delete" };
-            let value_hash = value.as_ref().map(|v| ValueHash::with::<H>(v));
+            let value_hash = value.as_ref().map(|v| self.value_hash(v));
             tree_cache.put_value(latest_version, key, value);
             self.put(key, value_hash, latest_version, &mut tree_cache, false)
                 .with_context(|| {
@@ -486,14 +812,14 @@ where
         value_sets: impl IntoIterator<Item = impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>>,
         first_version: Version,
     ) -> Result<(Vec<(RootHash, UpdateMerkleProof<H>)>, TreeUpdateBatch)> {
-        let mut tree_cache = TreeCache::new(self.reader, first_version)?;
+        let mut tree_cache = self.new_tree_cache(first_version)?;
         let mut batch_proofs = Vec::new();
         for (idx, value_set) in value_sets.into_iter().enumerate() {
             let version = first_version + idx as u64;
             let mut proofs = Vec::new();
             for (i, (key, value)) in value_set.into_iter().enumerate() {
                 let action = if value.is_some() { "insert" } else { "delete" };
-                let value_hash = value.as_ref().map(|v| ValueHash::with::<H>(v));
+                let value_hash = value.as_ref().map(|v| self.value_hash(v));
                 tree_cache.put_value(version, key, value.clone());
                 let merkle_proof = self
                     .put(key, value_hash, version, &mut tree_cache, true)
@@ -515,6 +841,7 @@ where
         }
 
         let (root_hashes, update_batch): (Vec<RootHash>, TreeUpdateBatch) = tree_cache.into();
+        debug_assert_eq!(update_batch.validate(), Ok(()));
 
         let zipped_hashes_proofs = root_hashes
             .into_iter()
@@ -612,7 +939,7 @@ where
             ),
             Node::Null => {
                 let merkle_proof_null = if with_proof {
-                    Some(SparseMerkleProof::new(None, vec![]))
+                    Some(SparseMerkleProof::from_nodes(None, vec![]))
                 } else {
                     None
                 };
@@ -672,7 +999,7 @@ where
                         tree_cache,
                         &node_key,
                         child_index,
-                    );
+                    )?;
                     (child_key.unwrap(), siblings)
                 } else {
                     (
@@ -697,7 +1024,7 @@ where
                     // We need to reverse the siblings
                     siblings.reverse();
                     new_siblings.append(&mut siblings);
-                    SparseMerkleProof::new(proof_leaf, new_siblings)
+                    SparseMerkleProof::from_nodes(proof_leaf, new_siblings)
                 });
 
                 (update_result, new_proof_opt)
@@ -709,7 +1036,7 @@ where
                 // we are looking for.
                 let merkle_proof = if with_proof {
                     let (child_key_opt, mut siblings) = internal_node
-                        .get_only_child_with_siblings::<H>(tree_cache, &node_key, child_index);
+                        .get_only_child_with_siblings::<H>(tree_cache, &node_key, child_index)?;
 
                     let leaf: Option<SparseMerkleLeafNode> = child_key_opt.map(|child_key|
                     {
@@ -724,7 +1051,7 @@ where
                     });
 
                     siblings.reverse();
-                    Some(SparseMerkleProof::new(leaf, siblings))
+                    Some(SparseMerkleProof::from_nodes(leaf, siblings))
                 } else {
                     None
                 };
@@ -748,7 +1075,7 @@ where
                     (
                         PutResult::NotChanged,
                         if with_proof {
-                            Some(SparseMerkleProof::new(None, vec![]))
+                            Some(SparseMerkleProof::from_nodes(None, vec![]))
                         } else {
                             None
                         },
@@ -764,7 +1091,7 @@ where
                 return Ok((
                     PutResult::NotChanged,
                     if with_proof {
-                        Some(SparseMerkleProof::new(None, vec![]))
+                        Some(SparseMerkleProof::from_nodes(None, vec![]))
                     } else {
                         None
                     },
@@ -864,7 +1191,7 @@ where
             tree_cache.delete_node(&node_key, true /* is_leaf */);
 
             let merkle_proof = if with_proof {
-                Some(SparseMerkleProof::new(
+                Some(SparseMerkleProof::from_nodes(
                     Some(existing_leaf_node.into()),
                     vec![],
                 ))
@@ -957,7 +1284,7 @@ where
             Ok((
                 PutResult::Updated((node_key, next_internal_node.into())),
                 if with_proof {
-                    Some(SparseMerkleProof::new(
+                    Some(SparseMerkleProof::from_nodes(
                         Some(existing_leaf_node.into()),
                         vec![],
                     ))
@@ -970,7 +1297,7 @@ where
             Ok((
                 PutResult::NotChanged,
                 if with_proof {
-                    Some(SparseMerkleProof::new(None, vec![]))
+                    Some(SparseMerkleProof::from_nodes(None, vec![]))
                 } else {
                     None
                 },
@@ -1008,152 +1335,602 @@ where
         key: KeyHash,
         version: Version,
     ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
-        // Empty tree just returns proof with no sibling hash.
-        let mut next_node_key = NodeKey::new_empty_path(version);
-        let mut siblings: Vec<SparseMerkleNode> = vec![];
-        let nibble_path = NibblePath::new(key.0.to_vec());
-        let mut nibble_iter = nibble_path.nibbles();
+        let proof = self.get_proof(key, version)?;
+        let value = match proof.leaf() {
+            Some(leaf) if leaf.key_hash() == key => Some(self.reader.get_value(version, key)?),
+            _ => None,
+        };
+        Ok((value, proof))
+    }
 
-        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
-        // in the tree structure.
-        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
-            let next_node = self.reader.get_node(&next_node_key).map_err(|err| {
-                if nibble_depth == 0 {
-                    anyhow::anyhow!(MissingRootError { version })
-                } else {
-                    err
-                }
-            })?;
-            match next_node {
-                Node::Internal(internal_node) => {
-                    let queried_child_index = nibble_iter
-                        .next()
-                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), but only returns the
+    /// proof, for callers that already hold the value. Its leaf only carries the key hash and the
+    /// value hash, and the value is supplied at verification time, e.g. to
+    /// [`SparseMerkleProof::verify`].
+    pub fn get_proof_only(&self, key: KeyHash, version: Version) -> Result<SparseMerkleProof<H>> {
+        self.get_proof(key, version)
+    }
 
-                    let (child_node_key, mut siblings_in_internal) = internal_node
-                        .get_only_child_with_siblings::<H>(
-                            self.reader,
-                            &next_node_key,
-                            queried_child_index,
-                        );
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), but returns the value and
+    /// the proof together as a [`ValueProof`], which checks the value while verifying the proof.
+    pub fn get_value_proof(&self, key: KeyHash, version: Version) -> Result<ValueProof<H>> {
+        let (value, proof) = self.get_with_proof(key, version)?;
+        Ok(ValueProof::new(value, proof))
+    }
 
-                    siblings.append(&mut siblings_in_internal);
-                    next_node_key = match child_node_key {
-                        Some(node_key) => node_key,
-                        None => {
-                            return Ok((
-                                None,
-                                SparseMerkleProof::new(None, {
-                                    siblings.reverse();
-                                    siblings
-                                }),
-                            ))
-                        }
-                    };
-                }
-                Node::Leaf(leaf_node) => {
-                    return Ok((
-                        if leaf_node.key_hash() == key {
-                            Some(self.reader.get_value(version, leaf_node.key_hash())?)
-                        } else {
-                            None
-                        },
-                        SparseMerkleProof::new(Some(leaf_node.into()), {
-                            siblings.reverse();
-                            siblings
-                        }),
-                    ));
-                }
-                Node::Null => {
-                    if nibble_depth == 0 {
-                        return Ok((None, SparseMerkleProof::new(None, vec![])));
-                    } else {
-                        bail!(
-                            "Non-root null node exists with node key {:?}",
-                            next_node_key
-                        );
-                    }
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), but reports a node needed
+    /// for the proof but missing from storage, either on the path to `key` or as a sibling of that
+    /// path, as [`GetProofOutcome::Incomplete`] rather than as an error, e.g. to sync a tree that
+    /// is only partially materialized. A missing root is reported as a missing node too.
+    pub fn try_get_with_proof(&self, key: KeyHash, version: Version) -> Result<GetProofOutcome<H>> {
+        match self.get_with_proof(key, version) {
+            Ok((Some(value), proof)) => Ok(GetProofOutcome::Found { value, proof }),
+            Ok((None, proof)) => Ok(GetProofOutcome::NotFound { proof }),
+            Err(err) => {
+                if let Some(ReadError::UnexpectedMissing(node_key)) = err.downcast_ref() {
+                    Ok(GetProofOutcome::Incomplete {
+                        missing: node_key.clone(),
+                    })
+                } else if err.downcast_ref::<MissingRootError>().is_some() {
+                    Ok(GetProofOutcome::Incomplete {
+                        missing: NodeKey::new_empty_path(version),
+                    })
+                } else {
+                    Err(err)
                 }
             }
         }
-        bail!("Jellyfish Merkle tree has cyclic graph inside.");
     }
 
-    fn search_closest_extreme_node(
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), but looks the value and
+    /// proof up in `cache` first, and caches them otherwise. See [`ProofCache`] for which versions
+    /// are cached.
+    #[cfg(feature = "std")]
+    pub fn get_with_proof_cached(
         &self,
+        key: KeyHash,
         version: Version,
-        extreme: Extreme,
-        to: NibblePath,
-        parents: Vec<InternalNode>,
-    ) -> Result<Option<KeyHash>> {
-        fn neighbor_nibble(
-            node: &InternalNode,
-            child_index: Nibble,
-            extreme: Extreme,
-        ) -> Option<(Nibble, Version)> {
-            match extreme {
-                // Rightmost left neighbor
-                Extreme::Left => node
-                    .children_unsorted()
-                    .filter(|(nibble, _)| nibble < &child_index)
-                    .max_by_key(|(nibble, _)| *nibble)
-                    .map(|p| (p.0, p.1.version)),
-                // Leftmost right neighbor
-                Extreme::Right => node
-                    .children_unsorted()
-                    .filter(|(nibble, _)| nibble > &child_index)
-                    .min_by_key(|(nibble, _)| *nibble)
-                    .map(|p| (p.0, p.1.version)),
-            }
+        cache: &ProofCache<H>,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        if let Some(value_and_proof) = cache.get(version, key) {
+            return Ok(value_and_proof);
         }
-        let mut parents = parents;
-        let mut path = to;
+        let value_and_proof = self.get_with_proof(key, version)?;
+        cache.insert(version, key, value_and_proof.clone());
+        Ok(value_and_proof)
+    }
 
-        while let (Some(index), Some(parent)) = (path.pop(), parents.pop()) {
-            if let Some((neighbor, found_version)) = neighbor_nibble(&parent, index, extreme) {
-                // nibble path will represent the left nibble path. this is currently at
-                // the parent of the leaf for `key`
-                path.push(neighbor);
-                return Ok(Some(self.get_extreme_key_hash(
-                    version,
-                    NodeKey::new(found_version, path.clone()),
-                    path.num_nibbles(),
-                    extreme.opposite(),
-                )?));
+    /// Returns a [`ConsistencyProof`] of the changes between the trees at `old_version` and
+    /// `new_version`. It holds the nodes of both trees where they differ, and leaves out the
+    /// subtrees they share, so its size grows with the changes rather than with the tree.
+    pub fn get_consistency_proof(
+        &self,
+        old_version: Version,
+        new_version: Version,
+    ) -> Result<ConsistencyProof<H>> {
+        let root = |version| -> Result<_> {
+            let root_hash = self
+                .get_root_hash_option(version)?
+                .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+            Ok(Some((NodeKey::new_empty_path(version), root_hash.0)))
+        };
+        let mut old_nodes = Vec::new();
+        let mut new_nodes = Vec::new();
+        self.collect_consistency_nodes(
+            root(old_version)?,
+            root(new_version)?,
+            &mut old_nodes,
+            &mut new_nodes,
+        )?;
+        Ok(ConsistencyProof::new(old_nodes, new_nodes))
+    }
+
+    /// Collects the nodes of the subtrees at the same position in both trees, given by their node
+    /// keys and hashes, down to the subtrees they share.
+    fn collect_consistency_nodes(
+        &self,
+        old: ConsistencySubtree,
+        new: ConsistencySubtree,
+        old_nodes: &mut Vec<Node>,
+        new_nodes: &mut Vec<Node>,
+    ) -> Result<()> {
+        if old.as_ref().map(|(_, hash)| hash) == new.as_ref().map(|(_, hash)| hash) {
+            return Ok(());
+        }
+        let old_children = self.reveal_consistency_node(old, old_nodes)?;
+        let new_children = self.reveal_consistency_node(new, new_nodes)?;
+        for (old_child, new_child) in old_children.into_iter().zip(new_children) {
+            self.collect_consistency_nodes(old_child, new_child, old_nodes, new_nodes)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the node at `node_key`, if any, into `nodes`, and returns the node keys and hashes of
+    /// its children by nibble.
+    fn reveal_consistency_node(
+        &self,
+        node_key: ConsistencySubtree,
+        nodes: &mut Vec<Node>,
+    ) -> Result<[ConsistencySubtree; 16]> {
+        let mut children: [ConsistencySubtree; 16] = Default::default();
+        if let Some((node_key, _)) = node_key {
+            let node = self.reader.get_node(&node_key)?;
+            if let Node::Internal(internal_node) = &node {
+                for (nibble, child) in internal_node.children_unsorted() {
+                    children[u8::from(nibble) as usize] = Some((
+                        node_key.gen_child_node_key(child.version, nibble),
+                        child.hash,
+                    ));
+                }
             }
+            nodes.push(node);
         }
-        Ok(None)
+        Ok(children)
     }
 
-    // given a search_key,
-    fn search_for_closest_node(
+    /// Returns the hash of the value of `key` (if applicable) and the corresponding merkle proof,
+    /// without reading the value itself.
+    ///
+    /// The proof can be checked with [`SparseMerkleProof::verify_value_hash`], which only needs
+    /// the key and the value hash, so that a value can be committed to without being revealed.
+    pub fn get_value_hash_proof(
         &self,
+        key: KeyHash,
         version: Version,
-        search_key: KeyHash,
-    ) -> Result<SearchResult> {
-        let search_path = NibblePath::new(search_key.0.to_vec());
-        let mut search_nibbles = search_path.nibbles();
-        let mut next_node_key = NodeKey::new_empty_path(version);
-        let mut internal_nodes = vec![];
+    ) -> Result<(Option<ValueHash>, SparseMerkleProof<H>)> {
+        let proof = self.get_proof(key, version)?;
+        let value_hash = proof
+            .leaf()
+            .filter(|leaf| leaf.key_hash() == key)
+            .map(|leaf| leaf.value_hash());
+        Ok((value_hash, proof))
+    }
 
-        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
-            let next_node = self.reader.get_node(&next_node_key).map_err(|err| {
-                if nibble_depth == 0 {
-                    anyhow::anyhow!(MissingRootError { version })
-                } else {
-                    err
-                }
-            })?;
+    /// Returns an [`UnchangedProof`] that `key` has the same value in the tree at `from` and in
+    /// the tree at `to`, e.g. to show a light client that a value it cached at `from` is still
+    /// current at `to`. The proof is checked with [`UnchangedProof::verify`] against the root
+    /// hashes of both versions.
+    ///
+    /// Fails if `from` is after `to`, if `key` is absent from either tree, or if its value differs
+    /// between them.
+    pub fn get_unchanged_proof(
+        &self,
+        key: KeyHash,
+        from: Version,
+        to: Version,
+    ) -> Result<UnchangedProof<H>> {
+        ensure!(
+            from <= to,
+            "Version {} to prove unchanged from is after version {}.",
+            from,
+            to
+        );
+        let (from_value_hash, from_proof) = self.get_value_hash_proof(key, from)?;
+        let (to_value_hash, to_proof) = self.get_value_hash_proof(key, to)?;
+        match (from_value_hash, to_value_hash) {
+            (Some(from_value_hash), Some(to_value_hash)) => ensure!(
+                from_value_hash == to_value_hash,
+                "Key {:?} changed between versions {} and {}.",
+                key,
+                from,
+                to
+            ),
+            _ => bail!("Key {:?} is absent at version {} or {}.", key, from, to),
+        }
+        Ok(UnchangedProof::new(from_proof, to_proof))
+    }
 
-            match next_node {
-                Node::Internal(node) => {
-                    internal_nodes.push(node.clone());
-                    let queried_child_index = search_nibbles
-                        .next()
-                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
+    /// Returns the value (if applicable) and the corresponding merkle proof against the
+    /// pre-genesis root, i.e. the state a new genesis is applied on top of when the history
+    /// before it was ditched (see [`TreeCache::new`]).
+    ///
+    /// Fails if the underlying storage has no pre-genesis root.
+    pub fn get_with_proof_pre_genesis(
+        &self,
+        key: KeyHash,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        let pre_genesis_root_key = NodeKey::new_empty_path(PRE_GENESIS_VERSION);
+        ensure!(
+            self.reader
+                .get_node_option(&pre_genesis_root_key)?
+                .is_some(),
+            "No pre-genesis root exists."
+        );
+        self.get_with_proof(key, PRE_GENESIS_VERSION)
+    }
 
-                    let child_node_key =
-                        node.get_only_child_without_siblings(&next_node_key, queried_child_index);
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), but fails with
+    /// [`ProofTooDeep`] instead of building a proof with more than `max_depth` siblings, so that
+    /// the cost of building and verifying a proof can be bounded.
+    pub fn get_with_proof_max_depth(
+        &self,
+        key: KeyHash,
+        version: Version,
+        max_depth: usize,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        let proof = self.get_proof_with_max_depth(key, version, max_depth)?;
+        let value = match proof.leaf() {
+            Some(leaf) if leaf.key_hash() == key => Some(self.reader.get_value(version, key)?),
+            _ => None,
+        };
+        Ok((value, proof))
+    }
+
+    /// Returns the merkle proof of `key` in the tree at `version`.
+    fn get_proof(&self, key: KeyHash, version: Version) -> Result<SparseMerkleProof<H>> {
+        self.get_proof_with_max_depth(key, version, usize::MAX)
+    }
+
+    /// Returns the merkle proof of `key` in the tree at `version`, failing with [`ProofTooDeep`]
+    /// if it has more than `max_depth` siblings.
+    fn get_proof_with_max_depth(
+        &self,
+        key: KeyHash,
+        version: Version,
+        max_depth: usize,
+    ) -> Result<SparseMerkleProof<H>> {
+        let mut siblings = vec![];
+        let leaf = self.fill_proof(key, version, max_depth, &mut siblings)?;
+        Ok(SparseMerkleProof::from_nodes(leaf, siblings))
+    }
+
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), but the value and the
+    /// proof are written into `out`, reusing its allocations, instead of being returned.
+    ///
+    /// This avoids allocating a list of siblings for every proof when serving many of them. If
+    /// this fails, the content of `out` is unspecified until the next successful call.
+    pub fn get_with_proof_into(
+        &self,
+        key: KeyHash,
+        version: Version,
+        out: &mut ProofBuffer<H>,
+    ) -> Result<()> {
+        let (leaf, siblings) = out.proof.parts_mut();
+        siblings.clear();
+        *leaf = self.fill_proof(key, version, usize::MAX, siblings)?;
+        out.value = match leaf {
+            Some(leaf) if leaf.key_hash() == key => Some(self.reader.get_value(version, key)?),
+            _ => None,
+        };
+        Ok(())
+    }
+
+    /// Returns a single proof of all `keys` in the tree at `version`, which stores each sibling
+    /// shared by their paths once. See [`SparseMerkleMultiproof`].
+    ///
+    /// Duplicated keys are proven once. The proof is verified with
+    /// [`SparseMerkleMultiproof::verify_multiproof`], given the values of the keys present in the
+    /// tree, which this does not read.
+    pub fn get_multiproof(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<SparseMerkleMultiproof<H>> {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+        let proofs = keys
+            .iter()
+            .map(|key| self.get_proof(*key, version))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SparseMerkleMultiproof::from_proofs(&keys, &proofs))
+    }
+
+    /// Returns a proof of `keys`, which must all start with `prefix`, in the tree at `version`,
+    /// split at the root of the subtree holding the namespace `prefix`. See [`NamespaceProof`].
+    ///
+    /// Duplicated keys are proven once. Since a subtree holding a single key is lifted into its
+    /// parent, the subtree has no root of its own then: fails unless the namespace holds at least
+    /// two keys at `version`, or `prefix` is empty. Fails if `keys` is empty.
+    pub fn get_namespace_proof(
+        &self,
+        prefix: &[Nibble],
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<NamespaceProof<H>> {
+        ensure!(
+            !keys.is_empty(),
+            "No key to prove in namespace {:?}.",
+            prefix
+        );
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+        for key in &keys {
+            ensure!(
+                NibblePath::new(key.0.to_vec())
+                    .nibbles()
+                    .zip(prefix)
+                    .all(|(a, b)| a == *b),
+                "Key {:?} is not in namespace {:?}.",
+                key,
+                prefix
+            );
+        }
+        let subtree_root_hash = self.subtree_root_hash(prefix, version)?;
+        let proofs = keys
+            .iter()
+            .map(|key| self.get_proof(*key, version))
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            proofs[0].siblings().len() > prefix.len() * 4,
+            "Namespace {:?} holds fewer than two keys at version {}.",
+            prefix,
+            version
+        );
+        Ok(NamespaceProof::from_proofs(
+            prefix.iter().copied().collect(),
+            subtree_root_hash,
+            &keys,
+            &proofs,
+        ))
+    }
+
+    /// Returns the same proof as [`get_multiproof`](JellyfishMerkleTree::get_multiproof), but
+    /// reads far less when `keys` are adjacent: present in the tree at `version`, with no other
+    /// key of the tree between them, like a window of keys in hash order.
+    ///
+    /// The paths of adjacent keys share all their siblings but those on the left of the first key
+    /// and on the right of the last one, so only these two paths are read, along with the values
+    /// of the other keys. If `keys` turn out not to be adjacent, this falls back to
+    /// `get_multiproof`.
+    ///
+    /// Fails if `keys` are not sorted and distinct.
+    pub fn get_adjacent_proof(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<SparseMerkleMultiproof<H>> {
+        ensure!(
+            keys.windows(2).all(|pair| pair[0] < pair[1]),
+            "Keys of an adjacent proof must be sorted and distinct."
+        );
+        if keys.len() <= 2 {
+            return self.get_multiproof(keys, version);
+        }
+        let (first, last) = (keys[0], keys[keys.len() - 1]);
+        let first_proof = self.get_proof(first, version)?;
+        let last_proof = self.get_proof(last, version)?;
+        let is_inclusion = |proof: &SparseMerkleProof<H>, key: KeyHash| {
+            proof.leaf().map(|leaf| leaf.key_hash()) == Some(key)
+        };
+        if !is_inclusion(&first_proof, first) || !is_inclusion(&last_proof, last) {
+            return self.get_multiproof(keys, version);
+        }
+
+        // The sibling at `depth` on the path of an inner key is on the path of the first or the
+        // last key if that key shares the path down to it. Otherwise, it lies between two keys,
+        // and is empty if they are adjacent.
+        let boundary_sibling = |key: &KeyHash, depth: usize| {
+            for (proof_key, proof) in [(first, &first_proof), (last, &last_proof)] {
+                let proof_depth = proof.siblings().len();
+                if key.0.common_prefix_bits_len(&proof_key.0) > depth && depth < proof_depth {
+                    return proof.siblings()[proof_depth - 1 - depth];
+                }
+            }
+            SparseMerkleNode::Null
+        };
+        let mut proofs = Vec::with_capacity(keys.len());
+        proofs.push(first_proof.clone());
+        for window in keys.windows(3) {
+            let (previous, key, next) = (&window[0], &window[1], &window[2]);
+            let value =
+                self.reader
+                    .get_value_option(version, *key)
+                    .context(ReadError::BackendValue {
+                        max_version: version,
+                        key_hash: *key,
+                    })?;
+            let Some(value) = value else {
+                return self.get_multiproof(keys, version);
+            };
+            // A leaf sits right below the longest prefix it shares with its neighbors.
+            let depth = 1 + key
+                .0
+                .common_prefix_bits_len(&previous.0)
+                .max(key.0.common_prefix_bits_len(&next.0));
+            let siblings = (0..depth)
+                .rev()
+                .map(|depth| boundary_sibling(key, depth))
+                .collect();
+            let leaf = SparseMerkleLeafNode::new(*key, ValueHash::with::<H>(value));
+            proofs.push(SparseMerkleProof::from_nodes(Some(leaf), siblings));
+        }
+        proofs.push(last_proof.clone());
+
+        let proof = SparseMerkleMultiproof::from_proofs(keys, &proofs);
+        if proof.root_hash(keys)? != self.get_root_hash(version)? {
+            return self.get_multiproof(keys, version);
+        }
+        Ok(proof)
+    }
+
+    /// Appends the siblings of the merkle proof of `key` in the tree at `version` to the empty
+    /// `siblings`, from the bottom level to the root level, and returns the leaf of the proof.
+    /// Fails with [`ProofTooDeep`] if the proof has more than `max_depth` siblings.
+    fn fill_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+        max_depth: usize,
+        siblings: &mut Vec<SparseMerkleNode>,
+    ) -> Result<Option<SparseMerkleLeafNode>> {
+        let leaf = self.walk_path(key, version, |node_key, node, child_index| match node {
+            Node::Internal(internal_node) => {
+                let queried_child_index =
+                    child_index.ok_or_else(|| format_err!("ran out of nibbles"))?;
+
+                let child_node_key = internal_node.append_only_child_siblings::<H>(
+                    self.reader,
+                    node_key,
+                    queried_child_index,
+                    siblings,
+                )?;
+                if siblings.len() > max_depth {
+                    return Err(anyhow::anyhow!(ProofTooDeep {
+                        key_hash: key,
+                        max_depth,
+                    }));
+                }
+                Ok(match child_node_key {
+                    Some(child_node_key) => ControlFlow::Continue(child_node_key),
+                    None => ControlFlow::Break(None),
+                })
+            }
+            Node::Leaf(leaf_node) => Ok(ControlFlow::Break(Some(leaf_node.into()))),
+            // Empty tree just returns proof with no sibling hash.
+            Node::Null => ensure_null_node_is_root(node_key).map(|()| ControlFlow::Break(None)),
+        })?;
+        siblings.reverse();
+        Ok(leaf)
+    }
+
+    /// Walks the path of `key` in the tree at `version`, from the root down. `visit` is called
+    /// with the key of each node on the path, the node, and the nibble of `key` leading to its
+    /// child, if any is left, and returns either the key of the next node to visit or the result
+    /// of the walk.
+    ///
+    /// A missing root is reported as a [`MissingRootError`].
+    fn walk_path<T>(
+        &self,
+        key: KeyHash,
+        version: Version,
+        mut visit: impl FnMut(&NodeKey, Node, Option<Nibble>) -> Result<ControlFlow<T, NodeKey>>,
+    ) -> Result<T> {
+        let mut node_key = NodeKey::new_empty_path(version);
+        let nibble_path = NibblePath::new(key.0.to_vec());
+        let mut nibble_iter = nibble_path.nibbles();
+
+        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
+        // in the tree structure.
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let node = self.reader.get_node(&node_key).map_err(|err| {
+                if nibble_depth == 0 {
+                    missing_root_or(err, version)
+                } else {
+                    err
+                }
+            })?;
+            match visit(&node_key, node, nibble_iter.next())? {
+                ControlFlow::Continue(child_node_key) => node_key = child_node_key,
+                ControlFlow::Break(result) => return Ok(result),
+            }
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns the number of siblings in the merkle proof of `key` at `version` that are not
+    /// placeholders, without building the proof. No sibling hash is computed, which makes this
+    /// much cheaper than [`get_with_proof`](JellyfishMerkleTree::get_with_proof) when only the
+    /// size of the proof is of interest.
+    pub fn proof_depth(&self, key: KeyHash, version: Version) -> Result<usize> {
+        let mut sibling_count = 0;
+        self.walk_path(key, version, |node_key, node, child_index| match node {
+            Node::Internal(internal_node) => {
+                let queried_child_index =
+                    child_index.ok_or_else(|| format_err!("ran out of nibbles"))?;
+                let (child_node_key, count) =
+                    internal_node.get_only_child_with_sibling_count(node_key, queried_child_index);
+                sibling_count += count;
+                Ok(match child_node_key {
+                    Some(child_node_key) => ControlFlow::Continue(child_node_key),
+                    None => ControlFlow::Break(sibling_count),
+                })
+            }
+            Node::Leaf(_) => Ok(ControlFlow::Break(sibling_count)),
+            Node::Null => ensure_null_node_is_root(node_key).map(|()| ControlFlow::Break(0)),
+        })
+    }
+
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), but nodes and values are
+    /// looked up in `overlay` before the underlying storage, which is only read for the ones
+    /// missing from it, as with an [`OverlayReader`](crate::storage::OverlayReader). This lets
+    /// related proof requests share a working set of nodes already held in memory.
+    pub fn get_with_proof_with_overlay(
+        &self,
+        key: KeyHash,
+        version: Version,
+        overlay: &NodeBatch,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        let reader = OverlayReader::new(self.reader, overlay);
+        JellyfishMerkleTree::<_, H>::new(&reader).get_with_proof(key, version)
+    }
+
+    fn search_closest_extreme_node(
+        &self,
+        version: Version,
+        extreme: Extreme,
+        to: NibblePath,
+        parents: Vec<InternalNode>,
+    ) -> Result<Option<LeafNode>> {
+        fn neighbor_nibble(
+            node: &InternalNode,
+            child_index: Nibble,
+            extreme: Extreme,
+        ) -> Option<(Nibble, Version)> {
+            match extreme {
+                // Rightmost left neighbor
+                Extreme::Left => node
+                    .children_unsorted()
+                    .filter(|(nibble, _)| nibble < &child_index)
+                    .max_by_key(|(nibble, _)| *nibble)
+                    .map(|p| (p.0, p.1.version)),
+                // Leftmost right neighbor
+                Extreme::Right => node
+                    .children_unsorted()
+                    .filter(|(nibble, _)| nibble > &child_index)
+                    .min_by_key(|(nibble, _)| *nibble)
+                    .map(|p| (p.0, p.1.version)),
+            }
+        }
+        let mut parents = parents;
+        let mut path = to;
+
+        while let (Some(index), Some(parent)) = (path.pop(), parents.pop()) {
+            if let Some((neighbor, found_version)) = neighbor_nibble(&parent, index, extreme) {
+                // nibble path will represent the left nibble path. this is currently at
+                // the parent of the leaf for `key`
+                path.push(neighbor);
+                return Ok(Some(self.get_extreme_leaf(
+                    version,
+                    NodeKey::new(found_version, path.clone()),
+                    path.num_nibbles(),
+                    extreme.opposite(),
+                )?));
+            }
+        }
+        Ok(None)
+    }
+
+    // given a search_key,
+    fn search_for_closest_node(
+        &self,
+        version: Version,
+        search_key: KeyHash,
+    ) -> Result<SearchResult> {
+        let search_path = NibblePath::new(search_key.0.to_vec());
+        let mut search_nibbles = search_path.nibbles();
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let mut internal_nodes = vec![];
+
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let next_node = self.reader.get_node(&next_node_key).map_err(|err| {
+                if nibble_depth == 0 {
+                    missing_root_or(err, version)
+                } else {
+                    err
+                }
+            })?;
+
+            match next_node {
+                Node::Internal(node) => {
+                    internal_nodes.push(node.clone());
+                    let queried_child_index = search_nibbles
+                        .next()
+                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
+
+                    let child_node_key =
+                        node.get_only_child_without_siblings(&next_node_key, queried_child_index);
 
                     match child_node_key {
                         Some(node_key) => {
@@ -1170,10 +1947,9 @@ where
                     }
                 }
                 Node::Leaf(node) => {
-                    let key_hash = node.key_hash();
                     return Ok(SearchResult::FoundLeaf {
-                        ordering: key_hash.cmp(&search_key),
-                        leaf_hash: key_hash,
+                        ordering: node.key_hash().cmp(&search_key),
+                        leaf: node,
                         path_to_leaf: search_nibbles.visited_nibbles().get_nibble_path(),
                         parents: internal_nodes,
                     });
@@ -1206,31 +1982,36 @@ where
         match search_result {
             SearchResult::FoundLeaf {
                 ordering,
-                leaf_hash,
+                leaf,
                 path_to_leaf,
                 parents,
             } => {
+                let leaf_hash = leaf.key_hash();
                 match ordering {
                     Ordering::Less => {
                         // found the closest leaf to the left of the search key.
                         // find the other bound (the leftmost right keyhash)
-                        let leftmost_right_keyhash = self.search_closest_extreme_node(
-                            version,
-                            Extreme::Right,
-                            path_to_leaf,
-                            parents,
-                        )?;
+                        let leftmost_right_keyhash = self
+                            .search_closest_extreme_node(
+                                version,
+                                Extreme::Right,
+                                path_to_leaf,
+                                parents,
+                            )?
+                            .map(|leaf| leaf.key_hash());
 
                         Ok((Some(leaf_hash), leftmost_right_keyhash))
                     }
                     Ordering::Greater => {
                         // found the closest leaf to the right of the search key
-                        let rightmost_left_keyhash = self.search_closest_extreme_node(
-                            version,
-                            Extreme::Left,
-                            path_to_leaf,
-                            parents,
-                        )?;
+                        let rightmost_left_keyhash = self
+                            .search_closest_extreme_node(
+                                version,
+                                Extreme::Left,
+                                path_to_leaf,
+                                parents,
+                            )?
+                            .map(|leaf| leaf.key_hash());
 
                         Ok((rightmost_left_keyhash, Some(leaf_hash)))
                     }
@@ -1243,24 +2024,63 @@ where
                 path_to_internal,
                 parents,
             } => {
-                let leftmost_right_keyhash = self.search_closest_extreme_node(
-                    version,
-                    Extreme::Right,
-                    path_to_internal.clone(),
-                    parents.clone(),
-                )?;
-                let rightmost_left_keyhash = self.search_closest_extreme_node(
-                    version,
-                    Extreme::Left,
-                    path_to_internal,
-                    parents,
-                )?;
+                let leftmost_right_keyhash = self
+                    .search_closest_extreme_node(
+                        version,
+                        Extreme::Right,
+                        path_to_internal.clone(),
+                        parents.clone(),
+                    )?
+                    .map(|leaf| leaf.key_hash());
+                let rightmost_left_keyhash = self
+                    .search_closest_extreme_node(version, Extreme::Left, path_to_internal, parents)?
+                    .map(|leaf| leaf.key_hash());
 
                 Ok((rightmost_left_keyhash, leftmost_right_keyhash))
             }
         }
     }
 
+    /// Returns the leaf of the tree at `version` whose key is the closest to `target` in
+    /// `direction`: the leaf with the greatest key not above `target` for [`Direction::Below`],
+    /// and the one with the smallest key not below `target` for [`Direction::Above`]. The leaf of
+    /// `target` itself is returned if it is in the tree. Returns `None` if no key of the tree lies
+    /// on that side of `target`.
+    ///
+    /// This descends to where the path of `target` leaves the tree, and from there to the closest
+    /// leaf on the side of `direction`.
+    pub fn get_closest_leaf(
+        &self,
+        target: KeyHash,
+        version: Version,
+        direction: Direction,
+    ) -> Result<Option<LeafNode>> {
+        if let Some(Node::Null) = self.get_root_node_option(version)? {
+            return Ok(None);
+        }
+        let extreme = match direction {
+            Direction::Below => Extreme::Left,
+            Direction::Above => Extreme::Right,
+        };
+        match self.search_for_closest_node(version, target)? {
+            SearchResult::FoundLeaf {
+                ordering,
+                leaf,
+                path_to_leaf,
+                parents,
+            } => match (ordering, direction) {
+                (Ordering::Equal, _)
+                | (Ordering::Less, Direction::Below)
+                | (Ordering::Greater, Direction::Above) => Ok(Some(leaf)),
+                _ => self.search_closest_extreme_node(version, extreme, path_to_leaf, parents),
+            },
+            SearchResult::FoundInternal {
+                path_to_internal,
+                parents,
+            } => self.search_closest_extreme_node(version, extreme, path_to_internal, parents),
+        }
+    }
+
     /// Returns the value (if applicable) and the corresponding merkle proof.
     pub fn get_with_exclusion_proof(
         &self,
@@ -1305,13 +2125,13 @@ where
         }
     }
 
-    fn get_extreme_key_hash(
+    fn get_extreme_leaf(
         &self,
         version: Version,
         mut node_key: NodeKey,
         nibble_depth: usize,
         extreme: Extreme,
-    ) -> Result<KeyHash> {
+    ) -> Result<LeafNode> {
         // Depending on the extreme specified, get either the least nibble or the most nibble
         let min_or_max = |internal_node: &InternalNode| {
             match extreme {
@@ -1324,7 +2144,7 @@ where
         for nibble_depth in nibble_depth..=ROOT_NIBBLE_HEIGHT {
             let node = self.reader.get_node(&node_key).map_err(|err| {
                 if nibble_depth == 0 {
-                    anyhow::anyhow!(MissingRootError { version })
+                    missing_root_or(err, version)
                 } else {
                     err
                 }
@@ -1345,7 +2165,7 @@ where
                     };
                 }
                 Node::Leaf(leaf_node) => {
-                    return Ok(leaf_node.key_hash());
+                    return Ok(leaf_node);
                 }
                 Node::Null => bail!("Null node cannot have children"),
             }
@@ -1354,7 +2174,12 @@ where
     }
 
     fn get_without_proof(&self, key: KeyHash, version: Version) -> Result<Option<OwnedValue>> {
-        self.reader.get_value_option(version, key)
+        self.reader
+            .get_value_option(version, key)
+            .context(ReadError::BackendValue {
+                max_version: version,
+                key_hash: key,
+            })
     }
 
     /// Gets the proof that shows a list of keys up to `rightmost_key_to_prove` exist at `version`.
@@ -1392,6 +2217,160 @@ where
         self.get_without_proof(key, version)
     }
 
+    /// Returns the value of `key` (if applicable) in the tree at `latest - max_age`, along with that
+    /// version, for reads that tolerate data up to `max_age` versions old. The returned value may
+    /// therefore be stale: it need not reflect the changes committed after that version.
+    ///
+    /// Reading at a version that lags behind `latest` lets concurrent readers share the same
+    /// version, and so the nodes, values and root hashes cached for it. If the root of that
+    /// version is not available, e.g. because it was pruned, the value is read at `latest`
+    /// instead.
+    ///
+    /// The root of `latest - max_age` is the only node read: the value itself is read straight
+    /// from the value storage, which does not tell whether that root is available. Use
+    /// [`get_value_bounded_staleness_cached`](JellyfishMerkleTree::get_value_bounded_staleness_cached)
+    /// to look that root up in a [`RootHashCache`] instead.
+    pub fn get_value_bounded_staleness(
+        &self,
+        key: KeyHash,
+        latest: Version,
+        max_age: u64,
+    ) -> Result<(Option<OwnedValue>, Version)> {
+        let oldest = latest.saturating_sub(max_age);
+        let version = if self.get_root_node_option(oldest)?.is_some() {
+            oldest
+        } else {
+            latest
+        };
+        Ok((self.get(key, version)?, version))
+    }
+
+    /// Same as
+    /// [`get_value_bounded_staleness`](JellyfishMerkleTree::get_value_bounded_staleness), but
+    /// checks that the root of `latest - max_age` is available by looking its hash up in `cache`
+    /// first, and caches it otherwise, so that readers sharing that version only read its root
+    /// once.
+    #[cfg(feature = "std")]
+    pub fn get_value_bounded_staleness_cached(
+        &self,
+        key: KeyHash,
+        latest: Version,
+        max_age: u64,
+        cache: &RootHashCache,
+    ) -> Result<(Option<OwnedValue>, Version)> {
+        let oldest = latest.saturating_sub(max_age);
+        let version = if cache.cached_root_hash(oldest).is_some() {
+            oldest
+        } else if let Some(root_hash) = self.get_root_hash_option(oldest)? {
+            cache.insert(oldest, root_hash);
+            oldest
+        } else {
+            latest
+        };
+        Ok((self.get(key, version)?, version))
+    }
+
+    /// Returns every change to the value of `key` up to and including version `up_to`, oldest
+    /// first. Each entry holds the version at which the change was committed and the new value, or
+    /// `None` if the key was deleted at that version.
+    ///
+    /// The history is read from the tree itself, by walking back through the versions that last
+    /// modified the path to `key`, so the number of versions visited depends on the number of
+    /// changes under that path rather than on `up_to`. Only versions whose root is available are
+    /// covered: the walk stops at the first missing root, e.g. before genesis or at a pruned
+    /// version.
+    pub fn key_history(
+        &self,
+        key: KeyHash,
+        up_to: Version,
+    ) -> Result<Vec<(Version, Option<OwnedValue>)>> {
+        if self.get_root_node_option(up_to)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError { version: up_to }));
+        }
+
+        // `value_hash` is the state of `key` in all the versions from `since` to the last one
+        // walked.
+        let (leaf, mut since) = self.get_leaf_and_last_update(key, up_to)?;
+        let mut value_hash = leaf.map(|leaf| leaf.value_hash());
+        let mut changes = Vec::new();
+        while since > 0 && self.get_root_node_option(since - 1)?.is_some() {
+            let (leaf, earlier_since) = self.get_leaf_and_last_update(key, since - 1)?;
+            let earlier_value_hash = leaf.map(|leaf| leaf.value_hash());
+            if earlier_value_hash != value_hash {
+                changes.push((since, value_hash.is_some()));
+                value_hash = earlier_value_hash;
+            }
+            since = earlier_since;
+        }
+        if value_hash.is_some() {
+            changes.push((since, true));
+        }
+
+        changes
+            .into_iter()
+            .rev()
+            .map(|(version, exists)| {
+                let value = if exists {
+                    Some(self.reader.get_value(version, key)?)
+                } else {
+                    None
+                };
+                Ok((version, value))
+            })
+            .collect()
+    }
+
+    /// Returns the key of every node on the path to `key` in the tree at `version`, from the root
+    /// down to the leaf or to the internal node without a child towards `key`, along with the
+    /// version at which the node was written.
+    ///
+    /// This is a diagnostic: since writing a node rewrites all its ancestors, versions never
+    /// increase along a valid path, and a node with a higher version than its parent indicates a
+    /// corrupted store. Fails if the root of `version` or a node on the path is missing.
+    pub fn path_versions(&self, key: KeyHash, version: Version) -> Result<Vec<(NodeKey, Version)>> {
+        let mut path = Vec::new();
+        self.walk_path(key, version, |node_key, node, child_index| {
+            path.push((node_key.clone(), node_key.version()));
+            let Node::Internal(internal_node) = node else {
+                return Ok(ControlFlow::Break(()));
+            };
+            let child_index = child_index.ok_or_else(|| format_err!("ran out of nibbles"))?;
+            Ok(match internal_node.child(child_index) {
+                Some(child) => {
+                    ControlFlow::Continue(node_key.gen_child_node_key(child.version, child_index))
+                }
+                None => ControlFlow::Break(()),
+            })
+        })?;
+        Ok(path)
+    }
+
+    /// Follows the path of `key` in the tree at `version`, and returns the leaf holding `key` if
+    /// there is one, along with the version of the last node on the path. The state of `key` is
+    /// the same in every version from that one to `version`.
+    fn get_leaf_and_last_update(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<LeafNode>, Version)> {
+        self.walk_path(key, version, |node_key, node, child_index| match node {
+            Node::Internal(internal_node) => {
+                let child_index = child_index.ok_or_else(|| format_err!("ran out of nibbles"))?;
+                Ok(match internal_node.child(child_index) {
+                    Some(child) => ControlFlow::Continue(
+                        node_key.gen_child_node_key(child.version, child_index),
+                    ),
+                    None => ControlFlow::Break((None, node_key.version())),
+                })
+            }
+            Node::Leaf(leaf_node) => {
+                let leaf = (leaf_node.key_hash() == key).then_some(leaf_node);
+                Ok(ControlFlow::Break((leaf, node_key.version())))
+            }
+            Node::Null => Ok(ControlFlow::Break((None, node_key.version()))),
+        })
+    }
+
     fn get_root_node(&self, version: Version) -> Result<Node> {
         self.get_root_node_option(version)?
             .ok_or_else(|| format_err!("Root node not found for version {}.", version))
@@ -1399,23 +2378,790 @@ where
 
     pub(crate) fn get_root_node_option(&self, version: Version) -> Result<Option<Node>> {
         let root_node_key = NodeKey::new_empty_path(version);
-        self.reader.get_node_option(&root_node_key)
+        self.reader
+            .get_node_option(&root_node_key)
+            .context(ReadError::Backend(root_node_key))
     }
 
     pub fn get_root_hash(&self, version: Version) -> Result<RootHash> {
         self.get_root_node(version).map(|n| RootHash(n.hash::<H>()))
     }
 
+    /// Same as [`get_root_hash`](JellyfishMerkleTree::get_root_hash), but looks the root hash
+    /// up in `cache` first, and caches it otherwise. See [`RootHashCache`] for which versions are
+    /// cached.
+    #[cfg(feature = "std")]
+    pub fn get_root_hash_cached(
+        &self,
+        version: Version,
+        cache: &RootHashCache,
+    ) -> Result<RootHash> {
+        if let Some(root_hash) = cache.cached_root_hash(version) {
+            return Ok(root_hash);
+        }
+        let root_hash = self.get_root_hash(version)?;
+        cache.insert(version, root_hash);
+        Ok(root_hash)
+    }
+
+    /// Commits `batch` to `writer` with [`TreeWriter::commit`], and caches the `root_hashes` of
+    /// the versions it covers in `cache` once they are persisted, so that later reads of these
+    /// versions through [`get_root_hash_cached`](JellyfishMerkleTree::get_root_hash_cached) do not
+    /// hit storage.
+    #[cfg(feature = "std")]
+    pub fn commit_cached<W: TreeWriter>(
+        &self,
+        writer: &W,
+        root_hashes: Vec<RootHash>,
+        batch: TreeUpdateBatch,
+        cache: &RootHashCache,
+    ) -> Result<Vec<CommittedRoot>> {
+        // Every version of the batch puts its root, so the last one holds the latest root.
+        let latest_version = batch.node_batch.latest_version();
+        let committed_roots = writer.commit(root_hashes, batch)?;
+        if let Some(latest_version) = latest_version {
+            let first_version = latest_version + 1 - committed_roots.len() as Version;
+            for (version, committed_root) in (first_version..).zip(&committed_roots) {
+                cache.insert(version, committed_root.root_hash());
+            }
+        }
+        Ok(committed_roots)
+    }
+
     pub fn get_root_hash_option(&self, version: Version) -> Result<Option<RootHash>> {
         Ok(self
             .get_root_node_option(version)?
             .map(|n| RootHash(n.hash::<H>())))
     }
 
+    /// Returns whether the trees at versions `a` and `b` have the same root hash, e.g. to collapse
+    /// the versions committed by no-op transactions. Only the two root nodes are read.
+    pub fn roots_equal(&self, a: Version, b: Version) -> Result<bool> {
+        Ok(self.get_root_hash(a)? == self.get_root_hash(b)?)
+    }
+
+    /// Returns the root hash of the subtree holding the keys starting with `prefix` at
+    /// `version`, i.e. the hash the parent internal node records for that subtree: the
+    /// placeholder hash if there is no such key, the hash of the leaf if there is exactly one,
+    /// and the hash of the internal node at `prefix` otherwise.
+    ///
+    /// Combining the hashes of the 16 subtrees below a prefix with the internal node rule
+    /// reproduces the hash at that prefix, and eventually the root hash. Since the rule lifts a
+    /// subtree holding a single leaf, this requires knowing which subtrees are single leaves.
+    pub fn subtree_root_hash(&self, prefix: &[Nibble], version: Version) -> Result<[u8; 32]> {
+        ensure!(
+            prefix.len() <= ROOT_NIBBLE_HEIGHT,
+            "Prefix of {} nibbles is longer than a key.",
+            prefix.len()
+        );
+        let mut node_key = NodeKey::new_empty_path(version);
+        let mut node = self
+            .reader
+            .get_node_option(&node_key)?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+
+        for (depth, nibble) in prefix.iter().enumerate() {
+            match node {
+                Node::Internal(internal_node) => match internal_node.child(*nibble) {
+                    Some(child) => {
+                        node_key = node_key.gen_child_node_key(child.version, *nibble);
+                        node = self.reader.get_node(&node_key)?;
+                    }
+                    None => return Ok(SPARSE_MERKLE_PLACEHOLDER_HASH),
+                },
+                Node::Leaf(leaf_node) => {
+                    let leaf_nibble_path = NibblePath::new(leaf_node.key_hash().0.to_vec());
+                    let leaf_has_prefix = leaf_nibble_path
+                        .nibbles()
+                        .skip(depth)
+                        .zip(&prefix[depth..])
+                        .all(|(a, b)| a == *b);
+                    return Ok(if leaf_has_prefix {
+                        leaf_node.hash::<H>()
+                    } else {
+                        SPARSE_MERKLE_PLACEHOLDER_HASH
+                    });
+                }
+                Node::Null => return Ok(SPARSE_MERKLE_PLACEHOLDER_HASH),
+            }
+        }
+        Ok(node.hash::<H>())
+    }
+
+    /// Returns an iterator over the key hashes of all the leaves of the tree at `version`, in key
+    /// order, e.g. to replicate the key space to an external index. Like
+    /// [`JellyfishMerkleIterator::hashes_only`], it never reads values.
+    pub fn key_set(&self, version: Version) -> Result<impl Iterator<Item = Result<KeyHash>> + 'a> {
+        Ok(
+            JellyfishMerkleIterator::hashes_only(Arc::new(self.reader), version)?
+                .map(|result| result.map(|(key_hash, _)| key_hash)),
+        )
+    }
+
     // TODO: should this be public? seems coupled to tests?
     pub fn get_leaf_count(&self, version: Version) -> Result<usize> {
         self.get_root_node(version).map(|n| n.leaf_count())
     }
+
+    /// Returns the smallest and the largest key hashes in the tree at `version`, or `None` if the
+    /// tree is empty, e.g. to split the key space into shards.
+    ///
+    /// Only the leftmost and the rightmost paths of the tree are read. Note that
+    /// [`TreeReader::get_rightmost_leaf`] cannot be used instead, since it ignores versions.
+    pub fn key_bounds(&self, version: Version) -> Result<Option<(KeyHash, KeyHash)>> {
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = self
+            .reader
+            .get_node(&root_node_key)
+            .map_err(|err| missing_root_or(err, version))?;
+        if let Node::Null = root_node {
+            return Ok(None);
+        }
+
+        let first = self.get_extreme_leaf(version, root_node_key.clone(), 0, Extreme::Left)?;
+        let last = self.get_extreme_leaf(version, root_node_key, 0, Extreme::Right)?;
+        Ok(Some((first.key_hash(), last.key_hash())))
+    }
+
+    /// Returns the number of keys smaller than `key` in the tree at `version`, whether or not
+    /// `key` itself is present, e.g. to split the key space into shards of even size.
+    ///
+    /// Every internal node records the number of leaves under each of its children, so only the
+    /// nodes on the path to `key` are read.
+    pub fn rank(&self, key: KeyHash, version: Version) -> Result<usize> {
+        let mut rank = 0;
+        self.walk_path(key, version, |node_key, node, child_index| match node {
+            Node::Internal(internal_node) => {
+                let child_index = child_index.ok_or_else(|| format_err!("ran out of nibbles"))?;
+                rank += internal_node
+                    .children_sorted()
+                    .take_while(|(nibble, _)| *nibble < child_index)
+                    .map(|(_, child)| child.leaf_count())
+                    .sum::<usize>();
+                Ok(match internal_node.child(child_index) {
+                    Some(child) => ControlFlow::Continue(
+                        node_key.gen_child_node_key(child.version, child_index),
+                    ),
+                    None => ControlFlow::Break(rank),
+                })
+            }
+            Node::Leaf(leaf_node) => Ok(ControlFlow::Break(
+                rank + usize::from(leaf_node.key_hash() < key),
+            )),
+            Node::Null => Ok(ControlFlow::Break(rank)),
+        })
+    }
+
+    /// Rolls the tree back to `version`, discarding every version committed after it.
+    ///
+    /// All nodes created after `version` are collected by walking the roots of the later versions,
+    /// and handed to `writer` in a [`TruncationBatch`] together with `version`, so that the storage
+    /// can also drop the values and [`StaleNodeIndex`](crate::storage::StaleNodeIndex)es recorded
+    /// after it. Once this returns, the tree reads as if it had only ever been committed up to
+    /// `version`, and new versions can be applied on top of it again.
+    ///
+    /// The versions being discarded must not have been pruned, since their nodes need to be read
+    /// to be found.
+    pub fn truncate_to<W: TreeTruncator>(&self, writer: &W, version: Version) -> Result<()> {
+        if self.get_root_node_option(version)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+
+        let mut node_keys = BTreeSet::new();
+        let mut next_version = version + 1;
+        while let Some(root_node) = self.get_root_node_option(next_version)? {
+            let mut pending = vec![(NodeKey::new_empty_path(next_version), root_node)];
+            while let Some((node_key, node)) = pending.pop() {
+                if let Node::Internal(internal_node) = &node {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                        // Children that were created at or before `version` are kept, and so is
+                        // everything below them.
+                        if child.version > version && !node_keys.contains(&child_node_key) {
+                            let child_node = self.reader.get_node(&child_node_key)?;
+                            pending.push((child_node_key, child_node));
+                        }
+                    }
+                }
+                node_keys.insert(node_key);
+            }
+            next_version += 1;
+        }
+
+        writer.truncate(&TruncationBatch { version, node_keys })
+    }
+
+    /// Same as [`truncate_to`](JellyfishMerkleTree::truncate_to), but also drops the root hashes
+    /// of the discarded versions from `cache`, so that versions committed again after the rollback
+    /// are not served their former root hashes.
+    #[cfg(feature = "std")]
+    pub fn truncate_to_cached<W: TreeTruncator>(
+        &self,
+        writer: &W,
+        version: Version,
+        cache: &RootHashCache,
+    ) -> Result<()> {
+        self.truncate_to(writer, version)?;
+        cache.truncate_to(version);
+        Ok(())
+    }
+
+    /// Returns the number of versions committed so far, see [`TreeReader::num_versions`].
+    pub fn version_count(&self) -> Result<u64> {
+        self.reader.num_versions()
+    }
+
+    /// Returns the keys of the stored nodes that cannot be reached from the root of any of the
+    /// `live_versions`, in key order. Since pruning through the stale node index removes the nodes that
+    /// become unreachable, such nodes are left behind by bugs in writers.
+    ///
+    /// This is an offline audit: it reads every node reachable from the live roots, and requires
+    /// the storage to enumerate all its nodes with [`TreeReader::get_node_keys`]. Fails if the root
+    /// of a live version is missing.
+    pub fn find_orphans(&self, live_versions: &[Version]) -> Result<Vec<NodeKey>> {
+        let mut reachable = BTreeSet::new();
+        for &version in live_versions {
+            let root_node_key = NodeKey::new_empty_path(version);
+            if reachable.contains(&root_node_key) {
+                continue;
+            }
+            let root_node = self
+                .get_root_node_option(version)?
+                .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+            let mut pending = vec![(root_node_key, root_node)];
+            while let Some((node_key, node)) = pending.pop() {
+                if let Node::Internal(internal_node) = &node {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                        // Subtrees shared with a version walked before are only walked once.
+                        if !reachable.contains(&child_node_key) {
+                            let child_node = self.reader.get_node(&child_node_key)?;
+                            pending.push((child_node_key, child_node));
+                        }
+                    }
+                }
+                reachable.insert(node_key);
+            }
+        }
+
+        let mut orphans: Vec<NodeKey> = self
+            .reader
+            .get_node_keys()?
+            .into_iter()
+            .filter(|node_key| !reachable.contains(node_key))
+            .collect();
+        orphans.sort_unstable();
+        Ok(orphans)
+    }
+
+    /// Builds a [`MembershipFilter`] of the keys of the tree at `version`, to reject lookups of
+    /// absent keys without descending the tree.
+    ///
+    /// This reads every node of the tree at `version`, but no value. The filter is only valid for
+    /// `version`, so it must be rebuilt after each commit. Fails if the root of `version` is
+    /// missing.
+    pub fn build_membership_filter(&self, version: Version) -> Result<MembershipFilter> {
+        let root_node = self
+            .get_root_node_option(version)?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+        let mut key_hashes = Vec::new();
+        let mut pending = vec![(NodeKey::new_empty_path(version), root_node)];
+        while let Some((node_key, node)) = pending.pop() {
+            match node {
+                Node::Internal(internal_node) => {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                        let child_node = self.reader.get_node(&child_node_key)?;
+                        pending.push((child_node_key, child_node));
+                    }
+                }
+                Node::Leaf(leaf_node) => key_hashes.push(leaf_node.key_hash()),
+                Node::Null => {}
+            }
+        }
+        Ok(MembershipFilter::new(version, &key_hashes))
+    }
+
+    /// Returns the number of nodes that pruning up to `prune_to_version` would delete, and their
+    /// approximate size in bytes, without deleting anything.
+    ///
+    /// These are the nodes of the [`StaleNodeIndex`](crate::storage::StaleNodeIndex)es stale since
+    /// `prune_to_version` or earlier, as listed by [`TreeReader::stale_versions`] and
+    /// [`TreeReader::get_stale_nodes_since`]. Nodes already deleted from storage are not counted.
+    /// The size of a node is the length of its key and of itself as encoded by
+    /// [`NodeKey::to_bytes`] and [`Node::to_bytes`], which ignores the overhead of the storage.
+    pub fn prunable_stats(&self, prune_to_version: Version) -> Result<(usize, usize)> {
+        let mut num_nodes = 0;
+        let mut num_bytes = 0;
+        for stale_since_version in self.reader.stale_versions()? {
+            if stale_since_version > prune_to_version {
+                break;
+            }
+            for index in self.reader.get_stale_nodes_since(stale_since_version)? {
+                if let Some(node) = self.reader.get_node_option(&index.node_key)? {
+                    num_nodes += 1;
+                    num_bytes +=
+                        borsh::object_length(&index.node_key)? + borsh::object_length(&node)?;
+                }
+            }
+        }
+        Ok((num_nodes, num_bytes))
+    }
+
+    /// Returns the total size of the nodes and values of the tree at `version`, or `None` if
+    /// `cancel` was set before it was computed.
+    ///
+    /// This walks the whole tree at `version`, reading every node and the value of every leaf, so
+    /// it takes time linear in the number of keys; `cancel` is checked before each node is read,
+    /// e.g. to abort the walk from another thread. The size of a node is measured as in
+    /// [`prunable_stats`](JellyfishMerkleTree::prunable_stats), and the size of a value is its
+    /// length; neither includes the overhead of the storage. Fails if the root of `version` is
+    /// missing.
+    pub fn storage_size(
+        &self,
+        version: Version,
+        cancel: &AtomicBool,
+    ) -> Result<Option<StorageSize>> {
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = self
+            .get_root_node_option(version)?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+        let mut size = StorageSize::default();
+        let mut pending = vec![(root_node_key, root_node)];
+        while let Some((node_key, node)) = pending.pop() {
+            size.node_bytes += borsh::object_length(&node_key)? + borsh::object_length(&node)?;
+            match node {
+                Node::Internal(internal_node) => {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        if cancel.load(atomic::Ordering::Relaxed) {
+                            return Ok(None);
+                        }
+                        let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                        let child_node = self.reader.get_node(&child_node_key)?;
+                        pending.push((child_node_key, child_node));
+                    }
+                }
+                Node::Leaf(leaf_node) => {
+                    size.value_bytes += self.reader.get_value(version, leaf_node.key_hash())?.len();
+                }
+                Node::Null => {}
+            }
+        }
+        Ok(Some(size))
+    }
+
+    /// Rebuilds the root node of `version` from the nodes one level below it, for stores that lost
+    /// the root node but kept the rest of the tree. Nothing is written if the root node exists.
+    ///
+    /// For each nibble, the child is taken to be the newest node at that nibble created between
+    /// `min_version` and `version`. This is wrong when the subtree at that nibble was deleted in
+    /// the meantime, when it was last updated before `min_version`, or when the root was a single
+    /// leaf, so the rebuilt root is only written to `writer` if it hashes to `expected_root_hash`.
+    /// Otherwise the nibbles for which no node was found are reported in
+    /// [`RootRepair::Unrepairable`].
+    ///
+    /// This looks up each nibble at every version from `version` down to `min_version` until a
+    /// node is found, so it may issue up to `16 * (version - min_version + 1)` reads. A good
+    /// `min_version` is the oldest version still readable after pruning, or the version of the
+    /// previous intact root if the children of the lost root were all updated since.
+    pub fn repair_root<W: TreeWriter>(
+        &self,
+        writer: &W,
+        version: Version,
+        min_version: Version,
+        expected_root_hash: RootHash,
+    ) -> Result<RootRepair> {
+        ensure!(
+            min_version <= version,
+            "Minimum version {} is after version {}.",
+            min_version,
+            version
+        );
+        if self.get_root_node_option(version)?.is_some() {
+            return Ok(RootRepair::Intact);
+        }
+
+        let mut children = Children::new();
+        let mut missing = Vec::new();
+        for nibble in 0..16u8 {
+            let nibble = Nibble::from(nibble);
+            let mut child = None;
+            for child_version in (min_version..=version).rev() {
+                let child_node_key = NodeKey::new_empty_path(child_version)
+                    .gen_child_node_key(child_version, nibble);
+                match self.reader.get_node_option(&child_node_key)? {
+                    Some(Node::Null) | None => {}
+                    Some(node) => {
+                        child = Some(Child::new(
+                            node.hash::<H>(),
+                            child_version,
+                            node.node_type(),
+                        ));
+                        break;
+                    }
+                }
+            }
+            match child {
+                Some(child) => children.insert(nibble, child),
+                None => missing.push(nibble),
+            }
+        }
+
+        // An internal node needs either several children or an internal child, since a single leaf
+        // would have been lifted in its place.
+        if children.num_children() <= 1 && children.values().all(Child::is_leaf) {
+            return Ok(RootRepair::Unrepairable { missing });
+        }
+        let root_node: Node = InternalNode::new(children).into();
+        if root_node.hash::<H>() != expected_root_hash.0 {
+            return Ok(RootRepair::Unrepairable { missing });
+        }
+
+        let mut nodes = BTreeMap::new();
+        nodes.insert(NodeKey::new_empty_path(version), root_node);
+        writer.write_node_batch(&NodeBatch::new(nodes, BTreeMap::new()))?;
+        Ok(RootRepair::Repaired)
+    }
+
+    /// Rebuilds the tree at `version` under the hasher `NewH`, e.g. to migrate to a new hash
+    /// function, and hands all its nodes and values to `writer` in a single [`NodeBatch`]. Returns
+    /// the root hash of the new tree.
+    ///
+    /// The key hashes are kept as is, so the new tree has the same shape as the current one, but
+    /// every value hash and node hash is recomputed under `NewH` from the values read from the
+    /// tree. All the nodes of the new tree are written at `version`, so it is identical to the
+    /// tree built from scratch under `NewH` from the same key-value pairs in a single version.
+    /// `writer` must therefore back a different store than the one this tree reads from.
+    pub fn rehash<NewH: SimpleHasher, W: TreeWriter>(
+        &self,
+        writer: &W,
+        version: Version,
+    ) -> Result<RootHash> {
+        let mut nodes = BTreeMap::new();
+        let mut values = BTreeMap::new();
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = self.rehash_subtree::<NewH>(
+            &root_node_key,
+            root_node_key.clone(),
+            version,
+            &mut nodes,
+            &mut values,
+        )?;
+        let root_hash = RootHash(root_node.hash::<NewH>());
+        nodes.insert(root_node_key, root_node);
+        writer.write_node_batch(&NodeBatch::new(nodes, values))?;
+        Ok(root_hash)
+    }
+
+    /// Rebuilds under `NewH` the subtree rooted at `node_key` in the tree at `version`, whose
+    /// root is to be stored at `new_node_key`. The nodes below the root and the values are added
+    /// to `nodes` and `values`, and the new root node is returned.
+    fn rehash_subtree<NewH: SimpleHasher>(
+        &self,
+        node_key: &NodeKey,
+        new_node_key: NodeKey,
+        version: Version,
+        nodes: &mut BTreeMap<NodeKey, Node>,
+        values: &mut BTreeMap<(Version, KeyHash), Option<OwnedValue>>,
+    ) -> Result<Node> {
+        match self.reader.get_node(node_key)? {
+            Node::Internal(internal_node) => {
+                let mut children = Children::new();
+                for (nibble, child) in internal_node.children_sorted() {
+                    let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                    let new_child_node_key = new_node_key.gen_child_node_key(version, nibble);
+                    let new_child = self.rehash_subtree::<NewH>(
+                        &child_node_key,
+                        new_child_node_key.clone(),
+                        version,
+                        nodes,
+                        values,
+                    )?;
+                    children.insert(
+                        nibble,
+                        Child::new(new_child.hash::<NewH>(), version, new_child.node_type()),
+                    );
+                    nodes.insert(new_child_node_key, new_child);
+                }
+                Ok(InternalNode::new(children).into())
+            }
+            Node::Leaf(leaf_node) => {
+                let key_hash = leaf_node.key_hash();
+                let value = self.reader.get_value(version, key_hash)?;
+                let value_hash = ValueHash::with::<NewH>(&value);
+                values.insert((version, key_hash), Some(value));
+                Ok(LeafNode::new(key_hash, value_hash).into())
+            }
+            Node::Null => Ok(Node::Null),
+        }
+    }
+
+    /// Merges subtrees built independently under disjoint prefixes into a single tree at
+    /// `version`, and returns its root hash along with the [`TreeUpdateBatch`] holding the new
+    /// nodes.
+    ///
+    /// Each of `trees` is the prefix of a subtree and the hash of its root, which must be stored
+    /// at the [`NodeKey`] made of `version` and the prefix, with the rest of the subtree below it
+    /// as [`put_value_sets`](JellyfishMerkleTree::put_value_sets) would lay it out. Only the
+    /// internal nodes above the subtrees are created, so the root hash is the same as that of the
+    /// tree built from all their leaves at once. Fails if a prefix is a prefix of another one, or
+    /// if a subtree root is missing or does not match its hash.
+    ///
+    /// A subtree made of a single leaf is moved up in place of its parent when it has no sibling,
+    /// and the leaf left at its prefix is no longer referenced by the tree.
+    pub fn merge_disjoint(
+        &self,
+        trees: &[(NibblePath, RootHash)],
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let mut subtrees = Vec::with_capacity(trees.len());
+        for (prefix, root_hash) in trees {
+            let node = self
+                .reader
+                .get_node(&NodeKey::new(version, prefix.clone()))?;
+            ensure!(
+                !matches!(node, Node::Null) && node.hash::<H>() == root_hash.0,
+                "Subtree at {:?} does not match root hash {:?}.",
+                prefix,
+                root_hash
+            );
+            if let Node::Leaf(leaf_node) = &node {
+                ensure!(
+                    NibblePath::new(leaf_node.key_hash().0.to_vec())
+                        .nibbles()
+                        .take(prefix.num_nibbles())
+                        .eq(prefix.nibbles()),
+                    "Leaf {:?} is not under its prefix {:?}.",
+                    leaf_node.key_hash(),
+                    prefix
+                );
+            }
+            subtrees.push((prefix.nibbles().collect::<Vec<Nibble>>(), node));
+        }
+        // Once sorted, a prefix is directly followed by the prefixes it is a prefix of.
+        subtrees.sort_by(|(left, _), (right, _)| left.cmp(right));
+        for pair in subtrees.windows(2) {
+            ensure!(
+                !pair[1].0.starts_with(&pair[0].0),
+                "Prefixes {:?} and {:?} overlap.",
+                pair[0].0,
+                pair[1].0
+            );
+        }
+
+        let mut nodes = BTreeMap::new();
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = if subtrees.is_empty() {
+            Node::new_null()
+        } else {
+            self.merge_subtrees(&root_node_key, &subtrees, &mut nodes)
+        };
+        let root_hash = RootHash(root_node.hash::<H>());
+        if !(subtrees.len() == 1 && subtrees[0].0.is_empty()) {
+            nodes.insert(root_node_key, root_node);
+        }
+
+        let node_stats = NodeStats {
+            new_nodes: nodes.len(),
+            new_leaves: nodes.values().filter(|node| node.is_leaf()).count(),
+            stale_nodes: 0,
+            stale_leaves: 0,
+        };
+        let batch = TreeUpdateBatch {
+            node_batch: NodeBatch::new(nodes, BTreeMap::new()),
+            stale_node_index_batch: StaleNodeIndexBatch::new(),
+            node_stats: vec![node_stats],
+        };
+        Ok((root_hash, batch))
+    }
+
+    /// Returns the node at `node_key` of the tree merging `subtrees`, whose prefixes all start
+    /// with the nibble path of `node_key`, and adds to `nodes` the nodes created below it.
+    fn merge_subtrees(
+        &self,
+        node_key: &NodeKey,
+        subtrees: &[(Vec<Nibble>, Node)],
+        nodes: &mut BTreeMap<NodeKey, Node>,
+    ) -> Node {
+        let depth = node_key.nibble_path().num_nibbles();
+        if let [(prefix, node)] = subtrees {
+            if prefix.len() == depth {
+                return node.clone();
+            }
+        }
+
+        let mut children = Children::new();
+        let mut child_nodes = Vec::new();
+        let mut rest = subtrees;
+        while let Some((prefix, _)) = rest.first() {
+            let nibble = prefix[depth];
+            let num_under_nibble = rest
+                .iter()
+                .take_while(|(prefix, _)| prefix[depth] == nibble)
+                .count();
+            let (under_nibble, after) = rest.split_at(num_under_nibble);
+            rest = after;
+
+            let child_node_key = node_key.gen_child_node_key(node_key.version(), nibble);
+            let child_node = self.merge_subtrees(&child_node_key, under_nibble, nodes);
+            children.insert(
+                nibble,
+                Child::new(
+                    child_node.hash::<H>(),
+                    node_key.version(),
+                    child_node.node_type(),
+                ),
+            );
+            // Subtree roots are already stored at their prefix.
+            let is_subtree_root =
+                matches!(under_nibble, [(prefix, _)] if prefix.len() == depth + 1);
+            child_nodes.push((child_node_key, child_node, is_subtree_root));
+        }
+
+        // A single leaf is lifted in place of its parent.
+        if let [(_, child_node @ Node::Leaf(_), _)] = child_nodes.as_slice() {
+            return child_node.clone();
+        }
+        for (child_node_key, child_node, is_subtree_root) in child_nodes {
+            if !is_subtree_root {
+                nodes.insert(child_node_key, child_node);
+            }
+        }
+        InternalNode::new(children).into()
+    }
+
+    /// Deletes every key in the inclusive range `[left, right]` at `version`, and returns the new
+    /// root hash along with a [`DeleteRangeProof`] of the deletion and the corresponding
+    /// [`TreeUpdateBatch`].
+    ///
+    /// The proof lets a verifier that only trusts the old and new root hashes check that exactly
+    /// the keys in `[left, right]` were removed and that the rest of the tree is untouched, see
+    /// [`DeleteRangeProof::verify`].
+    ///
+    /// A [`SparseMerkleRangeProof`] is not enough here: it only proves the leftmost keys of a
+    /// single tree up to a given key, so it can neither tie the old root to the new one nor show
+    /// that a range in the middle of the key space is empty. The [`DeleteRangeProof`] chains the
+    /// deletions from the old root to the new one, and proves the keys bounding the range in the
+    /// new tree.
+    pub fn delete_range_with_proof(
+        &self,
+        left: KeyHash,
+        right: KeyHash,
+        version: Version,
+    ) -> Result<(RootHash, DeleteRangeProof<H>, TreeUpdateBatch)> {
+        ensure!(
+            left <= right,
+            "Left bound {:?} is greater than right bound {:?}.",
+            left,
+            right
+        );
+
+        let mut tree_cache = self.new_tree_cache(version)?;
+
+        // Collect the keys in range from the latest committed tree. Subtrees whose keys all fall
+        // outside of the range are skipped.
+        let mut deleted_keys = Vec::new();
+        let mut pending = vec![tree_cache.get_root_node_key().clone()];
+        while let Some(node_key) = pending.pop() {
+            match tree_cache.get_node(&node_key)? {
+                Node::Internal(internal_node) => {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                        let (min_key, max_key) = key_hash_bounds(child_node_key.nibble_path());
+                        if max_key >= left && min_key <= right {
+                            pending.push(child_node_key);
+                        }
+                    }
+                }
+                Node::Leaf(leaf_node) => {
+                    if left <= leaf_node.key_hash() && leaf_node.key_hash() <= right {
+                        deleted_keys.push(leaf_node.key_hash());
+                    }
+                }
+                Node::Null => {}
+            }
+        }
+        deleted_keys.sort();
+
+        let mut proofs = Vec::with_capacity(deleted_keys.len());
+        for key in deleted_keys.iter() {
+            tree_cache.put_value(version, *key, None);
+            let merkle_proof = self
+                .put(*key, None, version, &mut tree_cache, true)
+                .with_context(|| format!("failed to delete key {:?} for version {}", key, version))?
+                .unwrap();
+            proofs.push(merkle_proof);
+        }
+        tree_cache.freeze::<H>()?;
+
+        // Prove the closest remaining keys on either side of the range in the new tree.
+        let snapshot = tree_cache.snapshot();
+        let new_tree = JellyfishMerkleTree::<_, H>::new(&snapshot);
+        let (left_neighbor, right_neighbor) = match new_tree.get_root_node(version)? {
+            Node::Null => (None, None),
+            _ => new_tree.get_bounding_path(left, version)?,
+        };
+        let left_neighbor = left_neighbor
+            .map(|key| {
+                new_tree
+                    .get_with_proof(key, version)
+                    .map(|(_, proof)| proof)
+            })
+            .transpose()?;
+        let right_neighbor = right_neighbor
+            .map(|key| {
+                new_tree
+                    .get_with_proof(key, version)
+                    .map(|(_, proof)| proof)
+            })
+            .transpose()?;
+
+        let (root_hashes, update_batch): (Vec<RootHash>, TreeUpdateBatch) = tree_cache.into();
+        let proof = DeleteRangeProof::new(
+            UpdateMerkleProof::new(proofs),
+            left_neighbor,
+            right_neighbor,
+        );
+
+        debug_assert_eq!(update_batch.validate(), Ok(()));
+        Ok((root_hashes[0], proof, update_batch))
+    }
+}
+
+/// Reports a root missing from storage as a [`MissingRootError`], keeping any other error, e.g. a
+/// failure of the storage backend, as is.
+fn missing_root_or(err: anyhow::Error, version: Version) -> anyhow::Error {
+    match err.downcast_ref::<ReadError>() {
+        Some(ReadError::UnexpectedMissing(_)) => anyhow::anyhow!(MissingRootError { version }),
+        _ => err,
+    }
+}
+
+/// Fails if the null node at `node_key` is not the root, which only an empty tree has.
+fn ensure_null_node_is_root(node_key: &NodeKey) -> Result<()> {
+    ensure!(
+        node_key.nibble_path().is_empty(),
+        "Non-root null node exists with node key {:?}",
+        node_key
+    );
+    Ok(())
+}
+
+/// Returns the smallest and the largest key hash starting with `nibble_path`.
+fn key_hash_bounds(nibble_path: &NibblePath) -> (KeyHash, KeyHash) {
+    let mut min_key = [0x00; 32];
+    let mut max_key = [0xff; 32];
+    for (i, nibble) in nibble_path.nibbles().enumerate() {
+        let nibble = u8::from(nibble);
+        if i % 2 == 0 {
+            min_key[i / 2] = nibble << 4;
+            max_key[i / 2] = nibble << 4 | 0x0f;
+        } else {
+            min_key[i / 2] |= nibble;
+            max_key[i / 2] = max_key[i / 2] & 0xf0 | nibble;
+        }
+    }
+    (KeyHash(min_key), KeyHash(max_key))
 }
 
 /// The result of putting a single key-value pair into the tree, or deleting a key.
@@ -1428,6 +3174,65 @@ enum PutResult<T> {
     NotChanged,
 }
 
+/// The outcome of [`JellyfishMerkleTree::repair_root`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RootRepair {
+    /// The root node exists, nothing was written.
+    Intact,
+    /// The root node was rebuilt from its children and written.
+    Repaired,
+    /// The root node could not be rebuilt, and nothing was written. `missing` lists the nibbles
+    /// for which no child node was found at all.
+    Unrepairable { missing: Vec<Nibble> },
+}
+
+/// The outcome of [`JellyfishMerkleTree::try_get_with_proof`].
+pub enum GetProofOutcome<H: SimpleHasher> {
+    /// The key exists, with this value, as proven by `proof`.
+    Found {
+        value: OwnedValue,
+        proof: SparseMerkleProof<H>,
+    },
+    /// The key does not exist, as proven by `proof`.
+    NotFound { proof: SparseMerkleProof<H> },
+    /// The node at `missing`, needed for the proof of the key, is not in storage.
+    Incomplete { missing: NodeKey },
+}
+
+// Deriving Debug fails since H is not Debug though SparseMerkleProof<H> implements it generically.
+impl<H: SimpleHasher> core::fmt::Debug for GetProofOutcome<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            GetProofOutcome::Found { value, proof } => f
+                .debug_struct("Found")
+                .field("value", value)
+                .field("proof", proof)
+                .finish(),
+            GetProofOutcome::NotFound { proof } => {
+                f.debug_struct("NotFound").field("proof", proof).finish()
+            }
+            GetProofOutcome::Incomplete { missing } => f
+                .debug_struct("Incomplete")
+                .field("missing", missing)
+                .finish(),
+        }
+    }
+}
+
+/// The size of the tree at a version, see [`JellyfishMerkleTree::storage_size`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StorageSize {
+    /// The total length of the keys and encodings of the nodes.
+    pub node_bytes: usize,
+    /// The total length of the values.
+    pub value_bytes: usize,
+}
+
+/// The node key and hash of a subtree visited by
+/// [`get_consistency_proof`](JellyfishMerkleTree::get_consistency_proof), `None` if there is no
+/// subtree at that position.
+type ConsistencySubtree = Option<(NodeKey, [u8; 32])>;
+
 /// A proof of non-existence by exclusion between two adjacent neighbors.
 #[derive(Debug)]
 pub enum ExclusionProof<H: SimpleHasher> {
@@ -1443,6 +3248,16 @@ pub enum ExclusionProof<H: SimpleHasher> {
     },
 }
 
+/// The side of a key to look for the closest leaf on, in
+/// [`JellyfishMerkleTree::get_closest_leaf`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Towards smaller keys.
+    Below,
+    /// Towards larger keys.
+    Above,
+}
+
 #[derive(Debug, Clone, Copy)]
 enum Extreme {
     Left,
@@ -1462,7 +3277,7 @@ impl Extreme {
 enum SearchResult {
     FoundLeaf {
         ordering: Ordering,
-        leaf_hash: KeyHash,
+        leaf: LeafNode,
         path_to_leaf: NibblePath,
         parents: Vec<InternalNode>,
     },
@@ -0,0 +1,61 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{KeyHash, Version};
+
+/// The number of filter bits per key.
+const BITS_PER_KEY: usize = 10;
+
+/// The number of bits set for each key, the optimum for [`BITS_PER_KEY`].
+const NUM_PROBES: u64 = 7;
+
+/// A Bloom filter of the keys of a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) at one
+/// version, built by
+/// [`JellyfishMerkleTree::build_membership_filter`](crate::JellyfishMerkleTree::build_membership_filter).
+///
+/// [`might_contain`](MembershipFilter::might_contain) never returns `false` for a key present at
+/// that version, so lookups of keys it rejects can skip descending the tree. It returns `true` for
+/// about 1% of the absent keys. The filter is not updated as the tree changes: it only describes
+/// the version it was built at, and must be rebuilt for every new version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MembershipFilter {
+    version: Version,
+    bits: Vec<u64>,
+}
+
+impl MembershipFilter {
+    /// Builds a filter holding `key_hashes`, the keys of the tree at `version`.
+    pub(crate) fn new(version: Version, key_hashes: &[KeyHash]) -> Self {
+        let num_words = (key_hashes.len() * BITS_PER_KEY).div_ceil(64).max(1);
+        let mut filter = Self {
+            version,
+            bits: vec![0; num_words],
+        };
+        for key_hash in key_hashes {
+            for bit in filter.probes(key_hash) {
+                filter.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    /// Returns the version whose keys this filter holds.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns `false` if `key_hash` is definitely not in the tree at [`version`](Self::version),
+    /// and `true` if it may be.
+    pub fn might_contain(&self, key_hash: KeyHash) -> bool {
+        self.probes(&key_hash)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Returns the bits of `key_hash`. Key hashes are already uniformly distributed, so they are
+    /// derived from the key hash itself by double hashing rather than by hashing it again.
+    fn probes(&self, key_hash: &KeyHash) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 64;
+        let h1 = u64::from_le_bytes(key_hash.0[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(key_hash.0[8..16].try_into().unwrap()) | 1;
+        (0..NUM_PROBES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
@@ -0,0 +1,39 @@
+use alloc::vec::Vec;
+
+use crate::{proof::SparseMerkleProof, OwnedValue, SimpleHasher};
+
+/// A value and its merkle proof, filled by
+/// [`JellyfishMerkleTree::get_with_proof_into`](crate::JellyfishMerkleTree::get_with_proof_into).
+///
+/// The buffer keeps its allocations from one call to the next, so that serving many proofs with
+/// the same buffer does not allocate a list of siblings for each of them.
+pub struct ProofBuffer<H: SimpleHasher> {
+    pub(crate) value: Option<OwnedValue>,
+    pub(crate) proof: SparseMerkleProof<H>,
+}
+
+impl<H: SimpleHasher> ProofBuffer<H> {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        Self {
+            value: None,
+            proof: SparseMerkleProof::from_nodes(None, Vec::new()),
+        }
+    }
+
+    /// Returns the value found by the last successful call, if any.
+    pub fn value(&self) -> Option<&[u8]> {
+        self.value.as_deref()
+    }
+
+    /// Returns the proof built by the last successful call.
+    pub fn proof(&self) -> &SparseMerkleProof<H> {
+        &self.proof
+    }
+}
+
+impl<H: SimpleHasher> Default for ProofBuffer<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{proof::SparseMerkleProof, KeyHash, OwnedValue, SimpleHasher, Version};
+
+type CachedProof<H> = (Option<OwnedValue>, SparseMerkleProof<H>);
+
+/// A bounded memoization of the values and proofs returned by
+/// [`JellyfishMerkleTree::get_with_proof_cached`](crate::JellyfishMerkleTree::get_with_proof_cached),
+/// keyed by version and key.
+///
+/// Only the newest version asked for is cached: asking for a newer version drops every entry, and
+/// asking for an older one bypasses the cache. Since a committed version of the tree never
+/// changes, cached proofs keep verifying against the root hash of their version. The cache must be
+/// cleared with [`ProofCache::clear`] if that version is rolled back, e.g. with
+/// [`JellyfishMerkleTree::truncate_to`](crate::JellyfishMerkleTree::truncate_to).
+pub struct ProofCache<H: SimpleHasher> {
+    capacity: usize,
+    inner: Mutex<ProofCacheInner<H>>,
+}
+
+struct ProofCacheInner<H: SimpleHasher> {
+    version: Option<Version>,
+    entries: HashMap<KeyHash, CachedProof<H>>,
+}
+
+impl<H: SimpleHasher> ProofCache<H> {
+    /// Creates a cache holding the proofs of at most `capacity` keys.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(ProofCacheInner {
+                version: None,
+                entries: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Drops every cached proof.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.version = None;
+        inner.entries.clear();
+    }
+
+    /// Returns the number of cached proofs.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Returns `true` if no proof is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the cached value and proof of `key` at `version`, if any.
+    pub(crate) fn get(&self, version: Version, key: KeyHash) -> Option<CachedProof<H>> {
+        let inner = self.lock();
+        if inner.version != Some(version) {
+            return None;
+        }
+        inner.entries.get(&key).cloned()
+    }
+
+    /// Caches the value and proof of `key` at `version`, unless `version` is older than the
+    /// version being cached.
+    pub(crate) fn insert(&self, version: Version, key: KeyHash, value_and_proof: CachedProof<H>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.lock();
+        match inner.version {
+            Some(cached_version) if cached_version > version => return,
+            Some(cached_version) if cached_version == version => {}
+            _ => {
+                inner.version = Some(version);
+                inner.entries.clear();
+            }
+        }
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            // Make room by evicting an arbitrary entry.
+            if let Some(evicted) = inner.entries.keys().next().copied() {
+                inner.entries.remove(&evicted);
+            }
+        }
+        inner.entries.insert(key, value_and_proof);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ProofCacheInner<H>> {
+        // The cache is left consistent by every operation, so it is still usable after a panic.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
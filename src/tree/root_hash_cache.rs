@@ -0,0 +1,77 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{RootHash, Version};
+
+/// A bounded map from versions to the root hashes of the tree at these versions, read through by
+/// [`JellyfishMerkleTree::get_root_hash_cached`](crate::JellyfishMerkleTree::get_root_hash_cached)
+/// and filled as versions are committed with
+/// [`JellyfishMerkleTree::commit_cached`](crate::JellyfishMerkleTree::commit_cached).
+///
+/// The cache holds the `capacity` most recent versions it was given: when it is full, the oldest
+/// version is evicted first. Since a committed version of the tree never changes, cached root
+/// hashes stay valid until that version is rolled back, which
+/// [`JellyfishMerkleTree::truncate_to_cached`](crate::JellyfishMerkleTree::truncate_to_cached)
+/// reflects in the cache. Rolling back through
+/// [`JellyfishMerkleTree::truncate_to`](crate::JellyfishMerkleTree::truncate_to) instead must be
+/// followed by [`RootHashCache::truncate_to`] with the same version.
+pub struct RootHashCache {
+    capacity: usize,
+    entries: Mutex<BTreeMap<Version, RootHash>>,
+}
+
+impl RootHashCache {
+    /// Creates a cache holding the root hashes of at most `capacity` versions.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the cached root hash of `version`, if any.
+    pub fn cached_root_hash(&self, version: Version) -> Option<RootHash> {
+        self.lock().get(&version).copied()
+    }
+
+    /// Caches `root_hash` as the root hash of `version`, which must have been committed. Does
+    /// nothing if the cache is full of more recent versions.
+    pub fn insert(&self, version: Version, root_hash: RootHash) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.lock();
+        entries.insert(version, root_hash);
+        if entries.len() > self.capacity {
+            entries.pop_first();
+        }
+    }
+
+    /// Drops the root hashes of the versions after `version`, which have been rolled back.
+    pub fn truncate_to(&self, version: Version) {
+        self.lock()
+            .retain(|cached_version, _| *cached_version <= version);
+    }
+
+    /// Drops every cached root hash.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Returns the number of cached root hashes.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns `true` if no root hash is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<Version, RootHash>> {
+        // The cache is left consistent by every operation, so it is still usable after a panic.
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
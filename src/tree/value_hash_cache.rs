@@ -0,0 +1,75 @@
+use alloc::vec::Vec;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::Hasher,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{SimpleHasher, ValueHash};
+
+/// The number of bytes sampled at the start, in the middle and at the end of a value to
+/// fingerprint it.
+const SAMPLE_LEN: usize = 32;
+
+/// A bounded memoization of value hashes, so that the same value written under many keys is only
+/// hashed once.
+///
+/// Entries are keyed by a fingerprint that costs the same for any value size. Since values sharing
+/// a fingerprint are told apart by comparing their bytes, the cache holds a copy of every value it
+/// remembers.
+pub(crate) struct ValueHashCache {
+    capacity: usize,
+    entries: Mutex<HashMap<u64, (Vec<u8>, ValueHash)>>,
+}
+
+impl ValueHashCache {
+    /// Creates a cache remembering the hashes of at most `capacity` values.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the hash of `value`, computing it only if `value` is not in the cache.
+    pub(crate) fn value_hash<H: SimpleHasher>(&self, value: &[u8]) -> ValueHash {
+        let fingerprint = fingerprint(value);
+        if let Some((cached_value, value_hash)) = self.lock().get(&fingerprint) {
+            if cached_value.as_slice() == value {
+                return *value_hash;
+            }
+        }
+
+        let value_hash = ValueHash::with::<H>(value);
+        if self.capacity > 0 {
+            let mut entries = self.lock();
+            if entries.len() >= self.capacity && !entries.contains_key(&fingerprint) {
+                // Make room by evicting an arbitrary entry.
+                if let Some(evicted) = entries.keys().next().copied() {
+                    entries.remove(&evicted);
+                }
+            }
+            entries.insert(fingerprint, (value.to_vec(), value_hash));
+        }
+        value_hash
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (Vec<u8>, ValueHash)>> {
+        // The map is left consistent by every operation, so it is still usable after a panic.
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn fingerprint(value: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_usize(value.len());
+    let middle = value.len() / 2;
+    for start in [
+        0,
+        middle.saturating_sub(SAMPLE_LEN / 2),
+        value.len().saturating_sub(SAMPLE_LEN),
+    ] {
+        hasher.write(&value[start..value.len().min(start + SAMPLE_LEN)]);
+    }
+    hasher.finish()
+}
@@ -66,21 +66,25 @@
 //! Updating node could be operated as deletion of the node followed by insertion of the updated
 //! node.
 
-use alloc::{collections::BTreeSet, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeSet, sync::Arc, vec::Vec};
 #[cfg(not(feature = "std"))]
 use hashbrown::{hash_map::Entry, HashMap, HashSet};
 #[cfg(feature = "std")]
 use std::collections::{hash_map::Entry, HashMap, HashSet};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 
 use crate::{
     node_type::{Node, NodeKey},
     storage::{
         NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch, TreeReader, TreeUpdateBatch,
+        TreeWriter,
     },
-    types::{Version, PRE_GENESIS_VERSION},
-    KeyHash, OwnedValue, RootHash, SimpleHasher,
+    types::{
+        nibble::{nibble_path::NibblePath, Nibble},
+        Version, PRE_GENESIS_VERSION,
+    },
+    Bytes32Ext, KeyHash, NodeAlreadyExists, OwnedValue, RootHash, SimpleHasher,
 };
 
 /// `FrozenTreeCache` is used as a field of `TreeCache` storing all the nodes and values that
@@ -88,8 +92,9 @@ use crate::{
 /// `FrozenTreeCache` is to let `TreeCache` freeze intermediate results from each transaction to
 /// help commit more than one transaction in a row atomically.
 struct FrozenTreeCache {
-    /// Immutable node_cache.
-    node_cache: NodeBatch,
+    /// Immutable node_cache. It is shared with any outstanding [`ReadOnlySnapshot`]s, and only
+    /// copied on freeze if one of them is still alive.
+    node_cache: Arc<NodeBatch>,
 
     /// Immutable stale_node_index_cache.
     stale_node_index_cache: StaleNodeIndexBatch,
@@ -112,6 +117,36 @@ impl FrozenTreeCache {
     }
 }
 
+/// A point in the history of a [`TreeCache`] it can be rolled back to, returned by
+/// [`TreeCache::checkpoint`].
+pub struct TreeCacheCheckpoint {
+    root_node_key: NodeKey,
+    next_version: Version,
+    node_cache: HashMap<NodeKey, Node>,
+    value_cache: HashMap<(Version, KeyHash), Option<OwnedValue>>,
+    num_new_leaves: usize,
+    stale_node_index_cache: HashSet<NodeKey>,
+    num_stale_leaves: usize,
+    num_frozen_versions: usize,
+}
+
+/// The part of a [`TreeCache`] a node is read from, reported to the hook set with
+/// [`TreeCache::set_read_hook`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheLayer {
+    /// The nodes put since the last freeze.
+    NodeCache,
+    /// The nodes of the versions frozen so far.
+    FrozenCache,
+    /// The nodes read ahead of time by [`TreeCache::warm`].
+    WarmCache,
+    /// The underlying storage, whether it has the node or not.
+    Reader,
+}
+
+/// A hook called on every node read from a [`TreeCache`].
+type ReadHook<'a> = Box<dyn Fn(&NodeKey, CacheLayer) + Send + Sync + 'a>;
+
 /// `TreeCache` is a in-memory cache for per-transaction updates of sparse Merkle nodes and values.
 pub struct TreeCache<'a, R> {
     /// `NodeKey` of the current root node in cache.
@@ -123,6 +158,10 @@ pub struct TreeCache<'a, R> {
     /// Intermediate nodes keyed by node hash.
     node_cache: HashMap<NodeKey, Node>,
 
+    /// Nodes read ahead of time from `reader` by [`warm`](TreeCache::warm). They are already
+    /// persisted, so they are never part of the batch built on `freeze`.
+    warm_node_cache: HashMap<NodeKey, Node>,
+
     /// Values keyed by version and keyhash.
     // TODO(@preston-evans98): Convert to a vector once we remove the non-batch APIs.
     // The Hashmap guarantees that if the same (version, key) pair is written several times, only the last
@@ -139,11 +178,18 @@ pub struct TreeCache<'a, R> {
     /// # of leaves in the `stale_node_index_cache`,
     num_stale_leaves: usize,
 
+    /// Whether the nodes of previous versions deleted by `delete_node` are recorded in
+    /// `stale_node_index_cache`, see [`without_stale_tracking`](TreeCache::without_stale_tracking).
+    track_stale_nodes: bool,
+
     /// The immutable part of this cache, which will be committed to the underlying storage.
     frozen_cache: FrozenTreeCache,
 
     /// The underlying persistent storage.
     reader: &'a R,
+
+    /// Called on every node read by `get_node` and `get_node_option`, if set.
+    read_hook: Option<ReadHook<'a>>,
 }
 
 impl<'a, R> TreeCache<'a, R>
@@ -177,6 +223,7 @@ where
         };
         Ok(Self {
             node_cache,
+            warm_node_cache: HashMap::new(),
             stale_node_index_cache: HashSet::new(),
             frozen_cache: FrozenTreeCache::new(),
             root_node_key,
@@ -185,6 +232,8 @@ where
             num_stale_leaves: 0,
             num_new_leaves: 0,
             value_cache: Default::default(),
+            read_hook: None,
+            track_stale_nodes: true,
         })
     }
 
@@ -198,7 +247,7 @@ where
     pub fn new_overwrite(reader: &'a R, current_version: Version) -> Result<Self> {
         let node_cache = HashMap::new();
         let Some((node_key, _)) = reader.get_rightmost_leaf()? else {
-            bail!("creating an overwrite cache for an empty tree is not supported")
+            anyhow::bail!("creating an overwrite cache for an empty tree is not supported")
         };
 
         anyhow::ensure!(
@@ -209,6 +258,7 @@ where
         let root_node_key = NodeKey::new_empty_path(current_version);
         Ok(Self {
             node_cache,
+            warm_node_cache: HashMap::new(),
             stale_node_index_cache: HashSet::new(),
             frozen_cache: FrozenTreeCache::new(),
             root_node_key,
@@ -217,30 +267,114 @@ where
             num_stale_leaves: 0,
             num_new_leaves: 0,
             value_cache: Default::default(),
+            read_hook: None,
+            track_stale_nodes: true,
         })
     }
 
+    /// Stops recording the nodes of previous versions that become stale, for short-lived trees
+    /// that are never pruned: [`delete_node`](TreeCache::delete_node) then only drops nodes, and
+    /// `freeze` produces an empty [`StaleNodeIndexBatch`] and [`NodeStats`] without stale nodes.
+    ///
+    /// The batches built this way cannot be pruned by version, since the stale node index that
+    /// pruning relies on is never written.
+    pub fn without_stale_tracking(mut self) -> Self {
+        self.track_stale_nodes = false;
+        self
+    }
+
     /// Gets a node with given node key. If it doesn't exist in node cache, read from `reader`.
     pub fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
-        Ok(if let Some(node) = self.node_cache.get(node_key) {
-            node.clone()
-        } else if let Some(node) = self.frozen_cache.node_cache.nodes().get(node_key) {
-            node.clone()
-        } else {
-            self.reader.get_node(node_key)?
+        Ok(match self.get_cached_node(node_key) {
+            Some(node) => node,
+            None => self.reader.get_node(node_key)?,
         })
     }
 
     /// Gets a node with the given node key. If it doesn't exist in node cache, read from `reader`
     /// If it doesn't exist anywhere, return `None`.
     pub fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
-        Ok(if let Some(node) = self.node_cache.get(node_key) {
-            Some(node.clone())
+        Ok(match self.get_cached_node(node_key) {
+            Some(node) => Some(node),
+            None => self.reader.get_node_option(node_key)?,
+        })
+    }
+
+    /// Gets a node with the given node key from memory, and reports where it is read from to the
+    /// read hook. `None` means it has to be read from `reader`.
+    fn get_cached_node(&self, node_key: &NodeKey) -> Option<Node> {
+        let (layer, node) = if let Some(node) = self.node_cache.get(node_key) {
+            (CacheLayer::NodeCache, Some(node))
         } else if let Some(node) = self.frozen_cache.node_cache.nodes().get(node_key) {
-            Some(node.clone())
+            (CacheLayer::FrozenCache, Some(node))
+        } else if let Some(node) = self.warm_node_cache.get(node_key) {
+            (CacheLayer::WarmCache, Some(node))
         } else {
-            self.reader.get_node_option(node_key)?
-        })
+            (CacheLayer::Reader, None)
+        };
+        if let Some(read_hook) = &self.read_hook {
+            read_hook(node_key, layer);
+        }
+        node.cloned()
+    }
+
+    /// Sets a hook called on every node read by [`get_node`](TreeCache::get_node) and
+    /// [`get_node_option`](TreeCache::get_node_option), with the key of the node and the
+    /// [`CacheLayer`] it is read from, e.g. to trace the reads of a request. There is none by
+    /// default.
+    pub fn set_read_hook(&mut self, read_hook: impl Fn(&NodeKey, CacheLayer) + Send + Sync + 'a) {
+        self.read_hook = Some(Box::new(read_hook));
+    }
+
+    /// Reads the nodes on the path from the current root down to `prefix` from `reader`, so that
+    /// the upcoming `put`s under `prefix` find them in memory. The path stops early at a leaf or
+    /// at a missing child.
+    ///
+    /// Warmed nodes are only kept for reading: they are not counted as new nodes, and are not
+    /// written again on `freeze`.
+    pub fn warm(&mut self, prefix: &[Nibble]) -> Result<()> {
+        let mut node_key = self.root_node_key.clone();
+        for depth in 0..=prefix.len() {
+            let node = if let Some(node) = self.node_cache.get(&node_key) {
+                node.clone()
+            } else if let Some(node) = self.frozen_cache.node_cache.nodes().get(&node_key) {
+                node.clone()
+            } else if let Some(node) = self.warm_node_cache.get(&node_key) {
+                node.clone()
+            } else {
+                match self.reader.get_node_option(&node_key)? {
+                    Some(node) => {
+                        self.warm_node_cache.insert(node_key.clone(), node.clone());
+                        node
+                    }
+                    None => return Ok(()),
+                }
+            };
+            let (Node::Internal(internal_node), Some(&nibble)) = (&node, prefix.get(depth)) else {
+                return Ok(());
+            };
+            match internal_node.child(nibble) {
+                Some(child) => node_key = node_key.gen_child_node_key(child.version, nibble),
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Captures the frozen part of this cache on top of the underlying storage as a
+    /// [`ReadOnlySnapshot`], which can be read from other threads while this cache keeps building
+    /// the next version. Nodes and values that have not been frozen yet are not visible through the
+    /// snapshot.
+    ///
+    /// The snapshot shares the frozen nodes and values with this cache, so the next
+    /// [`freeze`](TreeCache::freeze) while it is alive copies all of them before adding the new
+    /// version. Keeping a snapshot of every version alive while freezing the next one thus makes
+    /// building `n` versions cost `O(n²)`: drop snapshots as soon as they are no longer read.
+    pub fn snapshot(&self) -> ReadOnlySnapshot<'a, R> {
+        ReadOnlySnapshot {
+            frozen_node_cache: Arc::clone(&self.frozen_cache.node_cache),
+            reader: self.reader,
+        }
     }
 
     /// Gets the current root node key.
@@ -262,11 +396,26 @@ where
                 }
                 o.insert(new_node);
             }
-            Entry::Occupied(o) => bail!("Node with key {:?} already exists in NodeBatch", o.key()),
+            Entry::Occupied(o) => {
+                return Err(anyhow!(NodeAlreadyExists {
+                    node_key: o.key().clone(),
+                }))
+            }
         };
         Ok(())
     }
 
+    /// Same as [`put_node`](TreeCache::put_node), but putting a node equal to the one already in
+    /// `node_cache` at `node_key` succeeds without changing anything, so that updates can be
+    /// replayed safely. Fails with [`NodeAlreadyExists`] only if the nodes differ.
+    pub fn put_node_idempotent(&mut self, node_key: NodeKey, new_node: Node) -> Result<()> {
+        match self.node_cache.get(&node_key) {
+            Some(node) if *node == new_node => Ok(()),
+            Some(_) => Err(anyhow!(NodeAlreadyExists { node_key })),
+            None => self.put_node(node_key, new_node),
+        }
+    }
+
     pub fn put_value(&mut self, version: Version, key_hash: KeyHash, value: Option<OwnedValue>) {
         self.value_cache.insert((version, key_hash), value);
     }
@@ -276,16 +425,84 @@ where
         // If node cache doesn't have this node, it means the node is in the previous version of
         // the tree on the disk.
         if self.node_cache.remove(old_node_key).is_none() {
-            let is_new_entry = self.stale_node_index_cache.insert(old_node_key.clone());
-            assert!(is_new_entry, "Node gets stale twice unexpectedly.");
-            if is_leaf {
-                self.num_stale_leaves += 1;
+            if self.track_stale_nodes {
+                let is_new_entry = self.stale_node_index_cache.insert(old_node_key.clone());
+                assert!(is_new_entry, "Node gets stale twice unexpectedly.");
+                if is_leaf {
+                    self.num_stale_leaves += 1;
+                }
             }
         } else if is_leaf {
             self.num_new_leaves -= 1;
         }
     }
 
+    /// Writes to `writer` the nodes put since the last freeze whose subtree only holds keys
+    /// smaller than `next_key`, along with the values of these keys, and drops them from this
+    /// cache, e.g. to bound the memory used by a huge update applied in key order. The root is
+    /// never flushed, so the next [`freeze`](TreeCache::freeze) still computes the root hash of the
+    /// version, and the [`TreeUpdateBatch`] built from this cache holds the nodes and values that
+    /// were not flushed.
+    ///
+    /// The upcoming `put`s until the next freeze must be for keys greater than or equal to
+    /// `next_key`, which holds when updates are applied in key order. Deleting such a key can
+    /// lift a sibling leaf whose parent is on the path of `next_key`, so these leaves are kept in
+    /// the cache and no flushed node is ever deleted. Flushed internal nodes can still be read
+    /// afterwards, so `writer` must write to the storage this cache reads from. Flushed nodes are
+    /// not restored by [`rollback_to`](TreeCache::rollback_to), and the [`NodeStats`] of the
+    /// version only count the nodes left in the batch.
+    pub fn flush_finalized<W: TreeWriter>(&mut self, writer: &W, next_key: KeyHash) -> Result<()> {
+        let is_finalized = |nibble_path: &NibblePath, node: &Node| {
+            match nibble_path
+                .nibbles()
+                .enumerate()
+                .map(|(i, nibble)| (i, nibble.cmp(&next_key.0.get_nibble(i))))
+                .find(|(_, ordering)| ordering.is_ne())
+            {
+                // A leaf whose parent is on the path of `next_key` may still be lifted.
+                Some((i, core::cmp::Ordering::Less)) => {
+                    !node.is_leaf() || i + 1 < nibble_path.num_nibbles()
+                }
+                _ => false,
+            }
+        };
+        let finalized_node_keys: Vec<NodeKey> = self
+            .node_cache
+            .iter()
+            .filter(|(node_key, node)| is_finalized(node_key.nibble_path(), node))
+            .map(|(node_key, _)| node_key.clone())
+            .collect();
+        let finalized_value_keys: Vec<(Version, KeyHash)> = self
+            .value_cache
+            .keys()
+            .filter(|(_, key_hash)| *key_hash < next_key)
+            .copied()
+            .collect();
+        if finalized_node_keys.is_empty() && finalized_value_keys.is_empty() {
+            return Ok(());
+        }
+
+        let nodes = finalized_node_keys
+            .iter()
+            .map(|node_key| (node_key.clone(), self.node_cache[node_key].clone()))
+            .collect();
+        let values = finalized_value_keys
+            .iter()
+            .map(|value_key| (*value_key, self.value_cache[value_key].clone()))
+            .collect();
+        writer.write_node_batch(&NodeBatch::new(nodes, values))?;
+
+        for node_key in &finalized_node_keys {
+            if let Some(Node::Leaf(_)) = self.node_cache.remove(node_key) {
+                self.num_new_leaves -= 1;
+            }
+        }
+        for value_key in &finalized_value_keys {
+            self.value_cache.remove(value_key);
+        }
+        Ok(())
+    }
+
     /// Freezes all the contents in cache to be immutable and clear `node_cache`.
     pub fn freeze<H: SimpleHasher>(&mut self) -> Result<()> {
         let mut root_node_key = self.get_root_node_key().clone();
@@ -331,8 +548,7 @@ where
             stale_leaves: self.num_stale_leaves,
         };
         self.frozen_cache.node_stats.push(node_stats);
-        self.frozen_cache
-            .node_cache
+        Arc::make_mut(&mut self.frozen_cache.node_cache)
             .extend(self.node_cache.drain(), self.value_cache.drain());
         let stale_since_version = self.next_version;
         self.frozen_cache
@@ -355,6 +571,154 @@ where
 
         Ok(())
     }
+
+    /// Moves the root of this cache to the root of the version frozen last, so that the next
+    /// transaction is applied on top of it.
+    ///
+    /// This makes it possible to commit several transactions in a row with a single `TreeCache`:
+    /// apply the updates of a transaction, [`freeze`](TreeCache::freeze) the cache, `advance` it,
+    /// and repeat with the next transaction. The [`TreeUpdateBatch`] built from the cache then
+    /// covers all of them.
+    pub fn advance(&mut self) -> Result<()> {
+        ensure!(
+            !self.frozen_cache.root_hashes.is_empty(),
+            "Cannot advance a TreeCache that has not been frozen."
+        );
+        ensure!(
+            self.node_cache.is_empty()
+                && self.value_cache.is_empty()
+                && self.stale_node_index_cache.is_empty(),
+            "Cannot advance a TreeCache with updates that have not been frozen."
+        );
+        self.root_node_key = NodeKey::new_empty_path(self.next_version - 1);
+        Ok(())
+    }
+
+    /// Loads `batch`, a previously produced [`NodeBatch`] holding the nodes and values of a single
+    /// version, into the frozen part of this cache as the version `next_version`, along with its
+    /// `stats`. This is the same as applying the updates of that version, freezing the cache and
+    /// [`advance`](TreeCache::advance)-ing it: the loaded nodes are returned by
+    /// [`get_node`](TreeCache::get_node), and the next transaction is applied on top of the loaded
+    /// root.
+    ///
+    /// A `NodeBatch` does not record the nodes made stale by its version, so none are recorded for
+    /// the loaded version either.
+    ///
+    /// Fails if the cache has updates that have not been frozen, if `batch` has nodes or values of
+    /// another version or lacks the root node of `next_version`, or if `stats` does not count the
+    /// nodes and leaves of `batch`.
+    pub fn load_frozen<H: SimpleHasher>(
+        &mut self,
+        batch: NodeBatch,
+        stats: NodeStats,
+    ) -> Result<()> {
+        let version = self.next_version;
+        // A cache created at version 0 over an empty tree starts with a null root placeholder,
+        // which the loaded root replaces.
+        let only_genesis_placeholder = self.node_cache.len() == 1
+            && version == 0
+            && matches!(self.node_cache.get(&self.root_node_key), Some(Node::Null));
+        ensure!(
+            (self.node_cache.is_empty() || only_genesis_placeholder)
+                && self.value_cache.is_empty()
+                && self.stale_node_index_cache.is_empty(),
+            "Cannot load a NodeBatch into a TreeCache with updates that have not been frozen."
+        );
+        ensure!(
+            batch
+                .nodes()
+                .keys()
+                .all(|node_key| node_key.version() == version)
+                && batch
+                    .values()
+                    .keys()
+                    .all(|(value_version, _)| *value_version == version),
+            "NodeBatch has nodes or values of another version than {}.",
+            version
+        );
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_hash = match batch.get_node(&root_node_key) {
+            Some(root_node) => RootHash(root_node.hash::<H>()),
+            None => bail!("NodeBatch lacks the root node of version {}.", version),
+        };
+        let num_leaves = batch.nodes().values().filter(|node| node.is_leaf()).count();
+        ensure!(
+            stats.new_nodes == batch.nodes().len() && stats.new_leaves == num_leaves,
+            "NodeStats count {} nodes and {} leaves, but the NodeBatch has {} nodes and {} leaves.",
+            stats.new_nodes,
+            stats.new_leaves,
+            batch.nodes().len(),
+            num_leaves
+        );
+
+        self.node_cache.clear();
+        self.frozen_cache.root_hashes.push(root_hash);
+        self.frozen_cache.node_stats.push(stats);
+        Arc::make_mut(&mut self.frozen_cache.node_cache).merge(batch);
+        self.root_node_key = root_node_key;
+        self.next_version += 1;
+        Ok(())
+    }
+
+    /// Records the current state of this cache, so that it can be restored with
+    /// [`rollback_to`](TreeCache::rollback_to), e.g. to abort a block executed speculatively.
+    ///
+    /// The updates not frozen yet are copied into the checkpoint, whereas frozen versions are
+    /// only counted.
+    pub fn checkpoint(&self) -> TreeCacheCheckpoint {
+        TreeCacheCheckpoint {
+            root_node_key: self.root_node_key.clone(),
+            next_version: self.next_version,
+            node_cache: self.node_cache.clone(),
+            value_cache: self.value_cache.clone(),
+            num_new_leaves: self.num_new_leaves,
+            stale_node_index_cache: self.stale_node_index_cache.clone(),
+            num_stale_leaves: self.num_stale_leaves,
+            num_frozen_versions: self.frozen_cache.root_hashes.len(),
+        }
+    }
+
+    /// Restores the state of this cache recorded by `checkpoint`: the versions frozen since are
+    /// discarded along with their nodes, values and stale node indices, and the updates not frozen
+    /// at the time of the checkpoint replace the current ones. `next_version` and the root node
+    /// key are restored as well.
+    ///
+    /// Fails if `checkpoint` was not taken from this cache, or was taken from it after a later
+    /// checkpoint has been rolled back to.
+    pub fn rollback_to(&mut self, checkpoint: TreeCacheCheckpoint) -> Result<()> {
+        let num_frozen_versions = self.frozen_cache.root_hashes.len();
+        ensure!(
+            checkpoint.num_frozen_versions <= num_frozen_versions
+                && self.next_version.checked_sub(checkpoint.next_version)
+                    == Some((num_frozen_versions - checkpoint.num_frozen_versions) as Version),
+            "Checkpoint at version {} does not belong to this TreeCache at version {}.",
+            checkpoint.next_version,
+            self.next_version
+        );
+
+        self.frozen_cache
+            .root_hashes
+            .truncate(checkpoint.num_frozen_versions);
+        self.frozen_cache
+            .node_stats
+            .truncate(checkpoint.num_frozen_versions);
+        // Every node and value frozen since the checkpoint was put at its `next_version` or later.
+        if num_frozen_versions > checkpoint.num_frozen_versions {
+            Arc::make_mut(&mut self.frozen_cache.node_cache).remove_since(checkpoint.next_version);
+            self.frozen_cache
+                .stale_node_index_cache
+                .retain(|index| index.stale_since_version < checkpoint.next_version);
+        }
+
+        self.root_node_key = checkpoint.root_node_key;
+        self.next_version = checkpoint.next_version;
+        self.node_cache = checkpoint.node_cache;
+        self.value_cache = checkpoint.value_cache;
+        self.num_new_leaves = checkpoint.num_new_leaves;
+        self.stale_node_index_cache = checkpoint.stale_node_index_cache;
+        self.num_stale_leaves = checkpoint.num_stale_leaves;
+        Ok(())
+    }
 }
 
 impl<'a, R> TreeReader for TreeCache<'a, R>
@@ -384,7 +748,82 @@ where
     }
 
     fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, crate::storage::LeafNode)>> {
-        unimplemented!("get_rightmost_leaf should not be used with a tree cache")
+        let cached_leaves = self
+            .node_cache
+            .iter()
+            .filter_map(|(node_key, node)| match node {
+                Node::Leaf(leaf_node) => Some((node_key.clone(), leaf_node.clone())),
+                _ => None,
+            });
+        Ok(cached_leaves
+            .chain(self.frozen_cache.node_cache.rightmost_leaf())
+            .chain(self.reader.get_rightmost_leaf()?)
+            .max_by_key(|(_, leaf_node)| leaf_node.key_hash()))
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        Ok(self
+            .reader
+            .get_latest_version()?
+            .max(self.frozen_cache.node_cache.latest_version()))
+    }
+}
+
+/// A read-only view of the versions frozen in a [`TreeCache`] at the time
+/// [`TreeCache::snapshot`] was called, layered over the cache's underlying storage.
+///
+/// The snapshot is cheap to create and clone, and is `Send + Sync` as long as the underlying
+/// [`TreeReader`] is `Sync`.
+pub struct ReadOnlySnapshot<'a, R> {
+    frozen_node_cache: Arc<NodeBatch>,
+    reader: &'a R,
+}
+
+impl<'a, R> Clone for ReadOnlySnapshot<'a, R> {
+    fn clone(&self) -> Self {
+        Self {
+            frozen_node_cache: Arc::clone(&self.frozen_node_cache),
+            reader: self.reader,
+        }
+    }
+}
+
+impl<'a, R> TreeReader for ReadOnlySnapshot<'a, R>
+where
+    R: 'a + TreeReader,
+{
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.frozen_node_cache.get_node(node_key) {
+            Some(node) => Ok(Some(node.clone())),
+            None => self.reader.get_node_option(node_key),
+        }
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        match self.frozen_node_cache.get_value(max_version, key_hash) {
+            Some(value) => Ok(value.clone()),
+            None => self.reader.get_value_option(max_version, key_hash),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, crate::storage::LeafNode)>> {
+        Ok(self
+            .frozen_node_cache
+            .rightmost_leaf()
+            .into_iter()
+            .chain(self.reader.get_rightmost_leaf()?)
+            .max_by_key(|(_, leaf_node)| leaf_node.key_hash()))
+    }
+
+    fn get_latest_version(&self) -> Result<Option<Version>> {
+        Ok(self
+            .reader
+            .get_latest_version()?
+            .max(self.frozen_node_cache.latest_version()))
     }
 }
 
@@ -396,7 +835,8 @@ where
         (
             tree_cache.frozen_cache.root_hashes,
             TreeUpdateBatch {
-                node_batch: tree_cache.frozen_cache.node_cache,
+                node_batch: Arc::try_unwrap(tree_cache.frozen_cache.node_cache)
+                    .unwrap_or_else(|node_cache| (*node_cache).clone()),
                 stale_node_index_batch: tree_cache.frozen_cache.stale_node_index_cache,
                 node_stats: tree_cache.frozen_cache.node_stats,
             },
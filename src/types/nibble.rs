@@ -46,6 +46,12 @@ impl Nibble {
     }
 }
 
+/// Returns the length, in nibbles, of the common prefix of `a` and `b`, i.e. the index of the
+/// first nibble at which they diverge. Identical keys share all [`ROOT_NIBBLE_HEIGHT`] nibbles.
+pub fn common_prefix_nibbles(a: &KeyHash, b: &KeyHash) -> usize {
+    a.0.common_prefix_nibbles_len(&b.0)
+}
+
 /// An iterator that iterates the index range (inclusive) of each different nibble at given
 /// `nibble_idx` of all the keys in a sorted key-value pairs.
 pub(crate) struct NibbleRangeIterator<'a> {
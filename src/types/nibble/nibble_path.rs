@@ -120,7 +120,12 @@ impl NibblePath {
     }
 
     /// Adds a nibble to the end of the nibble path.
-    pub(crate) fn push(&mut self, nibble: Nibble) {
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path already holds [`ROOT_NIBBLE_HEIGHT`] (64) nibbles, the length of the
+    /// path to a leaf for a 256-bit key.
+    pub fn push(&mut self, nibble: Nibble) {
         assert!(ROOT_NIBBLE_HEIGHT > self.num_nibbles);
         if self.num_nibbles % 2 == 0 {
             self.bytes.push(u8::from(nibble) << 4);
@@ -131,7 +136,7 @@ impl NibblePath {
     }
 
     /// Pops a nibble from the end of the nibble path.
-    pub(crate) fn pop(&mut self) -> Option<Nibble> {
+    pub fn pop(&mut self) -> Option<Nibble> {
         let poped_nibble = if self.num_nibbles % 2 == 0 {
             self.bytes.last_mut().map(|last_byte| {
                 let nibble = *last_byte & 0x0f;
@@ -147,6 +152,22 @@ impl NibblePath {
         poped_nibble
     }
 
+    /// Shortens the nibble path to its first `num_nibbles` nibbles. Has no effect if the path is
+    /// not longer than that.
+    pub fn truncate(&mut self, num_nibbles: usize) {
+        if num_nibbles >= self.num_nibbles {
+            return;
+        }
+        self.bytes.truncate(num_nibbles.div_ceil(2));
+        if num_nibbles % 2 == 1 {
+            // Keep the second half of the last byte zeroed for an odd number of nibbles.
+            if let Some(last_byte) = self.bytes.last_mut() {
+                *last_byte &= 0xf0;
+            }
+        }
+        self.num_nibbles = num_nibbles;
+    }
+
     /// Returns the last nibble.
     pub fn last(&self) -> Option<Nibble> {
         let last_byte_option = self.bytes.last();
@@ -181,7 +202,8 @@ impl NibblePath {
         }
     }
 
-    /// Get a nibble iterator iterates over the whole nibble path.
+    /// Get a nibble iterator iterates over the whole nibble path, from the root down. A path
+    /// holds at most [`ROOT_NIBBLE_HEIGHT`] (64) nibbles.
     pub fn nibbles(&self) -> NibbleIterator {
         assume!(self.num_nibbles <= ROOT_NIBBLE_HEIGHT); // invariant
         NibbleIterator::new(self, 0, self.num_nibbles)
@@ -15,7 +15,11 @@ use crate::{
 #[cfg(all(test, feature = "std"))]
 use proptest_derive::Arbitrary;
 
-pub use self::definition::{SparseMerkleProof, SparseMerkleRangeProof, UpdateMerkleProof};
+pub use self::definition::{
+    ConsistencyProof, DeleteRangeProof, NamespaceProof, ProofStep, SparseMerkleMultiproof,
+    SparseMerkleProof, SparseMerkleRangeProof, UnchangedProof, UpdateMerkleProof, ValueHashChange,
+    ValueProof,
+};
 use crate::{KeyHash, ValueHash, SPARSE_MERKLE_PLACEHOLDER_HASH};
 use borsh::{BorshDeserialize, BorshSerialize};
 use serde::{Deserialize, Serialize};
@@ -35,6 +39,8 @@ pub const INTERNAL_DOMAIN_SEPARATOR: &[u8] = b"JMT::IntrnalNode";
 /// be able to forge the node/leaf type, as this assertion wouldn't be checked.
 /// Providing a [`SparseMerkleInternalNode`] or a [`SparseMerkleLeafNode`] structure is sufficient to
 /// prove the node type as one would need to reverse the hash function to forge them.
+/// Siblings supplied by their hash only, see [`SparseMerkleProof::new`], are opaque: their type is
+/// not asserted, so a deletion cannot be verified against them.
 pub(crate) enum SparseMerkleNode {
     // The default sparse node
     Null,
@@ -42,6 +48,8 @@ pub(crate) enum SparseMerkleNode {
     Internal(SparseMerkleInternalNode),
     // The leaf sparse merkle tree node
     Leaf(SparseMerkleLeafNode),
+    // A node known only by its hash, whose type cannot be asserted
+    Opaque([u8; 32]),
 }
 
 impl SparseMerkleNode {
@@ -50,6 +58,7 @@ impl SparseMerkleNode {
             SparseMerkleNode::Null => SPARSE_MERKLE_PLACEHOLDER_HASH,
             Internal(node) => node.hash::<H>(),
             Leaf(node) => node.hash::<H>(),
+            SparseMerkleNode::Opaque(hash) => *hash,
         }
     }
 }
@@ -136,17 +145,24 @@ impl PartialEq for SparseMerkleLeafNode {
 }
 
 impl SparseMerkleLeafNode {
-    pub(crate) fn new(key_hash: KeyHash, value_hash: ValueHash) -> Self {
+    /// Creates a leaf node binding `key_hash` to `value_hash`.
+    pub fn new(key_hash: KeyHash, value_hash: ValueHash) -> Self {
         SparseMerkleLeafNode {
             key_hash,
             value_hash,
         }
     }
 
-    pub(crate) fn key_hash(&self) -> KeyHash {
+    /// Gets the key hash.
+    pub fn key_hash(&self) -> KeyHash {
         self.key_hash
     }
 
+    /// Gets the associated value hash.
+    pub fn value_hash(&self) -> ValueHash {
+        self.value_hash
+    }
+
     pub(crate) fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
         let mut hasher = H::new();
         hasher.update(LEAF_DOMAIN_SEPARATOR);
@@ -6,11 +6,16 @@ use core::marker::PhantomData;
 
 use super::{SparseMerkleInternalNode, SparseMerkleLeafNode, SparseMerkleNode};
 use crate::{
+    node_type::InternalNode,
     storage::Node,
-    types::nibble::nibble_path::{skip_common_prefix, NibblePath},
-    Bytes32Ext, KeyHash, RootHash, SimpleHasher, ValueHash, SPARSE_MERKLE_PLACEHOLDER_HASH,
+    types::nibble::{
+        nibble_path::{skip_common_prefix, NibblePath},
+        Nibble, ROOT_NIBBLE_HEIGHT,
+    },
+    Bytes32Ext, KeyHash, OwnedValue, RootHash, SimpleHasher, ValueHash,
+    SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
-use alloc::vec::Vec;
+use alloc::{collections::BTreeMap, vec::Vec};
 use anyhow::{bail, ensure, format_err, Result};
 use serde::{Deserialize, Serialize};
 
@@ -73,9 +78,47 @@ impl<H: SimpleHasher> Clone for SparseMerkleProof<H> {
     }
 }
 
+/// One step of the path authenticated by a [`SparseMerkleProof`], as returned by
+/// [`SparseMerkleProof::steps`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProofStep {
+    /// The nibble of the key containing the bit taken at this step. Since the proof is over the
+    /// binary tree underlying each internal node, four consecutive steps share the same nibble.
+    pub nibble: Nibble,
+    /// The bit of the key taken at this step, `true` meaning the right child.
+    pub bit: bool,
+    /// The hash of the sibling at this step.
+    pub sibling: [u8; 32],
+    /// Whether the sibling is a leaf. This is `false` for empty and internal siblings, and for
+    /// siblings built from their hash only with [`SparseMerkleProof::new`].
+    pub sibling_is_leaf: bool,
+}
+
 impl<H: SimpleHasher> SparseMerkleProof<H> {
+    /// Constructs a `SparseMerkleProof` from its leaf and the hashes of its siblings, ordered from
+    /// the bottom level to the root level, e.g. as received from a remote prover.
+    ///
+    /// Such a proof can be verified like any other, but since the type of its siblings is unknown,
+    /// it cannot be used to verify the deletion of its leaf in an [`UpdateMerkleProof`].
+    pub fn new(leaf: Option<SparseMerkleLeafNode>, siblings: Vec<[u8; 32]>) -> Self {
+        let siblings = siblings
+            .into_iter()
+            .map(|hash| {
+                if hash == SPARSE_MERKLE_PLACEHOLDER_HASH {
+                    SparseMerkleNode::Null
+                } else {
+                    SparseMerkleNode::Opaque(hash)
+                }
+            })
+            .collect();
+        Self::from_nodes(leaf, siblings)
+    }
+
     /// Constructs a new `SparseMerkleProof` using leaf and a list of siblings.
-    pub(crate) fn new(leaf: Option<SparseMerkleLeafNode>, siblings: Vec<SparseMerkleNode>) -> Self {
+    pub(crate) fn from_nodes(
+        leaf: Option<SparseMerkleLeafNode>,
+        siblings: Vec<SparseMerkleNode>,
+    ) -> Self {
         SparseMerkleProof {
             leaf,
             siblings,
@@ -97,6 +140,16 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
         self.siblings
     }
 
+    /// Returns the leaf and the list of siblings of this proof, to build it in place.
+    pub(crate) fn parts_mut(
+        &mut self,
+    ) -> (
+        &mut Option<SparseMerkleLeafNode>,
+        &mut Vec<SparseMerkleNode>,
+    ) {
+        (&mut self.leaf, &mut self.siblings)
+    }
+
     /// Verifies an element whose key is `element_key` and value is
     /// `element_value` exists in the Sparse Merkle Tree using the provided proof.
     pub fn verify_existence<V: AsRef<[u8]>>(
@@ -118,6 +171,59 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
         self.verify(expected_root_hash, element_key, None::<&[u8]>)
     }
 
+    /// Returns the leaf occupying the position of `element_key` in the tree when it holds another
+    /// key, i.e. the leaf a non-inclusion proof of `element_key` shows instead of it. Returns
+    /// `None` for an inclusion proof of `element_key`, and for a non-inclusion proof ending in an
+    /// empty subtree.
+    ///
+    /// [`verify_nonexistence`](SparseMerkleProof::verify_nonexistence) checks that the key of
+    /// this leaf differs from `element_key` while sharing its path in the tree.
+    pub fn conflicting_leaf(&self, element_key: KeyHash) -> Option<SparseMerkleLeafNode> {
+        self.leaf.filter(|leaf| leaf.key_hash != element_key)
+    }
+
+    /// Verifies an element whose key is `element_key` and whose value hashes to `value_hash`
+    /// exists in the Sparse Merkle Tree using the provided proof, without needing the value
+    /// itself.
+    pub fn verify_value_hash(
+        &self,
+        expected_root_hash: RootHash,
+        element_key: KeyHash,
+        value_hash: ValueHash,
+    ) -> Result<()> {
+        ensure!(
+            self.siblings.len() <= 256,
+            "Sparse Merkle Tree proof has more than {} ({}) siblings.",
+            256,
+            self.siblings.len(),
+        );
+        let leaf = self
+            .leaf
+            .ok_or_else(|| format_err!("Expected inclusion proof. Found non-inclusion proof."))?;
+        ensure!(
+            element_key == leaf.key_hash,
+            "Keys do not match. Key in proof: {:?}. Expected key: {:?}.",
+            leaf.key_hash,
+            element_key
+        );
+        ensure!(
+            value_hash == leaf.value_hash,
+            "Value hashes do not match. Value hash in proof: {:?}. Expected value hash: {:?}",
+            leaf.value_hash,
+            value_hash,
+        );
+
+        let actual_root_hash = self.reconstruct_root(element_key, None::<&[u8]>);
+        ensure!(
+            actual_root_hash == expected_root_hash,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            expected_root_hash,
+        );
+
+        Ok(())
+    }
+
     /// If `element_value` is present, verifies an element whose key is `element_key` and value is
     /// `element_value` exists in the Sparse Merkle Tree using the provided proof. Otherwise
     /// verifies the proof is a valid non-inclusion proof that shows this key doesn't exist in the
@@ -179,10 +285,50 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
             }
         }
 
-        let current_hash = self
-            .leaf
-            .clone()
-            .map_or(SPARSE_MERKLE_PLACEHOLDER_HASH, |leaf| leaf.hash::<H>());
+        // The checks above ensure the leaf in the proof is the one implied by `element_value`, so
+        // the reconstructed root only depends on the proof itself.
+        let actual_root_hash = self.reconstruct_root(element_key, None::<&[u8]>);
+
+        ensure!(
+            actual_root_hash == expected_root_hash,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            expected_root_hash,
+        );
+
+        Ok(())
+    }
+
+    /// Same as [`verify`](SparseMerkleProof::verify), but takes the expected root hash as raw
+    /// bytes, e.g. as received over an FFI boundary.
+    pub fn verify_against_bytes<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: &[u8; 32],
+        element_key: KeyHash,
+        element_value: Option<V>,
+    ) -> Result<()> {
+        self.verify(RootHash(*expected_root_hash), element_key, element_value)
+    }
+
+    /// Computes the root hash implied by this proof for `element_key`, without comparing it to
+    /// any expected root.
+    ///
+    /// If `element_value` is present, the root is computed as if the leaf of this proof held
+    /// `element_key` and `element_value`. Otherwise the leaf of this proof is used as is (or the
+    /// placeholder hash if there is none), as in a non-inclusion proof.
+    pub fn reconstruct_root<V: AsRef<[u8]>>(
+        &self,
+        element_key: KeyHash,
+        element_value: Option<V>,
+    ) -> RootHash {
+        let current_hash = match element_value {
+            Some(value) => {
+                SparseMerkleLeafNode::new(element_key, ValueHash::with::<H>(value)).hash::<H>()
+            }
+            None => self
+                .leaf
+                .map_or(SPARSE_MERKLE_PLACEHOLDER_HASH, |leaf| leaf.hash::<H>()),
+        };
         let actual_root_hash = self
             .siblings
             .iter()
@@ -201,14 +347,24 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
                 }
             });
 
-        ensure!(
-            actual_root_hash == expected_root_hash.0,
-            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
-            actual_root_hash,
-            expected_root_hash.0,
-        );
+        RootHash(actual_root_hash)
+    }
 
-        Ok(())
+    /// Returns the steps of the path from the root to the leaf of this proof, taken along
+    /// `element_key`.
+    pub fn steps(&self, element_key: KeyHash) -> Vec<ProofStep> {
+        self.siblings
+            .iter()
+            .rev()
+            .zip(element_key.0.iter_bits())
+            .enumerate()
+            .map(|(depth, (sibling_node, bit))| ProofStep {
+                nibble: element_key.0.get_nibble(depth / 4),
+                bit,
+                sibling: sibling_node.hash::<H>(),
+                sibling_is_leaf: matches!(sibling_node, SparseMerkleNode::Leaf(_)),
+            })
+            .collect()
     }
 
     /// This function computes a new merkle path on split insertion (ie when inserting a new value creates
@@ -291,7 +447,7 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
 
         // Step 2: we compute the new Merkle path (we build a new [`SparseMerkleProof`] object)
         // In this case the siblings are left unchanged, only the leaf value is updated
-        SparseMerkleProof::new(
+        SparseMerkleProof::from_nodes(
             Some(SparseMerkleLeafNode::new(
                 new_element_key,
                 ValueHash::with::<H>(new_element_value),
@@ -320,7 +476,7 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
                     if new_element_key == leaf_node.key_hash {
                         // Step 2: we compute the new Merkle path (we build a new [`SparseMerkleProof`] object)
                         // In this case the siblings are left unchanged, only the leaf value is updated
-                        let new_merkle_path: SparseMerkleProof<H> = SparseMerkleProof::new(
+                        let new_merkle_path: SparseMerkleProof<H> = SparseMerkleProof::from_nodes(
                             Some(SparseMerkleLeafNode::new(
                                 new_element_key,
                                 ValueHash::with::<H>(new_element_value),
@@ -350,7 +506,7 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
 
                     // Step 2: we compute the new Merkle path (we build a new [`SparseMerkleProof`] object)
                     // In that case, the leaf is none so we don't need to change the siblings
-                    let new_merkle_path: SparseMerkleProof<H> = SparseMerkleProof::new(
+                    let new_merkle_path: SparseMerkleProof<H> = SparseMerkleProof::from_nodes(
                         Some(SparseMerkleLeafNode::new(
                             new_element_key,
                             ValueHash::with::<H>(new_element_value),
@@ -470,6 +626,9 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
 
                         // Step 3: we compute the new Merkle root
                     }
+                    SparseMerkleNode::Opaque(_) => bail!(
+                        "Cannot tell whether the sibling of the deleted leaf gets coalesced, as the proof only holds its hash"
+                    ),
                     SparseMerkleNode::Null => RootHash(SPARSE_MERKLE_PLACEHOLDER_HASH),
                 };
 
@@ -570,6 +729,213 @@ impl<H: SimpleHasher> UpdateMerkleProof<H> {
     }
 }
 
+/// A proof that every key in an inclusive range `[left, right]` was deleted from a
+/// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree), as returned by
+/// [`delete_range_with_proof`](crate::JellyfishMerkleTree::delete_range_with_proof).
+///
+/// It is made of two parts:
+/// - an [`UpdateMerkleProof`] deleting the keys that were in the range under the old root, one
+///   after the other, which ends at the new root. This shows that these keys existed and that
+///   nothing else in the tree changed;
+/// - inclusion proofs, under the new root, of the closest remaining keys on either side of the
+///   range. Their paths show that nothing lies between them, so the range is now empty.
+#[derive(Debug, Serialize, Deserialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
+pub struct DeleteRangeProof<H: SimpleHasher> {
+    /// The proofs of the deletion of every key in the range, in increasing key order.
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    update_proof: UpdateMerkleProof<H>,
+    /// The proof of the greatest key smaller than `left` in the new tree, if there is one.
+    #[serde(bound(serialize = "", deserialize = ""))]
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    left_neighbor: Option<SparseMerkleProof<H>>,
+    /// The proof of the smallest key greater than `right` in the new tree, if there is one.
+    #[serde(bound(serialize = "", deserialize = ""))]
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    right_neighbor: Option<SparseMerkleProof<H>>,
+}
+
+impl<H: SimpleHasher> DeleteRangeProof<H> {
+    /// Constructs a new `DeleteRangeProof`.
+    pub(crate) fn new(
+        update_proof: UpdateMerkleProof<H>,
+        left_neighbor: Option<SparseMerkleProof<H>>,
+        right_neighbor: Option<SparseMerkleProof<H>>,
+    ) -> Self {
+        Self {
+            update_proof,
+            left_neighbor,
+            right_neighbor,
+        }
+    }
+
+    /// Returns the keys this proof claims were deleted, in increasing order.
+    pub fn deleted_keys(&self) -> Vec<KeyHash> {
+        self.update_proof
+            .0
+            .iter()
+            .filter_map(|proof| proof.leaf.as_ref().map(|leaf| leaf.key_hash))
+            .collect()
+    }
+
+    /// Verifies that the tree at `new_root_hash` is the tree at `old_root_hash` with all the keys
+    /// in `[left, right]` removed, and nothing else changed. The removed keys are the ones
+    /// returned by [`deleted_keys`](DeleteRangeProof::deleted_keys).
+    pub fn verify(
+        self,
+        old_root_hash: RootHash,
+        new_root_hash: RootHash,
+        left: KeyHash,
+        right: KeyHash,
+    ) -> Result<()> {
+        ensure!(
+            left <= right,
+            "Left bound {:?} is greater than right bound {:?}.",
+            left,
+            right
+        );
+
+        // Every deletion must remove a key that exists, and the keys must all be in range.
+        ensure!(
+            self.update_proof.0.iter().all(|proof| proof.leaf.is_some()),
+            "Deletion proof does not remove an existing key."
+        );
+        let deleted_keys = self.deleted_keys();
+        ensure!(
+            deleted_keys.windows(2).all(|keys| keys[0] < keys[1]),
+            "Deleted keys are not in strictly increasing order."
+        );
+        ensure!(
+            deleted_keys.iter().all(|key| left <= *key && *key <= right),
+            "Deleted key is outside of the range [{:?}, {:?}].",
+            left,
+            right
+        );
+        let updates: Vec<(KeyHash, Option<&[u8]>)> =
+            deleted_keys.into_iter().map(|key| (key, None)).collect();
+        self.update_proof
+            .verify_update(old_root_hash, new_root_hash, updates)?;
+
+        // No key may be left between the two neighbors in the new tree.
+        let left_key = self
+            .left_neighbor
+            .as_ref()
+            .map(|proof| Self::verify_neighbor(proof, new_root_hash))
+            .transpose()?;
+        let right_key = self
+            .right_neighbor
+            .as_ref()
+            .map(|proof| Self::verify_neighbor(proof, new_root_hash))
+            .transpose()?;
+        if let Some(left_key) = left_key {
+            ensure!(left_key < left, "Left neighbor {:?} is in range.", left_key);
+        }
+        if let Some(right_key) = right_key {
+            ensure!(
+                right_key > right,
+                "Right neighbor {:?} is in range.",
+                right_key
+            );
+        }
+
+        match (
+            &self.left_neighbor,
+            left_key,
+            &self.right_neighbor,
+            right_key,
+        ) {
+            (Some(left_proof), Some(left_key), Some(right_proof), Some(right_key)) => {
+                // The paths of both neighbors split at `split_depth`, where the left one goes left
+                // and the right one goes right. Below that, there must be nothing to the right of
+                // the left neighbor, nor to the left of the right neighbor.
+                let split_depth = left_key.0.common_prefix_bits_len(&right_key.0);
+                Self::ensure_empty_siblings(left_proof, left_key, split_depth + 1, false)?;
+                Self::ensure_empty_siblings(right_proof, right_key, split_depth + 1, true)?;
+            }
+            (Some(left_proof), Some(left_key), None, _) => {
+                Self::ensure_empty_siblings(left_proof, left_key, 0, false)?;
+            }
+            (None, _, Some(right_proof), Some(right_key)) => {
+                Self::ensure_empty_siblings(right_proof, right_key, 0, true)?;
+            }
+            _ => {
+                ensure!(
+                    new_root_hash.0 == SPARSE_MERKLE_PLACEHOLDER_HASH,
+                    "Missing neighbor proofs for a non-empty tree."
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the leaf of `proof` exists under `root_hash`, and returns its key.
+    fn verify_neighbor(proof: &SparseMerkleProof<H>, root_hash: RootHash) -> Result<KeyHash> {
+        let leaf = proof
+            .leaf
+            .ok_or_else(|| format_err!("Neighbor proof has no leaf."))?;
+        ensure!(
+            proof.siblings.len() <= 256,
+            "Sparse Merkle Tree proof has more than {} ({}) siblings.",
+            256,
+            proof.siblings.len(),
+        );
+        let actual_root_hash = proof.reconstruct_root(leaf.key_hash, None::<&[u8]>);
+        ensure!(
+            actual_root_hash == root_hash,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            root_hash,
+        );
+        Ok(leaf.key_hash)
+    }
+
+    /// Checks that, from `start_depth` down to the leaf of `proof`, every sibling on the path of
+    /// `key` is empty wherever the path goes in the direction given by `bit`.
+    fn ensure_empty_siblings(
+        proof: &SparseMerkleProof<H>,
+        key: KeyHash,
+        start_depth: usize,
+        bit: bool,
+    ) -> Result<()> {
+        let num_siblings = proof.siblings.len();
+        ensure!(
+            start_depth <= num_siblings,
+            "Neighbor proof for {:?} is too short.",
+            key
+        );
+        for (depth, key_bit) in key
+            .0
+            .iter_bits()
+            .enumerate()
+            .take(num_siblings)
+            .skip(start_depth)
+        {
+            // Siblings are ordered from the bottom level to the root level.
+            let sibling = proof.siblings[num_siblings - 1 - depth];
+            ensure!(
+                key_bit != bit || sibling.hash::<H>() == SPARSE_MERKLE_PLACEHOLDER_HASH,
+                "Found a key between the neighbors of the deleted range."
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Tags identifying the kind of each sibling in the binary encoding of a
+/// [`SparseMerkleRangeProof`].
+const NULL_SIBLING_TAG: u8 = 0x00;
+const INTERNAL_SIBLING_TAG: u8 = 0x01;
+const LEAF_SIBLING_TAG: u8 = 0x02;
+const OPAQUE_SIBLING_TAG: u8 = 0x03;
+
+/// Splits the first `len` bytes off `bytes`.
+fn take_bytes<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    ensure!(bytes.len() >= len, "Unexpected end of range proof.");
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
 /// Note: this is not a range proof in the sense that a range of nodes is verified!
 /// Instead, it verifies the entire left part of the tree up to a known rightmost node.
 /// See the description below.
@@ -645,6 +1011,106 @@ impl<H: SimpleHasher> SparseMerkleRangeProof<H> {
         &self.right_siblings
     }
 
+    /// Encodes this proof in a compact binary format, which can be decoded with
+    /// [`from_bytes`](SparseMerkleRangeProof::from_bytes).
+    ///
+    /// The layout is:
+    ///
+    /// ```text
+    /// proof   := count sibling{count}
+    /// count   := the number of right siblings, at most 256, as an unsigned LEB128 varint
+    /// sibling := 0x00                                    a null node
+    ///          | 0x01 left_child[32] right_child[32]     an internal node
+    ///          | 0x02 key_hash[32] value_hash[32]        a leaf node
+    ///          | 0x03 hash[32]                           a node known only by its hash
+    /// ```
+    ///
+    /// Siblings are in the same order as in the proof, from the bottom level to the root level,
+    /// and all hashes are written as their raw 32 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2 + self.right_siblings.len() * 65);
+        let mut count = self.right_siblings.len();
+        while count >= 0x80 {
+            bytes.push(count as u8 | 0x80);
+            count >>= 7;
+        }
+        bytes.push(count as u8);
+
+        for sibling in &self.right_siblings {
+            match sibling {
+                SparseMerkleNode::Null => bytes.push(NULL_SIBLING_TAG),
+                SparseMerkleNode::Internal(node) => {
+                    bytes.push(INTERNAL_SIBLING_TAG);
+                    bytes.extend_from_slice(&node.left_child);
+                    bytes.extend_from_slice(&node.right_child);
+                }
+                SparseMerkleNode::Leaf(node) => {
+                    bytes.push(LEAF_SIBLING_TAG);
+                    bytes.extend_from_slice(&node.key_hash.0);
+                    bytes.extend_from_slice(&node.value_hash.0);
+                }
+                SparseMerkleNode::Opaque(hash) => {
+                    bytes.push(OPAQUE_SIBLING_TAG);
+                    bytes.extend_from_slice(hash);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Decodes a proof encoded with [`to_bytes`](SparseMerkleRangeProof::to_bytes). The whole of
+    /// `bytes` must be a single, canonically encoded proof.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let mut count = 0usize;
+        for shift in (0..).step_by(7) {
+            let byte = take_bytes(&mut bytes, 1)?[0];
+            ensure!(shift < 14, "Sibling count is too large.");
+            ensure!(
+                shift == 0 || byte != 0,
+                "Sibling count is not minimally encoded."
+            );
+            count |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        ensure!(
+            count <= 256,
+            "Range proof has more than {} ({}) siblings.",
+            256,
+            count
+        );
+
+        let mut right_siblings = Vec::with_capacity(count);
+        for _ in 0..count {
+            let sibling = match take_bytes(&mut bytes, 1)?[0] {
+                NULL_SIBLING_TAG => SparseMerkleNode::Null,
+                INTERNAL_SIBLING_TAG => {
+                    let children = take_bytes(&mut bytes, 64)?;
+                    SparseMerkleNode::Internal(SparseMerkleInternalNode::new(
+                        children[..32].try_into().unwrap(),
+                        children[32..].try_into().unwrap(),
+                    ))
+                }
+                LEAF_SIBLING_TAG => {
+                    let hashes = take_bytes(&mut bytes, 64)?;
+                    SparseMerkleNode::Leaf(SparseMerkleLeafNode::new(
+                        KeyHash(hashes[..32].try_into().unwrap()),
+                        ValueHash(hashes[32..].try_into().unwrap()),
+                    ))
+                }
+                OPAQUE_SIBLING_TAG => {
+                    SparseMerkleNode::Opaque(take_bytes(&mut bytes, 32)?.try_into().unwrap())
+                }
+                tag => bail!("Unknown sibling tag {:#04x}.", tag),
+            };
+            right_siblings.push(sibling);
+        }
+        ensure!(bytes.is_empty(), "Trailing bytes after range proof.");
+
+        Ok(Self::new(right_siblings))
+    }
+
     /// Verifies that the rightmost known leaf exists in the tree and that the resulting
     /// root hash matches the expected root hash.
     pub fn verify(
@@ -695,17 +1161,843 @@ impl<H: SimpleHasher> SparseMerkleRangeProof<H> {
     }
 }
 
+/// A proof of the changes between two versions of a
+/// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree), as returned by
+/// [`get_consistency_proof`](crate::JellyfishMerkleTree::get_consistency_proof).
+///
+/// Both trees are walked in parallel from their roots, and the proof holds the nodes of each tree
+/// at the positions where they differ. Subtrees with the same hash at the same position in both
+/// trees are shared: they are left out, their hash acting as an anchor. This shows that nothing
+/// changed outside of the revealed nodes, and the revealed leaves are exactly the ones of the keys
+/// that changed.
+#[derive(Serialize, Deserialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct ConsistencyProof<H: SimpleHasher> {
+    /// The nodes of the old tree that differ from the new tree, in pre-order, with children in
+    /// nibble order.
+    old_nodes: Vec<Node>,
+    /// The nodes of the new tree that differ from the old tree, in the same order.
+    new_nodes: Vec<Node>,
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    _phantom: PhantomData<H>,
+}
+
+// Manually implement PartialEq to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> PartialEq for ConsistencyProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.old_nodes == other.old_nodes && self.new_nodes == other.new_nodes
+    }
+}
+
+// Manually implement Clone to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> Clone for ConsistencyProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            old_nodes: self.old_nodes.clone(),
+            new_nodes: self.new_nodes.clone(),
+            _phantom: Default::default(),
+        }
+    }
+}
+
+// Manually implement Debug to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> core::fmt::Debug for ConsistencyProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ConsistencyProof")
+            .field("old_nodes", &self.old_nodes)
+            .field("new_nodes", &self.new_nodes)
+            .finish()
+    }
+}
+
+/// The value hashes of a key in the old and the new tree of a [`ConsistencyProof`], `None` meaning
+/// that the key is absent from that tree.
+pub type ValueHashChange = (Option<ValueHash>, Option<ValueHash>);
+
+impl<H: SimpleHasher> ConsistencyProof<H> {
+    /// Constructs a new `ConsistencyProof`.
+    pub(crate) fn new(old_nodes: Vec<Node>, new_nodes: Vec<Node>) -> Self {
+        Self {
+            old_nodes,
+            new_nodes,
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Verifies that this proof links the tree at `old_root_hash` to the tree at
+    /// `new_root_hash`, and returns the keys whose value differs between them, along with their
+    /// value hashes in both trees. Every other key is unchanged.
+    pub fn verify(
+        &self,
+        old_root_hash: RootHash,
+        new_root_hash: RootHash,
+    ) -> Result<BTreeMap<KeyHash, ValueHashChange>> {
+        let mut old = ConsistencySide::<H>::new(&self.old_nodes);
+        let mut new = ConsistencySide::<H>::new(&self.new_nodes);
+        verify_consistent_subtrees(
+            Some(old_root_hash.0),
+            Some(new_root_hash.0),
+            0,
+            &mut old,
+            &mut new,
+        )?;
+        ensure!(
+            old.nodes.next().is_none() && new.nodes.next().is_none(),
+            "Consistency proof has unused nodes."
+        );
+
+        let mut changes = BTreeMap::new();
+        for (key, old_value_hash) in &old.leaves {
+            let new_value_hash = new.leaves.get(key).copied();
+            if new_value_hash != Some(*old_value_hash) {
+                changes.insert(*key, (Some(*old_value_hash), new_value_hash));
+            }
+        }
+        for (key, new_value_hash) in &new.leaves {
+            if !old.leaves.contains_key(key) {
+                changes.insert(*key, (None, Some(*new_value_hash)));
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// The nodes of one of the trees of a [`ConsistencyProof`] left to check, and the leaves checked so
+/// far.
+struct ConsistencySide<'a, H> {
+    nodes: core::slice::Iter<'a, Node>,
+    leaves: BTreeMap<KeyHash, ValueHash>,
+    _phantom: PhantomData<H>,
+}
+
+impl<'a, H: SimpleHasher> ConsistencySide<'a, H> {
+    fn new(nodes: &'a [Node]) -> Self {
+        Self {
+            nodes: nodes.iter(),
+            leaves: BTreeMap::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Takes the next node, which must hash to `hash`, and returns it if it is an internal node.
+    fn reveal(&mut self, hash: Option<[u8; 32]>, depth: usize) -> Result<Option<&'a InternalNode>> {
+        let Some(hash) = hash else {
+            return Ok(None);
+        };
+        let node = self
+            .nodes
+            .next()
+            .ok_or_else(|| format_err!("Consistency proof is missing nodes."))?;
+        ensure!(
+            node.hash::<H>() == hash,
+            "Node in consistency proof does not match its hash."
+        );
+        match node {
+            Node::Internal(internal_node) => Ok(Some(internal_node)),
+            Node::Leaf(leaf_node) => {
+                ensure!(
+                    self.leaves
+                        .insert(leaf_node.key_hash(), leaf_node.value_hash())
+                        .is_none(),
+                    "Consistency proof holds the leaf of {:?} twice.",
+                    leaf_node.key_hash()
+                );
+                Ok(None)
+            }
+            Node::Null => {
+                ensure!(depth == 0, "Non-root null node in consistency proof.");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Checks the subtrees at the same position in both trees, of hashes `old_hash` and `new_hash`
+/// (`None` if there is no subtree), against the nodes of the proof.
+fn verify_consistent_subtrees<H: SimpleHasher>(
+    old_hash: Option<[u8; 32]>,
+    new_hash: Option<[u8; 32]>,
+    depth: usize,
+    old: &mut ConsistencySide<H>,
+    new: &mut ConsistencySide<H>,
+) -> Result<()> {
+    if old_hash == new_hash {
+        return Ok(());
+    }
+    ensure!(
+        depth <= ROOT_NIBBLE_HEIGHT,
+        "Consistency proof is deeper than a key."
+    );
+    let old_internal_node = old.reveal(old_hash, depth)?;
+    let new_internal_node = new.reveal(new_hash, depth)?;
+    if old_internal_node.is_none() && new_internal_node.is_none() {
+        return Ok(());
+    }
+    for nibble in 0..16u8 {
+        let nibble = Nibble::from(nibble);
+        let child_hash = |internal_node: Option<&InternalNode>| {
+            internal_node
+                .and_then(|internal_node| internal_node.child(nibble))
+                .map(|child| child.hash)
+        };
+        verify_consistent_subtrees(
+            child_hash(old_internal_node),
+            child_hash(new_internal_node),
+            depth + 1,
+            old,
+            new,
+        )?;
+    }
+    Ok(())
+}
+
+/// A proof that authenticates several keys at once in a Sparse Merkle Tree given a trusted root
+/// hash, returned by
+/// [`JellyfishMerkleTree::get_multiproof`](crate::JellyfishMerkleTree::get_multiproof).
+///
+/// It holds the same information as one [`SparseMerkleProof`] per key, but a sibling is stored
+/// once even if it is on the path of several keys, and not at all if it is itself on the path of
+/// another key, since it is then recomputed from that path. This makes it much smaller than
+/// independent proofs for keys that are close in the tree.
+#[derive(Serialize, Deserialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
+pub struct SparseMerkleMultiproof<H: SimpleHasher> {
+    /// For each proven key, in increasing order, the depth in bits of the subtree the key falls
+    /// in, and the only leaf of this subtree, or `None` if it is empty. As in a
+    /// [`SparseMerkleProof`], the leaf holds the key for an inclusion proof, and another key for
+    /// a non-inclusion proof.
+    // Prevent serde from adding a spurious Serialize/Deserialize bound on H
+    #[serde(bound(serialize = "", deserialize = ""))]
+    leaves: Vec<(u16, Option<SparseMerkleLeafNode>)>,
+
+    /// The hashes of the siblings that are not on the path of any proven key, in the order a
+    /// depth-first traversal of the paths from the root, left before right, meets them.
+    siblings: Vec<[u8; 32]>,
+
+    /// A marker type showing which hash function is used in this proof.
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    phantom_hasher: PhantomData<H>,
+}
+
+// Manually implement PartialEq to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> PartialEq for SparseMerkleMultiproof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaves == other.leaves && self.siblings == other.siblings
+    }
+}
+
+// Manually implement Clone to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> Clone for SparseMerkleMultiproof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            leaves: self.leaves.clone(),
+            siblings: self.siblings.clone(),
+            phantom_hasher: Default::default(),
+        }
+    }
+}
+
+// Manually implement Debug to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleMultiproof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleMultiproof")
+            .field("leaves", &self.leaves)
+            .field("siblings", &self.siblings)
+            .field("phantom_hasher", &self.phantom_hasher)
+            .finish()
+    }
+}
+
+/// Returns the `depth`-th bit of `key`, from the most significant one.
+fn key_bit(key: &KeyHash, depth: usize) -> bool {
+    key.0[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+impl<H: SimpleHasher> SparseMerkleMultiproof<H> {
+    /// Combines the proofs of `keys`, which must be sorted and distinct, `proofs[i]` being the
+    /// proof of `keys[i]`.
+    pub(crate) fn from_proofs(keys: &[KeyHash], proofs: &[SparseMerkleProof<H>]) -> Self {
+        Self::from_proofs_at(0, keys, proofs)
+    }
+
+    /// Same as [`from_proofs`](SparseMerkleMultiproof::from_proofs), but only stores the siblings
+    /// below `depth`, for keys falling in the same subtree at `depth`.
+    fn from_proofs_at(depth: usize, keys: &[KeyHash], proofs: &[SparseMerkleProof<H>]) -> Self {
+        let mut siblings = Vec::new();
+        if !keys.is_empty() {
+            Self::collect_siblings(depth, keys, proofs, &mut siblings);
+        }
+        Self {
+            leaves: proofs
+                .iter()
+                .map(|proof| (proof.siblings.len() as u16, proof.leaf))
+                .collect(),
+            siblings,
+            phantom_hasher: Default::default(),
+        }
+    }
+
+    /// Pushes to `siblings` the siblings of the subtree at `depth` holding `keys`, in the order
+    /// [`verify_multiproof`](SparseMerkleMultiproof::verify_multiproof) consumes them.
+    fn collect_siblings(
+        depth: usize,
+        keys: &[KeyHash],
+        proofs: &[SparseMerkleProof<H>],
+        siblings: &mut Vec<[u8; 32]>,
+    ) {
+        let proof_depth = proofs[0].siblings.len();
+        if proof_depth == depth {
+            return;
+        }
+        let num_left = keys.partition_point(|key| !key_bit(key, depth));
+        if num_left == 0 || num_left == keys.len() {
+            // Siblings are ordered from the bottom level to the root level.
+            siblings.push(proofs[0].siblings[proof_depth - 1 - depth].hash::<H>());
+        }
+        if num_left > 0 {
+            Self::collect_siblings(depth + 1, &keys[..num_left], &proofs[..num_left], siblings);
+        }
+        if num_left < keys.len() {
+            Self::collect_siblings(depth + 1, &keys[num_left..], &proofs[num_left..], siblings);
+        }
+    }
+
+    /// Returns the number of sibling hashes stored in this proof.
+    pub fn num_siblings(&self) -> usize {
+        self.siblings.len()
+    }
+
+    /// Verifies that each of `items` is in the Sparse Merkle Tree whose root hash is
+    /// `expected_root_hash` with the given value if it is `Some`, or is absent from it if it is
+    /// `None`. The keys of `items` must be the keys the proof was built for, in any order.
+    pub fn verify_multiproof<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: RootHash,
+        items: &[(KeyHash, Option<V>)],
+    ) -> Result<()> {
+        let actual_root_hash = match self.verified_subtree_hash(0, items)? {
+            Some(hash) => RootHash(hash),
+            None => return Ok(()),
+        };
+        ensure!(
+            actual_root_hash == expected_root_hash,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            expected_root_hash,
+        );
+        Ok(())
+    }
+
+    /// Checks the leaves of this proof against `items`, and computes the hash of the subtree at
+    /// `depth` they lead to, or `None` if there are no items.
+    fn verified_subtree_hash<V: AsRef<[u8]>>(
+        &self,
+        depth: usize,
+        items: &[(KeyHash, Option<V>)],
+    ) -> Result<Option<[u8; 32]>> {
+        let mut items: Vec<&(KeyHash, Option<V>)> = items.iter().collect();
+        items.sort_by_key(|(key, _)| *key);
+        ensure!(
+            items.windows(2).all(|pair| pair[0].0 != pair[1].0),
+            "Multiproof items have duplicated keys."
+        );
+        ensure!(
+            items.len() == self.leaves.len(),
+            "Multiproof covers {} keys, but {} items were given.",
+            self.leaves.len(),
+            items.len()
+        );
+
+        let mut leaves = Vec::with_capacity(items.len());
+        for ((key, value), (depth, leaf)) in items.iter().zip(self.leaves.iter()) {
+            let depth = usize::from(*depth);
+            ensure!(
+                depth <= 256,
+                "Sparse Merkle Tree proof has more than {} ({}) siblings.",
+                256,
+                depth,
+            );
+            match (value, leaf) {
+                (Some(value), Some(leaf)) => {
+                    ensure!(
+                        *key == leaf.key_hash,
+                        "Keys do not match. Key in proof: {:?}. Expected key: {:?}.",
+                        leaf.key_hash,
+                        key
+                    );
+                    let hash: ValueHash = ValueHash::with::<H>(value);
+                    ensure!(
+                        hash == leaf.value_hash,
+                        "Value hashes of {:?} do not match. Value hash in proof: {:?}. \
+                         Expected value hash: {:?}",
+                        key,
+                        leaf.value_hash,
+                        hash,
+                    );
+                }
+                (Some(_), None) => bail!(
+                    "Expected inclusion proof of {:?}. Found non-inclusion proof.",
+                    key
+                ),
+                (None, Some(leaf)) => {
+                    ensure!(
+                        *key != leaf.key_hash,
+                        "Expected non-inclusion proof of {:?}, but key exists in proof.",
+                        key
+                    );
+                    ensure!(
+                        key.0.common_prefix_bits_len(&leaf.key_hash.0) >= depth,
+                        "Key {:?} would not have ended up in the subtree where the provided key \
+                         in proof is the only existing key, if it existed.",
+                        key
+                    );
+                }
+                (None, None) => {}
+            }
+            leaves.push((*key, depth, *leaf));
+        }
+        if leaves.is_empty() {
+            return Ok(None);
+        }
+
+        let mut siblings = self.siblings.iter();
+        let subtree_hash = Self::subtree_hash(depth, &leaves, &mut siblings)?;
+        ensure!(
+            siblings.next().is_none(),
+            "Multiproof has more siblings than needed."
+        );
+        Ok(Some(subtree_hash))
+    }
+
+    /// Computes the root hash this proof of `keys`, which must be sorted and distinct, leads to,
+    /// without checking the leaves against any value.
+    pub(crate) fn root_hash(&self, keys: &[KeyHash]) -> Result<RootHash> {
+        ensure!(
+            !keys.is_empty() && keys.len() == self.leaves.len(),
+            "Multiproof covers {} keys, but {} keys were given.",
+            self.leaves.len(),
+            keys.len()
+        );
+        let leaves: Vec<_> = keys
+            .iter()
+            .zip(self.leaves.iter())
+            .map(|(key, (depth, leaf))| (*key, usize::from(*depth), *leaf))
+            .collect();
+        let mut siblings = self.siblings.iter();
+        Ok(RootHash(Self::subtree_hash(0, &leaves, &mut siblings)?))
+    }
+
+    /// Computes the hash of the subtree at `depth` holding the keys of `leaves`, consuming the
+    /// siblings it needs from `siblings`.
+    fn subtree_hash<'a>(
+        depth: usize,
+        leaves: &[(KeyHash, usize, Option<SparseMerkleLeafNode>)],
+        siblings: &mut impl Iterator<Item = &'a [u8; 32]>,
+    ) -> Result<[u8; 32]> {
+        let (_, leaf_depth, leaf) = leaves[0];
+        if leaves
+            .iter()
+            .any(|(_, other_depth, _)| *other_depth == depth)
+        {
+            // All the keys fall in the same subtree, which then has a single leaf.
+            ensure!(
+                leaves.iter().all(
+                    |(_, other_depth, other_leaf)| *other_depth == depth && *other_leaf == leaf
+                ),
+                "Multiproof has inconsistent leaves at depth {}.",
+                depth
+            );
+            return Ok(leaf.map_or(SPARSE_MERKLE_PLACEHOLDER_HASH, |leaf| leaf.hash::<H>()));
+        }
+        ensure!(
+            depth < 256 && depth < leaf_depth,
+            "Multiproof has inconsistent leaves at depth {}.",
+            depth
+        );
+
+        let num_left = leaves.partition_point(|(key, _, _)| !key_bit(key, depth));
+        let (left_hash, right_hash) = if num_left == 0 || num_left == leaves.len() {
+            let sibling = siblings
+                .next()
+                .copied()
+                .ok_or_else(|| format_err!("Multiproof has fewer siblings than needed."))?;
+            let subtree_hash = Self::subtree_hash(depth + 1, leaves, siblings)?;
+            if num_left == 0 {
+                (sibling, subtree_hash)
+            } else {
+                (subtree_hash, sibling)
+            }
+        } else {
+            (
+                Self::subtree_hash(depth + 1, &leaves[..num_left], siblings)?,
+                Self::subtree_hash(depth + 1, &leaves[num_left..], siblings)?,
+            )
+        };
+        Ok(SparseMerkleInternalNode::new(left_hash, right_hash).hash::<H>())
+    }
+}
+
+/// A proof that a key has the same value in the trees at two versions, returned by
+/// [`JellyfishMerkleTree::get_unchanged_proof`](crate::JellyfishMerkleTree::get_unchanged_proof).
+///
+/// It holds the proof of the key in each tree. It shows that the key had the same value at both
+/// versions, but not that the versions in between left it untouched: a value changed and then
+/// restored verifies as well.
+#[derive(Serialize, Deserialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct UnchangedProof<H: SimpleHasher> {
+    /// The proof of the key in the older tree.
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    from_proof: SparseMerkleProof<H>,
+    /// The proof of the key in the newer tree.
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    to_proof: SparseMerkleProof<H>,
+}
+
+// Manually implement PartialEq to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> PartialEq for UnchangedProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.from_proof == other.from_proof && self.to_proof == other.to_proof
+    }
+}
+
+// Manually implement Clone to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> Clone for UnchangedProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            from_proof: self.from_proof.clone(),
+            to_proof: self.to_proof.clone(),
+        }
+    }
+}
+
+// Manually implement Debug to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> core::fmt::Debug for UnchangedProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("UnchangedProof")
+            .field("from_proof", &self.from_proof)
+            .field("to_proof", &self.to_proof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> UnchangedProof<H> {
+    /// Constructs a new `UnchangedProof` from the proofs of the key in the older and the newer
+    /// tree.
+    pub(crate) fn new(from_proof: SparseMerkleProof<H>, to_proof: SparseMerkleProof<H>) -> Self {
+        Self {
+            from_proof,
+            to_proof,
+        }
+    }
+
+    /// Returns the proof of the key in the older tree.
+    pub fn from_proof(&self) -> &SparseMerkleProof<H> {
+        &self.from_proof
+    }
+
+    /// Returns the proof of the key in the newer tree.
+    pub fn to_proof(&self) -> &SparseMerkleProof<H> {
+        &self.to_proof
+    }
+
+    /// Verifies that `element_key` exists with the same value in the tree whose root hash is
+    /// `from_root_hash` and in the one whose root hash is `to_root_hash`, and returns the hash of
+    /// that value.
+    pub fn verify(
+        &self,
+        from_root_hash: RootHash,
+        to_root_hash: RootHash,
+        element_key: KeyHash,
+    ) -> Result<ValueHash> {
+        let value_hash = self
+            .from_proof
+            .leaf
+            .map(|leaf| leaf.value_hash)
+            .ok_or_else(|| format_err!("Expected inclusion proof. Found non-inclusion proof."))?;
+        self.from_proof
+            .verify_value_hash(from_root_hash, element_key, value_hash)?;
+        self.to_proof
+            .verify_value_hash(to_root_hash, element_key, value_hash)?;
+        Ok(value_hash)
+    }
+}
+
+/// A [`SparseMerkleProof`] carrying the value it proves, returned by
+/// [`JellyfishMerkleTree::get_value_proof`](crate::JellyfishMerkleTree::get_value_proof), so that
+/// the value can be checked and extracted in one step with [`ValueProof::verify_and_get`].
+#[derive(Serialize, Deserialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct ValueProof<H: SimpleHasher> {
+    /// The value of the key, or `None` if the proof is a non-inclusion proof.
+    value: Option<OwnedValue>,
+    /// The proof of the key.
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    proof: SparseMerkleProof<H>,
+}
+
+// Manually implement PartialEq to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> PartialEq for ValueProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value && self.proof == other.proof
+    }
+}
+
+// Manually implement Clone to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> Clone for ValueProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            proof: self.proof.clone(),
+        }
+    }
+}
+
+// Manually implement Debug to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> core::fmt::Debug for ValueProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ValueProof")
+            .field("value", &self.value)
+            .field("proof", &self.proof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> ValueProof<H> {
+    /// Constructs a new `ValueProof` from a value, or `None` for an absent key, and the proof of
+    /// the key, e.g. as returned by
+    /// [`JellyfishMerkleTree::get_with_proof`](crate::JellyfishMerkleTree::get_with_proof).
+    pub fn new(value: Option<OwnedValue>, proof: SparseMerkleProof<H>) -> Self {
+        Self { value, proof }
+    }
+
+    /// Returns the value carried by this proof, which is not verified.
+    pub fn value(&self) -> Option<&OwnedValue> {
+        self.value.as_ref()
+    }
+
+    /// Returns the proof of the key.
+    pub fn proof(&self) -> &SparseMerkleProof<H> {
+        &self.proof
+    }
+
+    /// Verifies that the carried value is the value of `element_key` in the Sparse Merkle Tree
+    /// whose root hash is `expected_root_hash`, or that `element_key` is absent from it if there
+    /// is no value, as [`SparseMerkleProof::verify`] does. Returns the verified value.
+    pub fn verify_and_get(
+        &self,
+        expected_root_hash: RootHash,
+        element_key: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.proof
+            .verify(expected_root_hash, element_key, self.value.as_ref())?;
+        Ok(self.value.clone())
+    }
+}
+
+/// A proof of several keys of a namespace, i.e. the keys starting with a common nibble prefix,
+/// returned by
+/// [`JellyfishMerkleTree::get_namespace_proof`](crate::JellyfishMerkleTree::get_namespace_proof).
+///
+/// It is split at the root of the subtree holding the namespace: the siblings of the path from the
+/// tree root to the subtree root, which all the keys share, and a [`SparseMerkleMultiproof`] of
+/// the keys within the subtree. The subtree root can thus be checked against the tree root once,
+/// with [`verify_subtree_root`](NamespaceProof::verify_subtree_root), and the keys verified
+/// within the subtree with [`verify_in_subtree`](NamespaceProof::verify_in_subtree).
+#[derive(Serialize, Deserialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct NamespaceProof<H: SimpleHasher> {
+    /// The common prefix of the keys of the namespace.
+    prefix: NibblePath,
+    /// The hash of the subtree holding the namespace.
+    subtree_root_hash: [u8; 32],
+    /// The siblings of the path from the tree root to the subtree root, ordered from the root
+    /// level down. There are 4 per nibble of `prefix`.
+    prefix_siblings: Vec<[u8; 32]>,
+    /// The proof of the keys within the subtree.
+    #[borsh(bound(serialize = "", deserialize = ""))]
+    subtree_proof: SparseMerkleMultiproof<H>,
+}
+
+// Manually implement PartialEq to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> PartialEq for NamespaceProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.prefix == other.prefix
+            && self.subtree_root_hash == other.subtree_root_hash
+            && self.prefix_siblings == other.prefix_siblings
+            && self.subtree_proof == other.subtree_proof
+    }
+}
+
+// Manually implement Clone to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> Clone for NamespaceProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            prefix: self.prefix.clone(),
+            subtree_root_hash: self.subtree_root_hash,
+            prefix_siblings: self.prefix_siblings.clone(),
+            subtree_proof: self.subtree_proof.clone(),
+        }
+    }
+}
+
+// Manually implement Debug to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> core::fmt::Debug for NamespaceProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NamespaceProof")
+            .field("prefix", &self.prefix)
+            .field("subtree_root_hash", &self.subtree_root_hash)
+            .field("prefix_siblings", &self.prefix_siblings)
+            .field("subtree_proof", &self.subtree_proof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> NamespaceProof<H> {
+    /// Splits the proofs of `keys`, which must be sorted, distinct and fall below `prefix` deeper
+    /// than the subtree root, `proofs[i]` being the proof of `keys[i]`.
+    pub(crate) fn from_proofs(
+        prefix: NibblePath,
+        subtree_root_hash: [u8; 32],
+        keys: &[KeyHash],
+        proofs: &[SparseMerkleProof<H>],
+    ) -> Self {
+        let depth = prefix.num_nibbles() * 4;
+        // Siblings are ordered from the bottom level to the root level.
+        let prefix_siblings = proofs[0]
+            .siblings
+            .iter()
+            .rev()
+            .take(depth)
+            .map(|sibling| sibling.hash::<H>())
+            .collect();
+        Self {
+            prefix,
+            subtree_root_hash,
+            prefix_siblings,
+            subtree_proof: SparseMerkleMultiproof::from_proofs_at(depth, keys, proofs),
+        }
+    }
+
+    /// Returns the common prefix of the keys of the namespace.
+    pub fn prefix(&self) -> &NibblePath {
+        &self.prefix
+    }
+
+    /// Returns the hash of the subtree holding the namespace, which is not verified.
+    pub fn subtree_root_hash(&self) -> [u8; 32] {
+        self.subtree_root_hash
+    }
+
+    /// Returns the proof of the keys within the subtree.
+    pub fn subtree_proof(&self) -> &SparseMerkleMultiproof<H> {
+        &self.subtree_proof
+    }
+
+    /// Verifies that the subtree root is at [`prefix`](NamespaceProof::prefix) in the Sparse
+    /// Merkle Tree whose root hash is `expected_root_hash`.
+    pub fn verify_subtree_root(&self, expected_root_hash: RootHash) -> Result<()> {
+        ensure!(
+            self.prefix_siblings.len() == self.prefix.num_nibbles() * 4,
+            "Namespace proof has {} siblings for a prefix of {} nibbles.",
+            self.prefix_siblings.len(),
+            self.prefix.num_nibbles()
+        );
+        let actual_root_hash = RootHash(
+            self.prefix
+                .bits()
+                .rev()
+                .zip(self.prefix_siblings.iter().rev())
+                .fold(self.subtree_root_hash, |hash, (bit, sibling)| {
+                    if bit {
+                        SparseMerkleInternalNode::new(*sibling, hash).hash::<H>()
+                    } else {
+                        SparseMerkleInternalNode::new(hash, *sibling).hash::<H>()
+                    }
+                }),
+        );
+        ensure!(
+            actual_root_hash == expected_root_hash,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            expected_root_hash,
+        );
+        Ok(())
+    }
+
+    /// Verifies that each of `items`, whose keys must start with
+    /// [`prefix`](NamespaceProof::prefix), is in the subtree with the given value if it is `Some`,
+    /// or is absent from it if it is `None`, as
+    /// [`SparseMerkleMultiproof::verify_multiproof`] does for a whole tree. The subtree root is
+    /// not checked against the tree root.
+    pub fn verify_in_subtree<V: AsRef<[u8]>>(&self, items: &[(KeyHash, Option<V>)]) -> Result<()> {
+        let depth = self.prefix.num_nibbles() * 4;
+        for (key, _) in items {
+            ensure!(
+                self.prefix
+                    .bits()
+                    .enumerate()
+                    .all(|(i, bit)| key_bit(key, i) == bit),
+                "Key {:?} is not in namespace {:?}.",
+                key,
+                self.prefix
+            );
+        }
+        let actual_hash = match self.subtree_proof.verified_subtree_hash(depth, items)? {
+            Some(hash) => hash,
+            None => return Ok(()),
+        };
+        ensure!(
+            actual_hash == self.subtree_root_hash,
+            "Subtree root hashes do not match. Actual hash: {:?}. Expected hash: {:?}.",
+            actual_hash,
+            self.subtree_root_hash,
+        );
+        Ok(())
+    }
+
+    /// Verifies both the subtree root, with
+    /// [`verify_subtree_root`](NamespaceProof::verify_subtree_root), and `items` within the
+    /// subtree, with [`verify_in_subtree`](NamespaceProof::verify_in_subtree).
+    pub fn verify<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: RootHash,
+        items: &[(KeyHash, Option<V>)],
+    ) -> Result<()> {
+        self.verify_subtree_root(expected_root_hash)?;
+        self.verify_in_subtree(items)
+    }
+}
+
 #[cfg(test)]
 mod serialization_tests {
     //! These tests ensure that the various proofs supported by the JMT can actually be serialized and deserialized
     //! when instantiated with a specific hasher. This is done as a sanity check to ensure the trait bounds inferred by Rustc
     //! are not too restrictive.
 
+    use alloc::vec::Vec;
+
+    use proptest::{collection::btree_map, prelude::*, sample::Index};
     use sha2::Sha256;
 
     use crate::{
+        mock::MockTreeStore,
         proof::{SparseMerkleInternalNode, SparseMerkleLeafNode, SparseMerkleNode},
-        KeyHash, ValueHash,
+        Bytes32Ext, JellyfishMerkleTree, KeyHash, OwnedValue, ValueHash,
     };
 
     use super::{SparseMerkleProof, SparseMerkleRangeProof};
@@ -775,4 +2067,92 @@ mod serialization_tests {
 
         assert_eq!(proof, deserialized);
     }
+
+    #[test]
+    fn test_sparse_merkle_range_proof_roundtrip_bytes() {
+        let proof = SparseMerkleRangeProof::<Sha256>::new(alloc::vec![
+            SparseMerkleNode::Null,
+            SparseMerkleNode::Leaf(SparseMerkleLeafNode::new(
+                KeyHash([1u8; 32]),
+                ValueHash([2u8; 32]),
+            )),
+            SparseMerkleNode::Internal(SparseMerkleInternalNode::new([3u8; 32], [4u8; 32])),
+        ]);
+        let bytes = proof.to_bytes();
+
+        let mut expected = alloc::vec![3u8, 0x00, 0x02];
+        expected.extend_from_slice(&[1u8; 32]);
+        expected.extend_from_slice(&[2u8; 32]);
+        expected.push(0x01);
+        expected.extend_from_slice(&[3u8; 32]);
+        expected.extend_from_slice(&[4u8; 32]);
+        assert_eq!(bytes, expected);
+        assert_eq!(
+            SparseMerkleRangeProof::<Sha256>::from_bytes(&bytes).unwrap(),
+            proof
+        );
+
+        let long_proof =
+            SparseMerkleRangeProof::<Sha256>::new(alloc::vec![SparseMerkleNode::Null; 256]);
+        let bytes = long_proof.to_bytes();
+        assert_eq!(bytes[..2], [0x80, 0x02]);
+        assert_eq!(
+            SparseMerkleRangeProof::<Sha256>::from_bytes(&bytes).unwrap(),
+            long_proof
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_get_range_proof_roundtrip_bytes(
+            btree in btree_map(any::<KeyHash>(), any::<OwnedValue>(), 1..100),
+            index in any::<Index>(),
+        ) {
+            let db = MockTreeStore::default();
+            let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+            let (root_hash, batch) = tree
+                .put_value_set(btree.iter().map(|(key, value)| (*key, Some(value.clone()))), 0)
+                .unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+
+            let key = *index.get(&btree.keys().collect::<Vec<_>>());
+            let proof = tree.get_range_proof(*key, 0).unwrap();
+            let decoded = SparseMerkleRangeProof::<Sha256>::from_bytes(&proof.to_bytes()).unwrap();
+            prop_assert_eq!(&decoded, &proof);
+
+            // The range proof only holds the right siblings of the path to `key`, the left ones
+            // are taken from its existence proof.
+            let (_, existence_proof) = tree.get_with_proof(*key, 0).unwrap();
+            let left_siblings = key
+                .0
+                .iter_bits()
+                .rev()
+                .skip(256 - existence_proof.siblings().len())
+                .zip(existence_proof.siblings())
+                .filter(|(bit, _)| *bit)
+                .map(|(_, sibling)| sibling.hash::<Sha256>())
+                .collect();
+            decoded
+                .verify(root_hash, existence_proof.leaf().unwrap(), left_siblings)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sparse_merkle_range_proof_from_invalid_bytes() {
+        let bytes = get_test_range_proof().to_bytes();
+        let from_bytes = SparseMerkleRangeProof::<Sha256>::from_bytes;
+
+        assert!(from_bytes(&[]).is_err());
+        assert!(from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert_eq!(
+            from_bytes(&[1, 4]).unwrap_err().to_string(),
+            "Unknown sibling tag 0x04."
+        );
+        // Non-minimal count.
+        assert!(from_bytes(&[0x80, 0x00]).is_err());
+        // More than 256 siblings.
+        assert!(from_bytes(&[&[0x81u8, 0x02][..], &[0; 257]].concat()).is_err());
+    }
 }
@@ -50,7 +50,7 @@ impl<H: SimpleHasher + 'static> Arbitrary for SparseMerkleProof<H> {
                 }
             }),
         )
-            .prop_map(|(leaf, siblings)| SparseMerkleProof::new(leaf, siblings))
+            .prop_map(|(leaf, siblings)| SparseMerkleProof::from_nodes(leaf, siblings))
             .boxed()
     }
 }
@@ -1,23 +1,118 @@
 use alloc::collections::{BTreeMap, BTreeSet};
 
 use alloc::vec::Vec;
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use borsh::{BorshDeserialize, BorshSerialize};
 #[cfg(any(test))]
 use proptest_derive::Arbitrary;
+#[cfg(feature = "std")]
+use thiserror::Error;
 
 use crate::{
-    node_type::{Node, NodeKey},
-    types::Version,
-    KeyHash, OwnedValue,
+    node_type::{LeafNode, Node, NodeKey},
+    types::{Version, PRE_GENESIS_VERSION},
+    KeyHash, OwnedValue, RootHash,
 };
 
 /// Defines the interface used to write a batch of updates from a
 /// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
 /// to the underlying storage holding nodes.
+///
+/// Node keys are never reused for different nodes, so writing the same batch more than once, e.g.
+/// when replaying a write-ahead log with at-least-once delivery, leaves the storage unchanged.
+/// Implementations should treat such repeated writes, and repeated [`StaleNodeIndex`]es, as
+/// no-ops rather than errors.
 pub trait TreeWriter {
     /// Writes a node batch into storage.
+    ///
+    /// Storage keeping values apart from nodes, e.g. in another column family, can route
+    /// [`NodeBatch::nodes`] and [`NodeBatch::values`] independently, as long as both are written
+    /// atomically.
     fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()>;
+
+    /// Writes all the versions covered by `batch` into storage, atomically: once this returns, all
+    /// of its nodes, values and [`StaleNodeIndex`]es are durable, and if it fails, or the process
+    /// crashes while it runs, none of them must be visible. Otherwise a reader could observe a
+    /// root whose nodes are partly missing, or prune nodes that are still reachable.
+    ///
+    /// This is the method to commit the batch built from a [`TreeCache`](crate::storage::TreeCache)
+    /// or returned by [`JellyfishMerkleTree::put_value_sets`](crate::JellyfishMerkleTree::put_value_sets),
+    /// which may cover several versions.
+    ///
+    /// There is no default implementation: storage must persist the node batch, as
+    /// [`write_node_batch`](TreeWriter::write_node_batch) does, along with the stale node indices
+    /// of the batch, in the same atomic write.
+    ///
+    /// # Migration
+    ///
+    /// This method used to have a default implementation, which failed whenever the batch held
+    /// stale node indices, that is on almost every update after the first version. Making it
+    /// required is a breaking change: implementations of `TreeWriter` written against earlier
+    /// releases no longer compile until they implement it. Storage that does not prune stale
+    /// nodes can implement it by writing [`TreeUpdateBatch::node_batch`] as
+    /// [`write_node_batch`](TreeWriter::write_node_batch) does and ignoring
+    /// [`TreeUpdateBatch::stale_node_index_batch`]; storage that prunes them must record them in
+    /// the same atomic write, e.g. in the same database transaction.
+    fn write_update_batch_atomic(&self, batch: TreeUpdateBatch) -> Result<()>;
+
+    /// Commits `batch` with [`write_update_batch_atomic`](TreeWriter::write_update_batch_atomic),
+    /// and returns the `root_hashes` of the versions it covers, as returned along with it by
+    /// [`JellyfishMerkleTree::put_value_sets`](crate::JellyfishMerkleTree::put_value_sets), once
+    /// they are persisted.
+    ///
+    /// Fails without writing anything if `batch` does not cover as many versions as there are
+    /// `root_hashes`.
+    fn commit(
+        &self,
+        root_hashes: Vec<RootHash>,
+        batch: TreeUpdateBatch,
+    ) -> Result<Vec<CommittedRoot>> {
+        ensure!(
+            root_hashes.len() == batch.node_stats.len(),
+            "Got {} root hashes for a batch of {} versions.",
+            root_hashes.len(),
+            batch.node_stats.len()
+        );
+        self.write_update_batch_atomic(batch)?;
+        Ok(root_hashes.into_iter().map(CommittedRoot).collect())
+    }
+}
+
+/// A [`RootHash`] whose tree has been persisted, as returned by [`TreeWriter::commit`].
+///
+/// Unlike the root hashes returned by
+/// [`JellyfishMerkleTree::put_value_sets`](crate::JellyfishMerkleTree::put_value_sets) or built by
+/// a [`TreeCache`](crate::storage::TreeCache), which may only exist in memory, a
+/// `CommittedRoot` cannot be constructed outside of this crate, so code that requires one can
+/// only be handed a root that was written to storage.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct CommittedRoot(RootHash);
+
+impl CommittedRoot {
+    /// Returns the persisted root hash.
+    pub fn root_hash(&self) -> RootHash {
+        self.0
+    }
+}
+
+/// Defines the interface used to roll the underlying storage back to an earlier version of a
+/// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree), see
+/// [`JellyfishMerkleTree::truncate_to`](crate::JellyfishMerkleTree::truncate_to).
+pub trait TreeTruncator {
+    /// Removes every node listed in `truncation_batch`, along with every value and every
+    /// [`StaleNodeIndex`] recorded after `truncation_batch.version`.
+    fn truncate(&self, truncation_batch: &TruncationBatch) -> Result<()>;
+}
+
+/// The set of changes needed to bring storage back to the state it was in right after
+/// `version` was committed.
+#[derive(Clone, Debug, Default, Eq, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct TruncationBatch {
+    /// The version the tree is rolled back to. Values and stale node indices recorded after this
+    /// version must be dropped.
+    pub version: Version,
+    /// The keys of all nodes created after `version`.
+    pub node_keys: BTreeSet<NodeKey>,
 }
 
 /// Node batch that will be written into db atomically with other batches.
@@ -47,6 +142,32 @@ impl NodeBatch {
         self.nodes.get(node_key)
     }
 
+    /// Gets the newest value of `key_hash` at or before `max_version`, `Some(None)` meaning that
+    /// it was deleted, or `None` if the batch has no value of `key_hash` up to `max_version`.
+    ///
+    /// Values being ordered by version first, this looks `key_hash` up once per version of the
+    /// batch rather than scanning all the values.
+    pub fn get_value(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Option<&Option<OwnedValue>> {
+        let mut upper = (max_version, key_hash);
+        loop {
+            let ((version, key), value) = self.values.range(..=upper).next_back()?;
+            if *key == key_hash {
+                return Some(value);
+            }
+            if *version < upper.0 {
+                // `upper` is past the last version holding values: look `key_hash` up there.
+                upper = (*version, key_hash);
+            } else {
+                // `key_hash` has no value at `version`: look at the previous version.
+                upper = (version.checked_sub(1)?, key_hash);
+            }
+        }
+    }
+
     /// Returns a reference to the current set of nodes.
     pub fn nodes(&self) -> &BTreeMap<NodeKey, Node> {
         &self.nodes
@@ -67,6 +188,17 @@ impl NodeBatch {
         &self.values
     }
 
+    /// Splits the batch into its nodes and its values, e.g. to write them to different places.
+    #[allow(clippy::type_complexity)]
+    pub fn into_parts(
+        self,
+    ) -> (
+        BTreeMap<NodeKey, Node>,
+        BTreeMap<(Version, KeyHash), Option<OwnedValue>>,
+    ) {
+        (self.nodes, self.values)
+    }
+
     /// Extend a node batch.
     pub fn extend(
         &mut self,
@@ -86,9 +218,38 @@ impl NodeBatch {
     pub fn is_empty(&self) -> bool {
         self.nodes.is_empty() && self.values.is_empty()
     }
+
+    /// Returns the latest version with nodes in this batch, ignoring the pre-genesis version. Since
+    /// every version puts its root, this is the latest version the batch holds the root of.
+    pub(crate) fn latest_version(&self) -> Option<Version> {
+        self.nodes
+            .range(..NodeKey::new_empty_path(PRE_GENESIS_VERSION))
+            .next_back()
+            .map(|(node_key, _)| node_key.version())
+    }
+
+    /// Returns the leaf with the greatest key hash among the nodes of this batch, if any.
+    pub(crate) fn rightmost_leaf(&self) -> Option<(NodeKey, LeafNode)> {
+        self.nodes
+            .iter()
+            .filter_map(|(node_key, node)| match node {
+                Node::Leaf(leaf_node) => Some((node_key.clone(), leaf_node.clone())),
+                _ => None,
+            })
+            .max_by_key(|(_, leaf_node)| leaf_node.key_hash())
+    }
+
+    /// Removes the nodes and values of `version` and every later version.
+    pub(crate) fn remove_since(&mut self, version: Version) {
+        self.nodes.split_off(&NodeKey::new_empty_path(version));
+        self.values.split_off(&(version, KeyHash([0; 32])));
+    }
 }
 /// [`StaleNodeIndex`](struct.StaleNodeIndex.html) batch that will be written into db atomically
 /// with other batches.
+///
+/// Being a set, a batch never holds the same node marked stale at the same version twice, and
+/// merging batches (e.g. with [`BTreeSet::extend`]) drops such duplicates.
 pub type StaleNodeIndexBatch = BTreeSet<StaleNodeIndex>;
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, borsh::BorshSerialize, borsh::BorshDeserialize)]
@@ -120,3 +281,161 @@ pub struct TreeUpdateBatch {
     pub stale_node_index_batch: StaleNodeIndexBatch,
     pub node_stats: Vec<NodeStats>,
 }
+
+impl TreeUpdateBatch {
+    /// Returns the keys of the nodes created by this batch, in key order, e.g. to maintain an
+    /// external index of nodes without cloning the batch.
+    pub fn created_node_keys(&self) -> impl Iterator<Item = &NodeKey> + '_ {
+        self.node_batch.nodes().keys()
+    }
+
+    /// Checks the internal consistency of this batch, so that bugs in whatever produced it are
+    /// caught before it reaches storage:
+    /// - no node is marked stale at or before the version that created it, the pre-genesis version
+    ///   coming before version 0;
+    /// - no node created by the batch is marked stale, unless a later version of the batch, whose
+    ///   root is then in the batch too, replaces it;
+    /// - the number of new nodes, new leaves and stale nodes in
+    ///   [`node_stats`](TreeUpdateBatch::node_stats) add up to the contents of the batch.
+    ///
+    /// This only looks at the batch itself and is cheap enough to run on every write in debug
+    /// builds.
+    pub fn validate(&self) -> core::result::Result<(), BatchError> {
+        for index in self.stale_node_index_batch.iter() {
+            // The pre-genesis version comes before version 0.
+            let created_version = index.node_key.version();
+            if created_version != PRE_GENESIS_VERSION
+                && index.stale_since_version <= created_version
+            {
+                return Err(BatchError::StaleBeforeCreation {
+                    node_key: index.node_key.clone(),
+                    stale_since_version: index.stale_since_version,
+                });
+            }
+            let nodes = self.node_batch.nodes();
+            if nodes.contains_key(&index.node_key)
+                && !nodes.contains_key(&NodeKey::new_empty_path(index.stale_since_version))
+            {
+                return Err(BatchError::StaleNewNode {
+                    node_key: index.node_key.clone(),
+                    stale_since_version: index.stale_since_version,
+                });
+            }
+        }
+
+        let expected_nodes = self.node_stats.iter().map(|s| s.new_nodes).sum();
+        let actual_nodes = self.node_batch.nodes().len();
+        if expected_nodes != actual_nodes {
+            return Err(BatchError::NodeCountMismatch {
+                expected: expected_nodes,
+                actual: actual_nodes,
+            });
+        }
+
+        let expected_leaves = self.node_stats.iter().map(|s| s.new_leaves).sum();
+        let actual_leaves = self
+            .node_batch
+            .nodes()
+            .values()
+            .filter(|node| node.is_leaf())
+            .count();
+        if expected_leaves != actual_leaves {
+            return Err(BatchError::LeafCountMismatch {
+                expected: expected_leaves,
+                actual: actual_leaves,
+            });
+        }
+
+        let expected_stale_nodes = self.node_stats.iter().map(|s| s.stale_nodes).sum();
+        let actual_stale_nodes = self.stale_node_index_batch.len();
+        if expected_stale_nodes != actual_stale_nodes {
+            return Err(BatchError::StaleNodeCountMismatch {
+                expected: expected_stale_nodes,
+                actual: actual_stale_nodes,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// An inconsistency found by [`TreeUpdateBatch::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Error))]
+pub enum BatchError {
+    /// A node is marked stale at or before the version that created it.
+    #[cfg_attr(
+        feature = "std",
+        error("Node {node_key:?} is marked stale since version {stale_since_version}, which does not come after its creation.")
+    )]
+    StaleBeforeCreation {
+        node_key: NodeKey,
+        stale_since_version: Version,
+    },
+    /// A node created by the batch is marked stale at a version the batch does not hold.
+    #[cfg_attr(
+        feature = "std",
+        error("Node {node_key:?} is created by the batch, but marked stale since version {stale_since_version}, which the batch does not hold.")
+    )]
+    StaleNewNode {
+        node_key: NodeKey,
+        stale_since_version: Version,
+    },
+    /// The node stats do not account for the number of nodes in the batch.
+    #[cfg_attr(
+        feature = "std",
+        error("Node stats count {expected} new nodes, but the batch contains {actual}.")
+    )]
+    NodeCountMismatch { expected: usize, actual: usize },
+    /// The node stats do not account for the number of leaves in the batch.
+    #[cfg_attr(
+        feature = "std",
+        error("Node stats count {expected} new leaves, but the batch contains {actual}.")
+    )]
+    LeafCountMismatch { expected: usize, actual: usize },
+    /// The node stats do not account for the number of stale node indices in the batch.
+    #[cfg_attr(
+        feature = "std",
+        error("Node stats count {expected} stale nodes, but the batch contains {actual}.")
+    )]
+    StaleNodeCountMismatch { expected: usize, actual: usize },
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            BatchError::StaleBeforeCreation {
+                node_key,
+                stale_since_version,
+            } => write!(
+                f,
+                "Node {:?} is marked stale since version {}, which does not come after its creation.",
+                node_key, stale_since_version
+            ),
+            BatchError::StaleNewNode {
+                node_key,
+                stale_since_version,
+            } => write!(
+                f,
+                "Node {:?} is created by the batch, but marked stale since version {}, which the batch does not hold.",
+                node_key, stale_since_version
+            ),
+            BatchError::NodeCountMismatch { expected, actual } => write!(
+                f,
+                "Node stats count {} new nodes, but the batch contains {}.",
+                expected, actual
+            ),
+            BatchError::LeafCountMismatch { expected, actual } => write!(
+                f,
+                "Node stats count {} new leaves, but the batch contains {}.",
+                expected, actual
+            ),
+            BatchError::StaleNodeCountMismatch { expected, actual } => write!(
+                f,
+                "Node stats count {} stale nodes, but the batch contains {}.",
+                expected, actual
+            ),
+        }
+    }
+}